@@ -0,0 +1,738 @@
+use crate::error::{Error, Result};
+use crate::events::{
+    decode_log, describe_morpho_event, describe_vault_event, get_logs_in_ranges,
+    vault_event_fields, vault_event_name, watch_morpho_events, watch_vault_events, DecodedLog,
+    FieldValue, MorphoLog, VaultLog,
+};
+use crate::market::{
+    fetch_market_info, morpho_deployment_block,
+    IMorpho::{self, IMorphoEvents},
+    MarketInfo, MORPHO_ADDRESS,
+};
+use crate::output::checksummed_option;
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+// Rules and sinks, read from a TOML file (see data/alerts/rules.toml)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertConfig {
+    // Seconds between two snapshots of the markets watched by the utilization, price move and
+    // large borrow rules, in the live mode
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval: u64,
+    pub rules: Vec<RuleConfig>,
+    // Alerts are printed on stdout when no sink is set
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+}
+
+fn default_snapshot_interval() -> u64 {
+    60
+}
+
+// A webhook that does not answer in time fails the delivery, instead of holding the next alerts
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl AlertConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config: AlertConfig = toml::from_str(&fs::read_to_string(path)?).map_err(|error| {
+            Error::Config(format!("invalid rules in {}: {}", path.display(), error))
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The snapshot rules only see the markets they list, snapshotting every market is not supported
+    fn validate(&self) -> Result<()> {
+        for rule in &self.rules {
+            match &rule.kind {
+                RuleKind::LargeBorrow { markets, .. }
+                | RuleKind::Utilization { markets, .. }
+                | RuleKind::PriceMove { markets, .. }
+                    if markets.is_empty() =>
+                {
+                    return Err(Error::Config(format!(
+                        "rule {} needs a list of markets",
+                        rule.name
+                    )))
+                }
+                // Vault events are only subscribed to for the listed vaults
+                RuleKind::VaultEvent { vaults, .. } if vaults.is_empty() => {
+                    return Err(Error::Config(format!(
+                        "rule {} needs a list of vaults",
+                        rule.name
+                    )))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+}
+
+// An empty list of markets matches every market, the rules reading snapshots (largeBorrow, utilization
+// and priceMove) need their markets listed and vaultEvent rules need their vaults listed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum RuleKind {
+    // Liquidate events on the markets, and on the markets `supplier` supplied to
+    Liquidation {
+        #[serde(default)]
+        markets: Vec<B256>,
+        supplier: Option<Address>,
+    },
+    // Borrow events larger than a share of the market liquidity (supply minus borrow) at the last snapshot
+    LargeBorrow {
+        #[serde(default)]
+        markets: Vec<B256>,
+        max_liquidity_share: f64,
+    },
+    // Raised when the utilization crosses `max`, and again only after it went back below
+    Utilization {
+        #[serde(default)]
+        markets: Vec<B256>,
+        max: f64,
+    },
+    // Vault events by name, e.g. SubmitCap, SubmitTimelock or SubmitGuardian
+    VaultEvent {
+        #[serde(default)]
+        vaults: Vec<Address>,
+        #[serde(default = "default_vault_events")]
+        events: Vec<String>,
+    },
+    // Oracle price changes larger than `max_change` (0.05 is 5%) within `window_seconds`
+    PriceMove {
+        #[serde(default)]
+        markets: Vec<B256>,
+        max_change: f64,
+        window_seconds: u64,
+    },
+}
+
+fn default_vault_events() -> Vec<String> {
+    vec!["SubmitCap".to_string()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SinkConfig {
+    Stdout,
+    // Alerts are posted as JSON
+    Webhook { url: String },
+    // Alerts are appended as JSON lines
+    File { path: PathBuf },
+}
+
+// Market state read between events, by the live mode or from a fixture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateSnapshot {
+    pub market_id: B256,
+    pub timestamp: u64,
+    pub total_supply_assets: U256,
+    pub total_borrow_assets: U256,
+    pub price: U256,
+}
+
+impl StateSnapshot {
+    pub fn new(info: &MarketInfo, timestamp: u64) -> Self {
+        StateSnapshot {
+            market_id: info.id,
+            timestamp,
+            total_supply_assets: U256::from(info.market.totalSupplyAssets),
+            total_borrow_assets: U256::from(info.market.totalBorrowAssets),
+            price: info.price,
+        }
+    }
+
+    fn utilization(&self) -> f64 {
+        if self.total_supply_assets.is_zero() {
+            return 0.0;
+        }
+        f64::from(self.total_borrow_assets) / f64::from(self.total_supply_assets)
+    }
+
+    fn liquidity(&self) -> f64 {
+        f64::from(
+            self.total_supply_assets
+                .saturating_sub(self.total_borrow_assets),
+        )
+    }
+}
+
+pub enum AlertInput {
+    Morpho(MorphoLog),
+    Vault(VaultLog),
+    Snapshot(StateSnapshot),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub rule: String,
+    pub market_id: Option<B256>,
+    #[serde(serialize_with = "checksummed_option")]
+    pub vault: Option<Address>,
+    pub block_number: Option<u64>,
+    pub transaction_hash: Option<B256>,
+    pub timestamp: Option<u64>,
+    pub message: String,
+}
+
+struct Rule {
+    name: String,
+    kind: RuleKind,
+    // Markets supplied to by the supplier of a liquidation rule
+    supplied: BTreeSet<B256>,
+    // Markets above the max of a utilization rule
+    above: BTreeSet<B256>,
+    // (timestamp, price) within the window of a price move rule
+    prices: HashMap<B256, VecDeque<(u64, f64)>>,
+}
+
+fn watches<T: PartialEq>(list: &[T], item: &T) -> bool {
+    list.is_empty() || list.contains(item)
+}
+
+// Evaluates the rules on events and snapshots, in the order they happened
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    // Last snapshot of each market
+    snapshots: HashMap<B256, StateSnapshot>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<RuleConfig>) -> Self {
+        AlertEngine {
+            rules: rules
+                .into_iter()
+                .map(|rule| Rule {
+                    name: rule.name,
+                    kind: rule.kind,
+                    supplied: BTreeSet::new(),
+                    above: BTreeSet::new(),
+                    prices: HashMap::new(),
+                })
+                .collect(),
+            snapshots: HashMap::new(),
+        }
+    }
+
+    pub fn suppliers(&self) -> BTreeSet<Address> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match &rule.kind {
+                RuleKind::Liquidation { supplier, .. } => *supplier,
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn add_supplied_market(&mut self, supplier: Address, market_id: B256) {
+        for rule in &mut self.rules {
+            if matches!(rule.kind, RuleKind::Liquidation { supplier: Some(s), .. } if s == supplier)
+            {
+                rule.supplied.insert(market_id);
+            }
+        }
+    }
+
+    // Markets to snapshot, listed by the largeBorrow, utilization and priceMove rules
+    pub fn snapshot_markets(&self) -> BTreeSet<B256> {
+        self.rules
+            .iter()
+            .flat_map(|rule| match &rule.kind {
+                RuleKind::LargeBorrow { markets, .. }
+                | RuleKind::Utilization { markets, .. }
+                | RuleKind::PriceMove { markets, .. } => markets.clone(),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    pub fn vaults(&self) -> BTreeSet<Address> {
+        self.rules
+            .iter()
+            .flat_map(|rule| match &rule.kind {
+                RuleKind::VaultEvent { vaults, .. } => vaults.clone(),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    pub fn process(&mut self, input: &AlertInput) -> Vec<Alert> {
+        match input {
+            AlertInput::Morpho(log) => self.process_morpho_log(log),
+            AlertInput::Vault(log) => self.process_vault_log(log),
+            AlertInput::Snapshot(snapshot) => self.process_snapshot(snapshot),
+        }
+    }
+
+    fn process_morpho_log(&mut self, log: &MorphoLog) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for rule in &mut self.rules {
+            match (&rule.kind, &log.event) {
+                (RuleKind::Liquidation { supplier, .. }, IMorphoEvents::Supply(e))
+                    if *supplier == Some(e.onBehalf) =>
+                {
+                    rule.supplied.insert(e.id);
+                }
+                (RuleKind::Liquidation { markets, supplier }, IMorphoEvents::Liquidate(e)) => {
+                    let watched = if supplier.is_some() {
+                        markets.contains(&e.id) || rule.supplied.contains(&e.id)
+                    } else {
+                        watches(markets, &e.id)
+                    };
+                    if watched {
+                        let message = describe_morpho_event(&log.event).unwrap_or_default();
+                        alerts.push(log_alert(&rule.name, log, Some(e.id), None, message));
+                    }
+                }
+                (
+                    RuleKind::LargeBorrow {
+                        markets,
+                        max_liquidity_share,
+                    },
+                    IMorphoEvents::Borrow(e),
+                ) if watches(markets, &e.id) => {
+                    // Without a snapshot the liquidity of the market is unknown
+                    let Some(snapshot) = self.snapshots.get(&e.id) else {
+                        continue;
+                    };
+                    let share = f64::from(e.assets) / snapshot.liquidity();
+                    if share > *max_liquidity_share {
+                        let message = format!(
+                            "User {:#20x} borrowed {} assets on market {:#32x}, {:.1}% of its liquidity",
+                            e.onBehalf,
+                            e.assets,
+                            e.id,
+                            share * 100.0
+                        );
+                        alerts.push(log_alert(&rule.name, log, Some(e.id), None, message));
+                    }
+                }
+                _ => (),
+            }
+        }
+        alerts
+    }
+
+    fn process_vault_log(&mut self, log: &VaultLog) -> Vec<Alert> {
+        let name = vault_event_name(&log.event);
+        let mut alerts = Vec::new();
+        for rule in &self.rules {
+            let RuleKind::VaultEvent { vaults, events } = &rule.kind else {
+                continue;
+            };
+            if watches(vaults, &log.address) && events.iter().any(|event| event == name) {
+                let message = describe_vault_event(&log.event).unwrap_or_else(|| {
+                    let fields: Vec<String> = vault_event_fields(&log.event)
+                        .into_iter()
+                        .map(|(field, value)| format!("{}={}", field, field_text(&value)))
+                        .collect();
+                    format!("{} {}", name, fields.join(" "))
+                });
+                alerts.push(log_alert(
+                    &rule.name,
+                    log,
+                    None,
+                    Some(log.address),
+                    format!("Vault {:#20x}: {}", log.address, message),
+                ));
+            }
+        }
+        alerts
+    }
+
+    fn process_snapshot(&mut self, snapshot: &StateSnapshot) -> Vec<Alert> {
+        let id = snapshot.market_id;
+        let mut alerts = Vec::new();
+        for rule in &mut self.rules {
+            let mut message = None;
+            match &rule.kind {
+                RuleKind::Utilization { markets, max } if watches(markets, &id) => {
+                    let utilization = snapshot.utilization();
+                    if utilization <= *max {
+                        rule.above.remove(&id);
+                    } else if rule.above.insert(id) {
+                        message = Some(format!(
+                            "Utilization of market {:#32x} is {:.2}%",
+                            id,
+                            utilization * 100.0
+                        ));
+                    }
+                }
+                RuleKind::PriceMove {
+                    markets,
+                    max_change,
+                    window_seconds,
+                } if watches(markets, &id) => {
+                    let price = f64::from(snapshot.price);
+                    let prices = rule.prices.entry(id).or_default();
+                    while prices.front().is_some_and(|(timestamp, _)| {
+                        *timestamp + *window_seconds < snapshot.timestamp
+                    }) {
+                        prices.pop_front();
+                    }
+                    // Largest change from a price of the window
+                    let largest = prices
+                        .iter()
+                        .filter(|(_, previous)| *previous > 0.0)
+                        .map(|(timestamp, previous)| (*timestamp, price / previous - 1.0))
+                        .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+                    match largest {
+                        Some((timestamp, change)) if change.abs() > *max_change => {
+                            message = Some(format!(
+                                "Oracle price of market {:#32x} moved {:+.2}% in {} seconds",
+                                id,
+                                change * 100.0,
+                                snapshot.timestamp.saturating_sub(timestamp)
+                            ));
+                            // The next alert needs a new move from this price
+                            prices.clear();
+                        }
+                        _ => (),
+                    }
+                    prices.push_back((snapshot.timestamp, price));
+                }
+                _ => (),
+            }
+            if let Some(message) = message {
+                alerts.push(Alert {
+                    rule: rule.name.clone(),
+                    market_id: Some(id),
+                    vault: None,
+                    block_number: None,
+                    transaction_hash: None,
+                    timestamp: Some(snapshot.timestamp),
+                    message,
+                });
+            }
+        }
+        self.snapshots.insert(id, snapshot.clone());
+        alerts
+    }
+}
+
+fn log_alert<E>(
+    rule: &str,
+    log: &DecodedLog<E>,
+    market_id: Option<B256>,
+    vault: Option<Address>,
+    message: String,
+) -> Alert {
+    Alert {
+        rule: rule.to_string(),
+        market_id,
+        vault,
+        block_number: log.block_number,
+        transaction_hash: log.transaction_hash,
+        timestamp: log.block_timestamp,
+        message,
+    }
+}
+
+fn field_text(value: &FieldValue) -> String {
+    match value {
+        FieldValue::Address(address) => address.to_checksum(None),
+        FieldValue::Uint(value) => value.to_string(),
+        FieldValue::Bytes32(value) => value.to_string(),
+        FieldValue::Bool(value) => value.to_string(),
+        FieldValue::Bytes32List(values) => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        FieldValue::String(value) => value.clone(),
+    }
+}
+
+pub enum Sink {
+    Stdout,
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+    },
+    File(fs::File),
+}
+
+impl Sink {
+    pub fn open(config: &SinkConfig) -> Result<Self> {
+        Ok(match config {
+            SinkConfig::Stdout => Sink::Stdout,
+            SinkConfig::Webhook { url } => Sink::Webhook {
+                client: reqwest::Client::builder()
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .build()?,
+                url: url.clone(),
+            },
+            SinkConfig::File { path } => Sink::File(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ),
+        })
+    }
+
+    pub async fn send(&mut self, alert: &Alert) -> Result<()> {
+        match self {
+            Sink::Stdout => println!("[{}] {}", alert.rule, alert.message),
+            Sink::Webhook { client, url } => {
+                client
+                    .post(url.as_str())
+                    .json(alert)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::File(file) => {
+                serde_json::to_writer(&mut *file, alert).map_err(io::Error::from)?;
+                writeln!(file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn open_sinks(configs: &[SinkConfig]) -> Result<Vec<Sink>> {
+    if configs.is_empty() {
+        return Ok(vec![Sink::Stdout]);
+    }
+    configs.iter().map(Sink::open).collect()
+}
+
+// A failing sink does not stop the others
+pub async fn deliver(sinks: &mut [Sink], alert: &Alert) {
+    for sink in sinks {
+        if let Err(error) = sink.send(alert).await {
+            eprintln!("Failed to deliver alert of rule {}: {}", alert.rule, error);
+        }
+    }
+}
+
+// Scripted events and snapshots, with the rules expected to raise an alert (see data/alerts/fixture.json)
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fixture {
+    pub steps: Vec<FixtureStep>,
+    // Rule names of the raised alerts, in order
+    #[serde(default)]
+    pub expected: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FixtureStep {
+    MorphoLog(Log),
+    VaultLog(Log),
+    Snapshot(StateSnapshot),
+}
+
+impl Fixture {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::from)?)
+    }
+}
+
+// Runs the fixture steps through the engine, without any RPC
+pub fn replay_fixture(engine: &mut AlertEngine, fixture: &Fixture) -> Result<Vec<Alert>> {
+    let mut alerts = Vec::new();
+    for step in &fixture.steps {
+        let input = match step {
+            FixtureStep::MorphoLog(log) => AlertInput::Morpho(decode_log(log)?),
+            FixtureStep::VaultLog(log) => AlertInput::Vault(decode_log(log)?),
+            FixtureStep::Snapshot(snapshot) => AlertInput::Snapshot(snapshot.clone()),
+        };
+        alerts.extend(engine.process(&input));
+    }
+    Ok(alerts)
+}
+
+// Markets a user ever supplied to, from its Supply events since the Morpho deployment on the chain
+pub async fn fetch_supplied_markets(rpc_url: Url, supplier: Address) -> Result<BTreeSet<B256>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let from_block = morpho_deployment_block(provider.get_chain_id().await?)?;
+    let to_block = provider.get_block_number().await?;
+    let filter = Filter::new()
+        .address(MORPHO_ADDRESS)
+        .event_signature(IMorpho::Supply::SIGNATURE_HASH)
+        .topic3(supplier.into_word());
+
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        from_block,
+        to_block,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+    let mut markets = BTreeSet::new();
+    for log in logs {
+        markets.insert(log.log_decode::<IMorpho::Supply>()?.inner.data.id);
+    }
+    Ok(markets)
+}
+
+// Evaluates the rules on the Morpho events, the events of the vaults of the rules and snapshots
+// of their markets, until every stream stopped
+pub async fn run_alerts(rpc_url: Url, ws_url: String, config: AlertConfig) -> Result<()> {
+    let mut engine = AlertEngine::new(config.rules);
+    let mut sinks = open_sinks(&config.sinks)?;
+    for supplier in engine.suppliers() {
+        for market_id in fetch_supplied_markets(rpc_url.clone(), supplier).await? {
+            engine.add_supplied_market(supplier, market_id);
+        }
+    }
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    {
+        let sender = sender.clone();
+        let ws_url = ws_url.clone();
+        tokio::spawn(async move {
+            let watched = watch_morpho_events(&ws_url, |log| {
+                let _ = sender.send(AlertInput::Morpho(log));
+            })
+            .await;
+            if let Err(error) = watched {
+                eprintln!("Morpho event stream stopped: {}", error);
+            }
+        });
+    }
+
+    let vaults: Vec<Address> = engine.vaults().into_iter().collect();
+    if !vaults.is_empty() {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let watched = watch_vault_events(&ws_url, &vaults, |log| {
+                let _ = sender.send(AlertInput::Vault(log));
+            })
+            .await;
+            if let Err(error) = watched {
+                eprintln!("Vault event stream stopped: {}", error);
+            }
+        });
+    }
+
+    let markets = engine.snapshot_markets();
+    if !markets.is_empty() {
+        let sender = sender.clone();
+        let interval = Duration::from_secs(config.snapshot_interval.max(1));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                for id in &markets {
+                    let snapshot = async {
+                        let info =
+                            fetch_market_info(rpc_url.clone(), *id, BlockId::latest()).await?;
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                        Ok::<_, Error>(StateSnapshot::new(&info, now))
+                    };
+                    match snapshot.await {
+                        Ok(snapshot) => {
+                            let _ = sender.send(AlertInput::Snapshot(snapshot));
+                        }
+                        Err(error) => eprintln!("Failed to snapshot market {}: {}", id, error),
+                    }
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    while let Some(input) = receiver.recv().await {
+        for alert in engine.process(&input) {
+            deliver(&mut sinks, &alert).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_the_fixture_with_the_example_rules() {
+        let config = AlertConfig::load("data/alerts/rules.toml").unwrap();
+        let fixture = Fixture::load("data/alerts/fixture.json").unwrap();
+        let mut engine = AlertEngine::new(config.rules);
+
+        let alerts = replay_fixture(&mut engine, &fixture).unwrap();
+        let rules: Vec<&str> = alerts.iter().map(|alert| alert.rule.as_str()).collect();
+        assert_eq!(
+            rules,
+            [
+                "large-borrow",
+                "high-utilization",
+                "price-move",
+                "my-liquidations",
+                "my-vault-caps",
+                "high-utilization"
+            ]
+        );
+        assert_eq!(rules, fixture.expected);
+    }
+
+    #[test]
+    fn rejects_rules_without_markets_or_vaults() {
+        let config: AlertConfig = toml::from_str(
+            r#"
+            [[rules]]
+            name = "any-utilization"
+            type = "utilization"
+            max = 0.95
+
+            [[rules]]
+            name = "any-liquidation"
+            type = "liquidation"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(Error::Config(_))));
+
+        let config: AlertConfig = toml::from_str(
+            r#"
+            [[rules]]
+            name = "any-cap"
+            type = "vaultEvent"
+            events = ["SubmitCap"]
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::arithmetic::to_assets_down;
+use crate::error::Result;
+use crate::irm::{signed_wad_to_f64, wad_to_f64, MarketCurve};
+use crate::market::{IMorpho, IIRM};
+use crate::vault::IVault;
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+
+// Number of chunks the vault assets are split into by the optimizer
+pub const DEFAULT_STEPS: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketAllocation {
+    pub id: B256,
+    // Supply cap set by the curator, in underlying assets
+    pub cap: f64,
+    // Assets currently supplied by the vault
+    pub current_assets: f64,
+    // Market state, including the vault's current supply
+    pub curve: MarketCurve,
+}
+
+impl MarketAllocation {
+    // The vault cannot withdraw more than the market idle liquidity
+    pub fn min_assets(&self) -> f64 {
+        let liquidity = self.curve.total_supply_assets - self.curve.total_borrow_assets;
+        (self.current_assets - liquidity).max(0.0)
+    }
+
+    pub fn supply_apy_at(&self, assets: f64) -> f64 {
+        self.curve.supply_apy_with(assets - self.current_assets)
+    }
+
+    // Yearly interest earned by the vault when `assets` are supplied to this market
+    pub fn interest_at(&self, assets: f64) -> f64 {
+        assets * self.supply_apy_at(assets)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationPlan {
+    // (market id, current assets, target assets)
+    pub allocations: Vec<(B256, f64, f64)>,
+    // Assets left out of every market because all caps are reached
+    pub idle_assets: f64,
+    pub apy_before: f64,
+    pub apy_after: f64,
+}
+
+pub fn blended_apy(total_assets: f64, markets: &[MarketAllocation], assets: &[f64]) -> f64 {
+    if total_assets == 0.0 {
+        return 0.0;
+    }
+    let interest: f64 = markets
+        .iter()
+        .zip(assets)
+        .map(|(market, assets)| market.interest_at(*assets))
+        .sum();
+    interest / total_assets
+}
+
+// Greedy allocation: assets are split in `steps` chunks and each chunk goes to the market
+// where it adds the most interest, given the utilization (and so the rate) it leads to.
+pub fn optimize_allocation(
+    total_assets: f64,
+    markets: &[MarketAllocation],
+    steps: usize,
+) -> AllocationPlan {
+    let current: Vec<f64> = markets.iter().map(|m| m.current_assets).collect();
+    let apy_before = blended_apy(total_assets, markets, &current);
+
+    let mut target: Vec<f64> = markets.iter().map(|m| m.min_assets()).collect();
+    let mut remaining = total_assets - target.iter().sum::<f64>();
+    let chunk = total_assets / steps.max(1) as f64;
+
+    while remaining > 0.0 && chunk > 0.0 {
+        let amount = chunk.min(remaining);
+        let mut best: Option<(usize, f64, f64)> = None;
+        for (i, market) in markets.iter().enumerate() {
+            let room = market.cap - target[i];
+            if room <= 0.0 {
+                continue;
+            }
+            let added = amount.min(room);
+            let gain = market.interest_at(target[i] + added) - market.interest_at(target[i]);
+            if best.is_none_or(|(_, best_gain, _)| gain > best_gain) {
+                best = Some((i, gain, added));
+            }
+        }
+        match best {
+            Some((i, _, added)) => {
+                target[i] += added;
+                remaining -= added;
+            }
+            None => break,
+        }
+    }
+
+    let apy_after = blended_apy(total_assets, markets, &target);
+    AllocationPlan {
+        allocations: markets
+            .iter()
+            .zip(current.iter().zip(&target))
+            .map(|(market, (current, target))| (market.id, *current, *target))
+            .collect(),
+        idle_assets: remaining.max(0.0),
+        apy_before,
+        apy_after,
+    }
+}
+
+pub async fn retrieve_vault_allocations(
+    rpc_url: Url,
+    vault_address: Address,
+) -> Result<(f64, Vec<MarketAllocation>)> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let vault = IVault::new(vault_address, provider.clone());
+    let morpho = IMorpho::new(vault.MORPHO().call().await?._0, provider.clone());
+
+    let total_assets = f64::from(vault.totalAssets().call().await?.assets);
+
+    // Every enabled market is in the withdraw queue
+    let queue_length = vault.withdrawQueueLength().call().await?._0;
+    let mut markets = Vec::new();
+    for i in 0..queue_length.to::<u64>() {
+        let id = vault.withdrawQueue(U256::from(i)).call().await?._0;
+        let config = vault.config(id).call().await?;
+        let market_params = morpho.idToMarketParams(id).call().await?;
+        let market_data = morpho.market(id).call().await?;
+        let position = morpho.position(id, vault_address).call().await?;
+
+        let current_assets = to_assets_down(
+            position.supplyShares,
+            U256::from(market_data.totalSupplyAssets),
+            U256::from(market_data.totalSupplyShares),
+        );
+
+        // Idle markets have no IRM and earn nothing
+        let rate_at_target = if market_params.irm == Address::ZERO {
+            0.0
+        } else {
+            let irm = IIRM::new(market_params.irm, provider.clone());
+            signed_wad_to_f64(irm.rateAtTarget(id).call().await?._0)
+        };
+
+        markets.push(MarketAllocation {
+            id,
+            cap: f64::from(config.cap),
+            current_assets: f64::from(current_assets),
+            curve: MarketCurve {
+                total_supply_assets: market_data.totalSupplyAssets as f64,
+                total_borrow_assets: market_data.totalBorrowAssets as f64,
+                rate_at_target,
+                fee: wad_to_f64(U256::from(market_data.fee)),
+            },
+        });
+    }
+    Ok((total_assets, markets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irm::INITIAL_RATE_AT_TARGET;
+
+    fn market(
+        id: u8,
+        cap: f64,
+        current_assets: f64,
+        total_supply_assets: f64,
+        total_borrow_assets: f64,
+        fee: f64,
+    ) -> MarketAllocation {
+        MarketAllocation {
+            id: B256::repeat_byte(id),
+            cap,
+            current_assets,
+            curve: MarketCurve {
+                total_supply_assets,
+                total_borrow_assets,
+                rate_at_target: INITIAL_RATE_AT_TARGET,
+                fee,
+            },
+        }
+    }
+
+    fn target(plan: &AllocationPlan, id: u8) -> f64 {
+        plan.allocations
+            .iter()
+            .find(|(market_id, _, _)| *market_id == B256::repeat_byte(id))
+            .unwrap()
+            .2
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn fills_the_best_market_up_to_its_cap() {
+        // 1 is at 90% utilization, 2 at 20%: everything movable goes to 1 until its cap
+        let markets = [
+            market(1, 600.0, 500.0, 1_000.0, 900.0, 0.0),
+            market(2, 2_000.0, 500.0, 500.0, 100.0, 0.0),
+        ];
+        let plan = optimize_allocation(1_000.0, &markets, DEFAULT_STEPS);
+
+        assert_close(target(&plan, 1), 600.0);
+        assert_close(target(&plan, 2), 400.0);
+        assert_close(plan.idle_assets, 0.0);
+    }
+
+    #[test]
+    fn keeps_what_cannot_be_withdrawn() {
+        // 2 earns nothing (100% fee) but only 50 assets of liquidity are left, so 250 assets stay there
+        let markets = [
+            market(1, 2_000.0, 700.0, 2_000.0, 1_000.0, 0.0),
+            market(2, 2_000.0, 300.0, 1_000.0, 950.0, 1.0),
+        ];
+        assert_close(markets[1].min_assets(), 250.0);
+
+        let plan = optimize_allocation(1_000.0, &markets, DEFAULT_STEPS);
+
+        assert_close(target(&plan, 1), 750.0);
+        assert_close(target(&plan, 2), 250.0);
+    }
+
+    #[test]
+    fn leaves_assets_idle_when_all_caps_are_reached() {
+        let markets = [
+            market(1, 300.0, 200.0, 1_000.0, 800.0, 0.0),
+            market(2, 400.0, 200.0, 1_000.0, 500.0, 0.0),
+        ];
+        let plan = optimize_allocation(1_000.0, &markets, DEFAULT_STEPS);
+
+        assert_close(target(&plan, 1), 300.0);
+        assert_close(target(&plan, 2), 400.0);
+        assert_close(plan.idle_assets, 300.0);
+    }
+
+    #[test]
+    fn reports_apy_before_and_after() {
+        let markets = [
+            market(1, 600.0, 500.0, 1_000.0, 900.0, 0.0),
+            market(2, 2_000.0, 500.0, 500.0, 100.0, 0.0),
+        ];
+        let plan = optimize_allocation(1_000.0, &markets, DEFAULT_STEPS);
+
+        assert_close(
+            plan.apy_before,
+            blended_apy(1_000.0, &markets, &[500.0, 500.0]),
+        );
+        assert_close(
+            plan.apy_after,
+            blended_apy(1_000.0, &markets, &[target(&plan, 1), target(&plan, 2)]),
+        );
+        assert!(plan.apy_after > plan.apy_before);
+    }
+}
@@ -1,22 +1,28 @@
+use crate::error::{ApiError, Result};
+use crate::output::checksummed;
 use alloy::primitives::Address;
-use eyre::Result;
-use reqwest;
+use serde::Serialize;
 use serde_json::{self, Value};
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
-#[derive(Debug)]
+// The API is queried from retry loops, a stuck request must fail instead of hanging them
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Asset {
-    token: Address,
-    price: Option<f64>,
-    decimals: u64,
-    symbol: String,
-    chain: u64,
+    #[serde(serialize_with = "checksummed")]
+    pub token: Address,
+    pub price: Option<f64>,
+    pub decimals: u64,
+    pub symbol: String,
+    pub chain: u64,
 }
 
 pub async fn get_usd_prices() -> Result<Vec<Asset>> {
     let mut res: Vec<Asset> = Vec::new();
 
-    let client = reqwest::Client::new();
+    let client = reqwest::Client::builder().timeout(API_TIMEOUT).build()?;
     let mut body = HashMap::new();
     let query: &str = "query {
         assets(first: 1000) {
@@ -41,23 +47,28 @@ pub async fn get_usd_prices() -> Result<Vec<Asset>> {
         .json(&body)
         .send()
         .await?
+        // Rate limits and server errors stay HTTP errors, which are transient
+        .error_for_status()?
         .text()
         .await?;
     let raw_data: Value = serde_json::from_str(data.as_str())?;
     let prices = raw_data["data"]["assets"]["items"].clone();
-    if prices.is_array() {
-        for x in prices.as_array().unwrap().iter() {
-            if x["address"].is_string() {
-                let s = x["address"].to_string();
-                let address = Address::from_str(&s[1..s.len() - 1])?;
-                res.push(Asset {
-                    token: address,
-                    price: x["price"].as_f64(),
-                    decimals: x["decimals"].as_u64().unwrap(),
-                    symbol: String::from(x["symbol"].as_str().unwrap()),
-                    chain: x["chain"]["id"].as_u64().unwrap(),
-                })
-            }
+    let Some(items) = prices.as_array() else {
+        return Err(ApiError::Schema(format!("no assets in {}", data)).into());
+    };
+    for x in items.iter() {
+        if x["address"].is_string() {
+            let s = x["address"].to_string();
+            let address = Address::from_str(&s[1..s.len() - 1])
+                .map_err(|_| ApiError::Schema(format!("invalid address {}", s)))?;
+            let field = |name: &str| ApiError::Schema(format!("missing {} for {}", name, s));
+            res.push(Asset {
+                token: address,
+                price: x["priceUsd"].as_f64(),
+                decimals: x["decimals"].as_u64().ok_or_else(|| field("decimals"))?,
+                symbol: String::from(x["symbol"].as_str().ok_or_else(|| field("symbol"))?),
+                chain: x["chain"]["id"].as_u64().ok_or_else(|| field("chain"))?,
+            })
         }
     }
     Ok(res)
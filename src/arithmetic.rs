@@ -23,3 +23,67 @@ pub fn w_taylor_compounded(x: U256, n: U256) -> U256 {
 
     first_term + second_term + third_term
 }
+
+// Equivalent of SharesMathLib.sol
+pub const VIRTUAL_SHARES: u64 = 1_000_000;
+pub const VIRTUAL_ASSETS: u64 = 1;
+
+pub fn to_assets_down(shares: U256, total_assets: U256, total_shares: U256) -> U256 {
+    mul_div_down(
+        shares,
+        total_assets + U256::from(VIRTUAL_ASSETS),
+        total_shares + U256::from(VIRTUAL_SHARES),
+    )
+}
+
+pub fn mul_div_up(x: U256, y: U256, z: U256) -> U256 {
+    (x * y + (z - U256::from(1))) / z
+}
+
+pub fn to_assets_up(shares: U256, total_assets: U256, total_shares: U256) -> U256 {
+    mul_div_up(
+        shares,
+        total_assets + U256::from(VIRTUAL_ASSETS),
+        total_shares + U256::from(VIRTUAL_SHARES),
+    )
+}
+
+pub fn to_shares_down(assets: U256, total_assets: U256, total_shares: U256) -> U256 {
+    mul_div_down(
+        assets,
+        total_shares + U256::from(VIRTUAL_SHARES),
+        total_assets + U256::from(VIRTUAL_ASSETS),
+    )
+}
+
+pub fn to_shares_up(assets: U256, total_assets: U256, total_shares: U256) -> U256 {
+    mul_div_up(
+        assets,
+        total_shares + U256::from(VIRTUAL_SHARES),
+        total_assets + U256::from(VIRTUAL_ASSETS),
+    )
+}
+
+// Equivalent of the health check of Morpho.sol (_isHealthy)
+pub fn max_borrow(collateral: U256, price: U256, lltv: U256) -> U256 {
+    w_mul_down(
+        mul_div_down(collateral, price, U256::from(10).pow(U256::from(36))),
+        lltv,
+    )
+}
+
+// Max borrow over borrowed assets, the position is liquidatable below 1
+pub fn health_factor(
+    collateral: U256,
+    borrow_shares: U256,
+    total_borrow_assets: U256,
+    total_borrow_shares: U256,
+    price: U256,
+    lltv: U256,
+) -> Option<f64> {
+    let borrowed = to_assets_up(borrow_shares, total_borrow_assets, total_borrow_shares);
+    if borrowed.is_zero() {
+        return None;
+    }
+    Some(f64::from(max_borrow(collateral, price, lltv)) / f64::from(borrowed))
+}
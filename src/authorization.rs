@@ -0,0 +1,179 @@
+use crate::error::{Error, Result};
+use crate::market::{IMorpho, MORPHO_ADDRESS};
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, PrimitiveSignature, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::{eip712_domain, Eip712Domain, SolCall, SolStruct},
+    transports::http::reqwest::Url,
+};
+use IMorpho::{Authorization, Signature};
+
+// Morpho's domain only has a chain id and a verifying contract:
+// keccak256("EIP712Domain(uint256 chainId,address verifyingContract)")
+pub fn morpho_domain(chain_id: u64, morpho: Address) -> Eip712Domain {
+    eip712_domain! {
+        chain_id: chain_id,
+        verifying_contract: morpho,
+    }
+}
+
+pub fn domain_separator(chain_id: u64, morpho: Address) -> B256 {
+    morpho_domain(chain_id, morpho).separator()
+}
+
+// Digest signed by the authorizer: keccak256("\x19\x01" ++ DOMAIN_SEPARATOR ++ hashStruct(authorization))
+pub fn authorization_digest(authorization: &Authorization, domain: &Eip712Domain) -> B256 {
+    authorization.eip712_signing_hash(domain)
+}
+
+// Splits a signature into the (v, r, s) expected by ecrecover, with v in {27, 28}
+pub fn ecrecover_vrs(signature: &PrimitiveSignature) -> (u8, B256, B256) {
+    (
+        27 + signature.v() as u8,
+        B256::from(signature.r()),
+        B256::from(signature.s()),
+    )
+}
+
+pub fn sign_authorization(
+    signer: &PrivateKeySigner,
+    authorization: &Authorization,
+    domain: &Eip712Domain,
+) -> Result<Signature> {
+    let signature = signer.sign_hash_sync(&authorization_digest(authorization, domain))?;
+    let (v, r, s) = ecrecover_vrs(&signature);
+    Ok(Signature { v, r, s })
+}
+
+// Offline equivalent of the checks done by setAuthorizationWithSig, except the nonce and the deadline
+pub fn recover_authorizer(
+    authorization: &Authorization,
+    signature: &Signature,
+    domain: &Eip712Domain,
+) -> Result<Address> {
+    let y_parity = match signature.v {
+        27 => false,
+        28 => true,
+        v => return Err(Error::Signer(format!("invalid signature v: {}", v))),
+    };
+    let signature = PrimitiveSignature::from_scalars_and_parity(signature.r, signature.s, y_parity);
+    Ok(signature.recover_address_from_prehash(&authorization_digest(authorization, domain))?)
+}
+
+pub fn verify_authorization(
+    authorization: &Authorization,
+    signature: &Signature,
+    domain: &Eip712Domain,
+) -> bool {
+    recover_authorizer(authorization, signature, domain)
+        .is_ok_and(|signatory| signatory == authorization.authorizer)
+}
+
+// Builds an authorization with the current nonce of the authorizer, checking the domain separator on chain
+pub async fn build_authorization(
+    rpc_url: Url,
+    authorizer: Address,
+    authorized: Address,
+    is_authorized: bool,
+    deadline: U256,
+) -> Result<(Authorization, Eip712Domain)> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    let chain_id = provider.get_chain_id().await?;
+    let domain = morpho_domain(chain_id, MORPHO_ADDRESS);
+    let separator = domain_separator(chain_id, MORPHO_ADDRESS);
+    let onchain_separator = morpho.DOMAIN_SEPARATOR().call().await?._0;
+    if onchain_separator != separator {
+        return Err(Error::Config(format!(
+            "domain separator mismatch: computed {:#32x}, Morpho has {:#32x}",
+            separator, onchain_separator
+        )));
+    }
+
+    let nonce = morpho.nonce(authorizer).call().await?._0;
+    Ok((
+        Authorization {
+            authorizer,
+            authorized,
+            isAuthorized: is_authorized,
+            nonce,
+            deadline,
+        },
+        domain,
+    ))
+}
+
+// Unsigned setAuthorizationWithSig transaction, it can be sent by anyone (e.g. a relayer or a bundler)
+pub fn build_authorization_transaction(
+    from: Address,
+    authorization: Authorization,
+    signature: Signature,
+) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_from(from)
+        .with_to(MORPHO_ADDRESS)
+        .with_input(
+            IMorpho::setAuthorizationWithSigCall {
+                authorization,
+                signature,
+            }
+            .abi_encode(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, b256};
+
+    fn authorization_by(authorizer: Address) -> Authorization {
+        Authorization {
+            authorizer,
+            authorized: address!("000000000000000000000000000000000000dEaD"),
+            isAuthorized: true,
+            nonce: U256::from(3),
+            deadline: U256::from(1_700_000_000u64),
+        }
+    }
+
+    #[test]
+    fn matches_the_morpho_domain_separator() {
+        assert_eq!(
+            domain_separator(1, MORPHO_ADDRESS),
+            b256!("ec6ac4ec6469375712b671d38548b711c7a4a17b4db99be41e0eb9f28cafdd2d")
+        );
+    }
+
+    #[test]
+    fn recovers_the_signer_of_an_authorization() {
+        let signer = PrivateKeySigner::random();
+        let domain = morpho_domain(1, MORPHO_ADDRESS);
+        let authorization = authorization_by(signer.address());
+
+        let signature = sign_authorization(&signer, &authorization, &domain).unwrap();
+        assert!(signature.v == 27 || signature.v == 28);
+        assert_eq!(
+            recover_authorizer(&authorization, &signature, &domain).unwrap(),
+            signer.address()
+        );
+        assert!(verify_authorization(&authorization, &signature, &domain));
+
+        // Another chain, another authorizer or a malformed v do not verify
+        assert!(!verify_authorization(
+            &authorization,
+            &signature,
+            &morpho_domain(8453, MORPHO_ADDRESS)
+        ));
+        assert!(!verify_authorization(
+            &authorization_by(Address::ZERO),
+            &signature,
+            &domain
+        ));
+        let malformed = Signature { v: 1, ..signature };
+        assert!(recover_authorizer(&authorization, &malformed, &domain).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::api::Asset;
+use crate::error::Result;
+use crate::market::{IMorpho, IOracle, MORPHO_ADDRESS};
+use crate::units::{collateral_price_in_loan_f64, TokenInfo};
+use alloy::{
+    primitives::{Address, B256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+
+// Deviation above which an alert is raised when no threshold is configured for a market
+pub const DEFAULT_DEVIATION_THRESHOLD: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketWatch {
+    pub id: B256,
+    // Maximum accepted relative deviation between the oracle and the API prices
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviationReport {
+    pub id: B256,
+    // Loan tokens per collateral token, from the oracle
+    pub oracle_price: f64,
+    // Loan tokens per collateral token, from the API USD prices
+    pub implied_price: f64,
+    pub deviation: f64,
+    pub threshold: f64,
+}
+
+impl DeviationReport {
+    pub fn is_alert(&self) -> bool {
+        self.deviation > self.threshold
+    }
+}
+
+pub fn find_asset(assets: &[Asset], chain_id: u64, token: Address) -> Option<&Asset> {
+    assets
+        .iter()
+        .find(|asset| asset.chain == chain_id && asset.token == token)
+}
+
+// Collateral/loan ratio implied by USD prices, None when a price is missing or not positive
+pub fn implied_price(collateral: &Asset, loan: &Asset) -> Option<f64> {
+    match (collateral.price, loan.price) {
+        (Some(collateral_usd), Some(loan_usd)) if collateral_usd > 0.0 && loan_usd > 0.0 => {
+            Some(collateral_usd / loan_usd)
+        }
+        _ => None,
+    }
+}
+
+// None when the implied price is not positive, there is nothing to compare the oracle to
+pub fn relative_deviation(oracle_price: f64, implied_price: f64) -> Option<f64> {
+    (implied_price > 0.0).then(|| (oracle_price - implied_price).abs() / implied_price)
+}
+
+// Compares every watched market oracle to the API prices. Markets whose tokens have no positive USD price are skipped.
+pub async fn check_oracle_deviations(
+    rpc_url: Url,
+    chain_id: u64,
+    markets: &[MarketWatch],
+    assets: &[Asset],
+) -> Result<Vec<DeviationReport>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    let mut reports = Vec::new();
+    for market in markets {
+        let market_params = morpho.idToMarketParams(market.id).call().await?;
+        let (Some(collateral), Some(loan)) = (
+            find_asset(assets, chain_id, market_params.collateralToken),
+            find_asset(assets, chain_id, market_params.loanToken),
+        ) else {
+            continue;
+        };
+        let Some(implied_price) = implied_price(collateral, loan) else {
+            continue;
+        };
+
+        let price = IOracle::new(market_params.oracle, provider.clone())
+            .price()
+            .call()
+            .await?
+            ._0;
+        let oracle_price = collateral_price_in_loan_f64(
+            price,
+            TokenInfo::try_from(collateral)?.decimals,
+            TokenInfo::try_from(loan)?.decimals,
+        )?;
+
+        let Some(deviation) = relative_deviation(oracle_price, implied_price) else {
+            continue;
+        };
+        reports.push(DeviationReport {
+            id: market.id,
+            oracle_price,
+            implied_price,
+            deviation,
+            threshold: market.threshold,
+        });
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(price: Option<f64>) -> Asset {
+        Asset {
+            token: Address::ZERO,
+            price,
+            decimals: 18,
+            symbol: String::new(),
+            chain: 1,
+        }
+    }
+
+    fn report(deviation: f64) -> DeviationReport {
+        DeviationReport {
+            id: B256::ZERO,
+            oracle_price: 0.0,
+            implied_price: 0.0,
+            deviation,
+            threshold: DEFAULT_DEVIATION_THRESHOLD,
+        }
+    }
+
+    #[test]
+    fn implies_a_price_from_positive_usd_prices_only() {
+        assert_eq!(
+            implied_price(&asset(Some(3_000.0)), &asset(Some(1.5))),
+            Some(2_000.0)
+        );
+        assert_eq!(
+            implied_price(&asset(Some(3_000.0)), &asset(Some(0.0))),
+            None
+        );
+        assert_eq!(implied_price(&asset(Some(0.0)), &asset(Some(1.0))), None);
+        assert_eq!(implied_price(&asset(Some(-1.0)), &asset(Some(1.0))), None);
+        assert_eq!(implied_price(&asset(None), &asset(Some(1.0))), None);
+    }
+
+    #[test]
+    fn deviates_relatively_to_the_implied_price() {
+        assert_eq!(relative_deviation(2_100.0, 2_000.0), Some(0.05));
+        assert_eq!(relative_deviation(1_900.0, 2_000.0), Some(0.05));
+        assert_eq!(relative_deviation(1.0, 0.0), None);
+    }
+
+    #[test]
+    fn alerts_above_the_threshold() {
+        assert!(report(0.05).is_alert());
+        assert!(!report(DEFAULT_DEVIATION_THRESHOLD).is_alert());
+        assert!(!report(0.0).is_alert());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::revert::{revert_data, ProtocolError};
+use alloy::{
+    contract,
+    primitives::{hex::FromHexError, utils::UnitsError, SignatureError},
+    signers, sol_types,
+    transports::{RpcError, TransportError, TransportErrorKind},
+};
+use arrow_schema::ArrowError;
+use parquet::errors::ParquetError;
+use std::{fmt, io, time::SystemTimeError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // Transport failures and JSON-RPC error responses
+    Rpc(TransportError),
+    // Logs, return data or revert data that do not match the ABI
+    AbiDecode(String),
+    // Calls reverted by Morpho, a vault or an oracle
+    Revert(ProtocolError),
+    Api(ApiError),
+    // Overflows, divisions by zero and unit conversions
+    Math(String),
+    // Invalid addresses, ids, specs or settings given by the caller
+    Config(String),
+    // Markets, vaults or blocks which do not exist
+    NotFound(String),
+    Slippage(String),
+    Signer(String),
+    // SQLite failures of the indexer
+    Database(rusqlite::Error),
+    // Registration or encoding of the exported gauges
+    Prometheus(prometheus::Error),
+    Csv(csv::Error),
+    // Parquet exports, Arrow errors included
+    Parquet(ParquetError),
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    // The response does not have the expected shape, the API schema probably changed
+    Schema(String),
+}
+
+impl Error {
+    // Transient failures (rate limits, timeouts, unavailable nodes) can be retried, the others will fail again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Rpc(error) => is_transient_rpc_error(error),
+            Error::Api(ApiError::Http(error)) => {
+                error.is_timeout()
+                    || error.is_connect()
+                    || error.status().is_some_and(|status| {
+                        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    })
+            }
+            Error::Io(error) => is_transient_io_error(error),
+            _ => false,
+        }
+    }
+}
+
+fn is_transient_io_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+fn is_transient_rpc_error(error: &TransportError) -> bool {
+    match error {
+        // 429 and -32005 are the rate limit codes of the main providers, -32603 is an internal error
+        RpcError::ErrorResp(payload) => {
+            let message = payload.message.to_lowercase();
+            matches!(payload.code, 429 | -32005 | -32603)
+                || [
+                    "rate limit",
+                    "too many requests",
+                    "timeout",
+                    "header not found",
+                ]
+                .iter()
+                .any(|pattern| message.contains(pattern))
+        }
+        RpcError::NullResp => true,
+        RpcError::Transport(kind) => match kind {
+            TransportErrorKind::HttpError(http_error) => {
+                http_error.status == 429 || http_error.status >= 500
+            }
+            TransportErrorKind::BackendGone | TransportErrorKind::MissingBatchResponse(_) => true,
+            // The HTTP client reports connection failures and timeouts as custom errors
+            TransportErrorKind::Custom(error) => {
+                if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+                    error.is_timeout() || error.is_connect()
+                } else if let Some(error) = error.downcast_ref::<io::Error>() {
+                    is_transient_io_error(error)
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc(error) => write!(f, "rpc error: {}", error),
+            Error::AbiDecode(message) => write!(f, "abi decode error: {}", message),
+            Error::Revert(error) => write!(f, "reverted with {}", error),
+            Error::Api(error) => write!(f, "api error: {}", error),
+            Error::Math(message) => write!(f, "math error: {}", message),
+            Error::Config(message) => write!(f, "config error: {}", message),
+            Error::NotFound(message) => write!(f, "not found: {}", message),
+            Error::Slippage(message) => write!(f, "slippage exceeded: {}", message),
+            Error::Signer(message) => write!(f, "signer error: {}", message),
+            Error::Database(error) => write!(f, "database error: {}", error),
+            Error::Prometheus(error) => write!(f, "prometheus error: {}", error),
+            Error::Csv(error) => write!(f, "csv error: {}", error),
+            Error::Parquet(error) => write!(f, "parquet error: {}", error),
+            Error::Io(error) => write!(f, "io error: {}", error),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Http(error) => write!(f, "{}", error),
+            ApiError::Json(error) => write!(f, "invalid json: {}", error),
+            ApiError::Schema(message) => write!(f, "unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rpc(error) => Some(error),
+            Error::Revert(error) => Some(error),
+            Error::Api(error) => Some(error),
+            Error::Database(error) => Some(error),
+            Error::Prometheus(error) => Some(error),
+            Error::Csv(error) => Some(error),
+            Error::Parquet(error) => Some(error),
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Http(error) => Some(error),
+            ApiError::Json(error) => Some(error),
+            ApiError::Schema(_) => None,
+        }
+    }
+}
+
+// Reverts are decoded, other transport errors are kept as they are
+impl From<TransportError> for Error {
+    fn from(error: TransportError) -> Self {
+        match revert_data(&error) {
+            Some(data) => Error::Revert(ProtocolError::decode(&data)),
+            None => Error::Rpc(error),
+        }
+    }
+}
+
+impl From<contract::Error> for Error {
+    fn from(error: contract::Error) -> Self {
+        match error {
+            contract::Error::TransportError(error) => error.into(),
+            error => Error::AbiDecode(error.to_string()),
+        }
+    }
+}
+
+impl From<sol_types::Error> for Error {
+    fn from(error: sol_types::Error) -> Self {
+        Error::AbiDecode(error.to_string())
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(error: ProtocolError) -> Self {
+        Error::Revert(error)
+    }
+}
+
+impl From<ApiError> for Error {
+    fn from(error: ApiError) -> Self {
+        Error::Api(error)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Api(ApiError::Http(error))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Api(ApiError::Json(error))
+    }
+}
+
+impl From<UnitsError> for Error {
+    fn from(error: UnitsError) -> Self {
+        Error::Math(error.to_string())
+    }
+}
+
+impl From<FromHexError> for Error {
+    fn from(error: FromHexError) -> Self {
+        Error::Config(error.to_string())
+    }
+}
+
+impl From<signers::Error> for Error {
+    fn from(error: signers::Error) -> Self {
+        Error::Signer(error.to_string())
+    }
+}
+
+impl From<SignatureError> for Error {
+    fn from(error: SignatureError) -> Self {
+        Error::Signer(error.to_string())
+    }
+}
+
+impl From<SystemTimeError> for Error {
+    fn from(error: SystemTimeError) -> Self {
+        Error::Config(format!("system clock is before the unix epoch: {}", error))
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Database(error)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(error: csv::Error) -> Self {
+        Error::Csv(error)
+    }
+}
+
+impl From<ParquetError> for Error {
+    fn from(error: ParquetError) -> Self {
+        Error::Parquet(error)
+    }
+}
+
+impl From<ArrowError> for Error {
+    fn from(error: ArrowError) -> Self {
+        ParquetError::from(error).into()
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(error: prometheus::Error) -> Self {
+        Error::Prometheus(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn retries_only_transient_custom_transport_errors() {
+        let refused = TransportErrorKind::custom(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(Error::Rpc(refused).is_transient());
+        let other = TransportErrorKind::custom(io::Error::other("invalid certificate"));
+        assert!(!Error::Rpc(other).is_transient());
+        let decode = TransportErrorKind::custom_str("invalid url");
+        assert!(!Error::Rpc(decode).is_transient());
+    }
+
+    #[test]
+    fn retries_rate_limited_and_failed_api_requests() {
+        let status_error = |status: u16| {
+            let response = axum::http::Response::builder()
+                .status(status)
+                .body("")
+                .unwrap();
+            let error = reqwest::Response::from(response)
+                .error_for_status()
+                .unwrap_err();
+            Error::from(error)
+        };
+        assert!(status_error(429).is_transient());
+        assert!(status_error(503).is_transient());
+        assert!(!status_error(400).is_transient());
+    }
+
+    #[test]
+    fn keeps_the_source_error() {
+        let error = Error::from(csv::Error::from(io::Error::other("disk full")));
+        assert!(matches!(error, Error::Csv(_)));
+        assert!(error.source().is_some());
+        let error = Error::from(prometheus::Error::Msg("duplicate".to_string()));
+        assert!(matches!(error, Error::Prometheus(_)));
+        assert!(Error::Config("bad".to_string()).source().is_none());
+    }
+}
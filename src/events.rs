@@ -0,0 +1,594 @@
+use crate::error::Result;
+use crate::market::{
+    IMorpho::{self, IMorphoEvents},
+    MORPHO_ADDRESS,
+};
+use crate::output::{checksummed, decimal};
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
+use crate::vault::IVault::IVaultEvents;
+use alloy::{
+    eips::BlockNumberOrTag,
+    network::Ethereum,
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder, WsConnect},
+    rpc::types::{Filter, Log},
+    sol_types::SolEventInterface,
+    transports::{http::reqwest::Url, Transport},
+};
+use futures_util::stream::StreamExt;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+// A decoded event with the position of its log in the chain
+#[derive(Clone)]
+pub struct DecodedLog<E> {
+    pub address: Address,
+    pub block_number: Option<u64>,
+    pub block_timestamp: Option<u64>,
+    pub transaction_hash: Option<B256>,
+    pub log_index: Option<u64>,
+    pub event: E,
+}
+
+pub type MorphoLog = DecodedLog<IMorphoEvents>;
+pub type VaultLog = DecodedLog<IVaultEvents>;
+
+// A typed event parameter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Address(#[serde(serialize_with = "checksummed")] Address),
+    Uint(#[serde(serialize_with = "decimal")] U256),
+    Bytes32(B256),
+    Bool(bool),
+    Bytes32List(Vec<B256>),
+    String(String),
+}
+
+// Events of an interface with their name and their parameters, in ABI order
+pub trait EventFields {
+    fn name(&self) -> &'static str;
+    fn fields(&self) -> Vec<(&'static str, FieldValue)>;
+}
+
+impl EventFields for IMorphoEvents {
+    fn name(&self) -> &'static str {
+        morpho_event_name(self)
+    }
+
+    fn fields(&self) -> Vec<(&'static str, FieldValue)> {
+        morpho_event_fields(self)
+    }
+}
+
+impl EventFields for IVaultEvents {
+    fn name(&self) -> &'static str {
+        vault_event_name(self)
+    }
+
+    fn fields(&self) -> Vec<(&'static str, FieldValue)> {
+        vault_event_fields(self)
+    }
+}
+
+// Serialized as a flat object: the log position, the event name and its parameters
+impl<E: EventFields> Serialize for DecodedLog<E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let fields = self.event.fields();
+        let mut map = serializer.serialize_map(Some(6 + fields.len()))?;
+        map.serialize_entry("address", &FieldValue::Address(self.address))?;
+        map.serialize_entry("blockNumber", &self.block_number)?;
+        map.serialize_entry("blockTimestamp", &self.block_timestamp)?;
+        map.serialize_entry("transactionHash", &self.transaction_hash)?;
+        map.serialize_entry("logIndex", &self.log_index)?;
+        map.serialize_entry("event", self.event.name())?;
+        for (name, value) in &fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+// Decodes a log with the events of an interface, e.g. IMorphoEvents or IVaultEvents
+pub fn decode_log<E: SolEventInterface>(log: &Log) -> Result<DecodedLog<E>> {
+    Ok(DecodedLog {
+        address: log.address(),
+        block_number: log.block_number,
+        block_timestamp: log.block_timestamp,
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index,
+        event: E::decode_log(&log.inner, true)?.data,
+    })
+}
+
+pub async fn fetch_events<E: SolEventInterface>(
+    rpc_url: Url,
+    address: Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<DecodedLog<E>>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let filter = Filter::new().address(address);
+
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        from_block,
+        to_block,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+    logs.iter().map(decode_log).collect()
+}
+
+// Logs of `filter` from `from_block` to `to_block`, requested `max_block_range` blocks at a time since
+// providers cap the range of eth_getLogs
+pub async fn get_logs_in_ranges<T, P>(
+    provider: &P,
+    filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+    max_block_range: u64,
+) -> Result<Vec<Log>>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum>,
+{
+    let range = max_block_range.max(1);
+    let mut logs = Vec::new();
+    for from in (from_block..=to_block).step_by(range as usize) {
+        let filter = filter
+            .clone()
+            .from_block(from)
+            .to_block(to_block.min(from + range - 1));
+        logs.extend(provider.get_logs(&filter).await?);
+    }
+    Ok(logs)
+}
+
+// Calls `on_event` for every Morpho event emitted from now on, until the subscription ends
+pub async fn watch_morpho_events<F>(ws_url: &str, mut on_event: F) -> Result<()>
+where
+    F: FnMut(MorphoLog),
+{
+    let provider = ProviderBuilder::new().on_ws(WsConnect::new(ws_url)).await?;
+
+    let filter = Filter::new()
+        .address(MORPHO_ADDRESS)
+        .from_block(BlockNumberOrTag::Latest);
+
+    let sub = provider.subscribe_logs(&filter).await?;
+    let mut stream = sub.into_stream();
+
+    while let Some(log) = stream.next().await {
+        on_event(decode_log(&log)?);
+    }
+    Ok(())
+}
+
+// Same as `watch_morpho_events` for the events of MetaMorpho vaults
+pub async fn watch_vault_events<F>(ws_url: &str, vaults: &[Address], mut on_event: F) -> Result<()>
+where
+    F: FnMut(VaultLog),
+{
+    let provider = ProviderBuilder::new().on_ws(WsConnect::new(ws_url)).await?;
+
+    let filter = Filter::new()
+        .address(vaults.to_vec())
+        .from_block(BlockNumberOrTag::Latest);
+
+    let sub = provider.subscribe_logs(&filter).await?;
+    let mut stream = sub.into_stream();
+
+    while let Some(log) = stream.next().await {
+        on_event(decode_log(&log)?);
+    }
+    Ok(())
+}
+
+// Morpho events on a block range (RPCs usually cap the number of logs per request)
+pub async fn fetch_morpho_events(
+    rpc_url: Url,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<MorphoLog>> {
+    fetch_events(rpc_url, MORPHO_ADDRESS, from_block, to_block).await
+}
+
+pub async fn fetch_vault_events(
+    rpc_url: Url,
+    vault: Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<VaultLog>> {
+    fetch_events(rpc_url, vault, from_block, to_block).await
+}
+
+pub fn morpho_event_name(event: &IMorphoEvents) -> &'static str {
+    match event {
+        IMorphoEvents::AccrueInterest(_) => "AccrueInterest",
+        IMorphoEvents::Borrow(_) => "Borrow",
+        IMorphoEvents::CreateMarket(_) => "CreateMarket",
+        IMorphoEvents::EnableIrm(_) => "EnableIrm",
+        IMorphoEvents::EnableLltv(_) => "EnableLltv",
+        IMorphoEvents::FlashLoan(_) => "FlashLoan",
+        IMorphoEvents::IncrementNonce(_) => "IncrementNonce",
+        IMorphoEvents::Liquidate(_) => "Liquidate",
+        IMorphoEvents::Repay(_) => "Repay",
+        IMorphoEvents::SetAuthorization(_) => "SetAuthorization",
+        IMorphoEvents::SetFee(_) => "SetFee",
+        IMorphoEvents::SetFeeRecipient(_) => "SetFeeRecipient",
+        IMorphoEvents::SetOwner(_) => "SetOwner",
+        IMorphoEvents::Supply(_) => "Supply",
+        IMorphoEvents::SupplyCollateral(_) => "SupplyCollateral",
+        IMorphoEvents::Withdraw(_) => "Withdraw",
+        IMorphoEvents::WithdrawCollateral(_) => "WithdrawCollateral",
+    }
+}
+
+pub fn morpho_event_fields(event: &IMorphoEvents) -> Vec<(&'static str, FieldValue)> {
+    use FieldValue::{Address as A, Bool, Bytes32 as B, Uint as U};
+    match event {
+        IMorphoEvents::AccrueInterest(e) => vec![
+            ("id", B(e.id)),
+            ("prevBorrowRate", U(e.prevBorrowRate)),
+            ("interest", U(e.interest)),
+            ("feeShares", U(e.feeShares)),
+        ],
+        IMorphoEvents::Borrow(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("receiver", A(e.receiver)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+        IMorphoEvents::CreateMarket(e) => vec![
+            ("id", B(e.id)),
+            ("loanToken", A(e.marketParams.loanToken)),
+            ("collateralToken", A(e.marketParams.collateralToken)),
+            ("oracle", A(e.marketParams.oracle)),
+            ("irm", A(e.marketParams.irm)),
+            ("lltv", U(e.marketParams.lltv)),
+        ],
+        IMorphoEvents::EnableIrm(e) => vec![("irm", A(e.irm))],
+        IMorphoEvents::EnableLltv(e) => vec![("lltv", U(e.lltv))],
+        IMorphoEvents::FlashLoan(e) => vec![
+            ("caller", A(e.caller)),
+            ("token", A(e.token)),
+            ("assets", U(e.assets)),
+        ],
+        IMorphoEvents::IncrementNonce(e) => vec![
+            ("caller", A(e.caller)),
+            ("authorizer", A(e.authorizer)),
+            ("usedNonce", U(e.usedNonce)),
+        ],
+        IMorphoEvents::Liquidate(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("borrower", A(e.borrower)),
+            ("repaidAssets", U(e.repaidAssets)),
+            ("repaidShares", U(e.repaidShares)),
+            ("seizedAssets", U(e.seizedAssets)),
+            ("badDebtAssets", U(e.badDebtAssets)),
+            ("badDebtShares", U(e.badDebtShares)),
+        ],
+        IMorphoEvents::Repay(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+        IMorphoEvents::SetAuthorization(e) => vec![
+            ("caller", A(e.caller)),
+            ("authorizer", A(e.authorizer)),
+            ("authorized", A(e.authorized)),
+            ("newIsAuthorized", Bool(e.newIsAuthorized)),
+        ],
+        IMorphoEvents::SetFee(e) => vec![("id", B(e.id)), ("newFee", U(e.newFee))],
+        IMorphoEvents::SetFeeRecipient(e) => vec![("newFeeRecipient", A(e.newFeeRecipient))],
+        IMorphoEvents::SetOwner(e) => vec![("newOwner", A(e.newOwner))],
+        IMorphoEvents::Supply(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+        IMorphoEvents::SupplyCollateral(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("assets", U(e.assets)),
+        ],
+        IMorphoEvents::Withdraw(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("receiver", A(e.receiver)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+        IMorphoEvents::WithdrawCollateral(e) => vec![
+            ("id", B(e.id)),
+            ("caller", A(e.caller)),
+            ("onBehalf", A(e.onBehalf)),
+            ("receiver", A(e.receiver)),
+            ("assets", U(e.assets)),
+        ],
+    }
+}
+
+// Amount fields of a Morpho event, in loan token units and in collateral token units
+pub fn morpho_event_amount_fields(
+    event: &IMorphoEvents,
+) -> (&'static [&'static str], &'static [&'static str]) {
+    match event {
+        IMorphoEvents::AccrueInterest(_) => (&["interest"], &[]),
+        IMorphoEvents::Borrow(_)
+        | IMorphoEvents::Repay(_)
+        | IMorphoEvents::Supply(_)
+        | IMorphoEvents::Withdraw(_) => (&["assets"], &[]),
+        IMorphoEvents::SupplyCollateral(_) | IMorphoEvents::WithdrawCollateral(_) => {
+            (&[], &["assets"])
+        }
+        IMorphoEvents::Liquidate(_) => (&["repaidAssets", "badDebtAssets"], &["seizedAssets"]),
+        _ => (&[], &[]),
+    }
+}
+
+// Market of a Morpho event, None for the events which are not specific to a market
+pub fn morpho_event_market_id(event: &IMorphoEvents) -> Option<B256> {
+    match event {
+        IMorphoEvents::AccrueInterest(e) => Some(e.id),
+        IMorphoEvents::Borrow(e) => Some(e.id),
+        IMorphoEvents::CreateMarket(e) => Some(e.id),
+        IMorphoEvents::Liquidate(e) => Some(e.id),
+        IMorphoEvents::Repay(e) => Some(e.id),
+        IMorphoEvents::SetFee(e) => Some(e.id),
+        IMorphoEvents::Supply(e) => Some(e.id),
+        IMorphoEvents::SupplyCollateral(e) => Some(e.id),
+        IMorphoEvents::Withdraw(e) => Some(e.id),
+        IMorphoEvents::WithdrawCollateral(e) => Some(e.id),
+        _ => None,
+    }
+}
+
+// Account whose supply shares a Morpho event changes, e.g. a vault supplying or withdrawing
+pub fn morpho_event_supplier(event: &IMorphoEvents) -> Option<Address> {
+    match event {
+        IMorphoEvents::Supply(e) => Some(e.onBehalf),
+        IMorphoEvents::Withdraw(e) => Some(e.onBehalf),
+        _ => None,
+    }
+}
+
+// Human readable description of the main Morpho events
+pub fn describe_morpho_event(event: &IMorphoEvents) -> Option<String> {
+    Some(match event {
+        IMorphoEvents::CreateMarket(IMorpho::CreateMarket { id, marketParams }) => format!(
+            "Market with id {:#32x} was created with params: {:#20x}, {:#20x}, {}, {:#20x}, {:#20x}",
+            id,
+            marketParams.collateralToken,
+            marketParams.loanToken,
+            marketParams.lltv,
+            marketParams.oracle,
+            marketParams.irm
+        ),
+        IMorphoEvents::Supply(e) => format!(
+            "User {:#20x} supplied {} assets on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::Withdraw(e) => format!(
+            "User {:#20x} withdrew {} assets on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::Borrow(e) => format!(
+            "User {:#20x} borrowed {} assets on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::Repay(e) => format!(
+            "User {:#20x} repaid {} assets on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::SupplyCollateral(e) => format!(
+            "User {:#20x} supplied {} assets as collateral on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::WithdrawCollateral(e) => format!(
+            "User {:#20x} withdrew {} assets as collateral on market {:#32x}",
+            e.onBehalf, e.assets, e.id
+        ),
+        IMorphoEvents::Liquidate(e) => format!(
+            "User {:#20x} was liquidated by user {:#20x} on market {:#32x}",
+            e.borrower, e.caller, e.id
+        ),
+        _ => return None,
+    })
+}
+
+pub fn vault_event_name(event: &IVaultEvents) -> &'static str {
+    match event {
+        IVaultEvents::AccrueInterest(_) => "AccrueInterest",
+        IVaultEvents::Approval(_) => "Approval",
+        IVaultEvents::Deposit(_) => "Deposit",
+        IVaultEvents::EIP712DomainChanged(_) => "EIP712DomainChanged",
+        IVaultEvents::OwnershipTransferStarted(_) => "OwnershipTransferStarted",
+        IVaultEvents::OwnershipTransferred(_) => "OwnershipTransferred",
+        IVaultEvents::ReallocateSupply(_) => "ReallocateSupply",
+        IVaultEvents::ReallocateWithdraw(_) => "ReallocateWithdraw",
+        IVaultEvents::RevokePendingCap(_) => "RevokePendingCap",
+        IVaultEvents::RevokePendingGuardian(_) => "RevokePendingGuardian",
+        IVaultEvents::RevokePendingMarketRemoval(_) => "RevokePendingMarketRemoval",
+        IVaultEvents::RevokePendingTimelock(_) => "RevokePendingTimelock",
+        IVaultEvents::SetCap(_) => "SetCap",
+        IVaultEvents::SetCurator(_) => "SetCurator",
+        IVaultEvents::SetFee(_) => "SetFee",
+        IVaultEvents::SetFeeRecipient(_) => "SetFeeRecipient",
+        IVaultEvents::SetGuardian(_) => "SetGuardian",
+        IVaultEvents::SetIsAllocator(_) => "SetIsAllocator",
+        IVaultEvents::SetSkimRecipient(_) => "SetSkimRecipient",
+        IVaultEvents::SetSupplyQueue(_) => "SetSupplyQueue",
+        IVaultEvents::SetTimelock(_) => "SetTimelock",
+        IVaultEvents::SetWithdrawQueue(_) => "SetWithdrawQueue",
+        IVaultEvents::Skim(_) => "Skim",
+        IVaultEvents::SubmitCap(_) => "SubmitCap",
+        IVaultEvents::SubmitGuardian(_) => "SubmitGuardian",
+        IVaultEvents::SubmitMarketRemoval(_) => "SubmitMarketRemoval",
+        IVaultEvents::SubmitTimelock(_) => "SubmitTimelock",
+        IVaultEvents::Transfer(_) => "Transfer",
+        IVaultEvents::UpdateLastTotalAssets(_) => "UpdateLastTotalAssets",
+        IVaultEvents::Withdraw(_) => "Withdraw",
+    }
+}
+
+pub fn vault_event_fields(event: &IVaultEvents) -> Vec<(&'static str, FieldValue)> {
+    use FieldValue::{Address as A, Bool, Bytes32 as B, Bytes32List, Uint as U};
+    match event {
+        IVaultEvents::AccrueInterest(e) => vec![
+            ("newTotalAssets", U(e.newTotalAssets)),
+            ("feeShares", U(e.feeShares)),
+        ],
+        IVaultEvents::Approval(e) => vec![
+            ("owner", A(e.owner)),
+            ("spender", A(e.spender)),
+            ("value", U(e.value)),
+        ],
+        IVaultEvents::Deposit(e) => vec![
+            ("sender", A(e.sender)),
+            ("owner", A(e.owner)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+        IVaultEvents::EIP712DomainChanged(_) => vec![],
+        IVaultEvents::OwnershipTransferStarted(e) => vec![
+            ("previousOwner", A(e.previousOwner)),
+            ("newOwner", A(e.newOwner)),
+        ],
+        IVaultEvents::OwnershipTransferred(e) => vec![
+            ("previousOwner", A(e.previousOwner)),
+            ("newOwner", A(e.newOwner)),
+        ],
+        IVaultEvents::ReallocateSupply(e) => vec![
+            ("caller", A(e.caller)),
+            ("id", B(e.id)),
+            ("suppliedAssets", U(e.suppliedAssets)),
+            ("suppliedShares", U(e.suppliedShares)),
+        ],
+        IVaultEvents::ReallocateWithdraw(e) => vec![
+            ("caller", A(e.caller)),
+            ("id", B(e.id)),
+            ("withdrawnAssets", U(e.withdrawnAssets)),
+            ("withdrawnShares", U(e.withdrawnShares)),
+        ],
+        IVaultEvents::RevokePendingCap(e) => vec![("caller", A(e.caller)), ("id", B(e.id))],
+        IVaultEvents::RevokePendingGuardian(e) => vec![("caller", A(e.caller))],
+        IVaultEvents::RevokePendingMarketRemoval(e) => {
+            vec![("caller", A(e.caller)), ("id", B(e.id))]
+        }
+        IVaultEvents::RevokePendingTimelock(e) => vec![("caller", A(e.caller))],
+        IVaultEvents::SetCap(e) => {
+            vec![("caller", A(e.caller)), ("id", B(e.id)), ("cap", U(e.cap))]
+        }
+        IVaultEvents::SetCurator(e) => vec![("newCurator", A(e.newCurator))],
+        IVaultEvents::SetFee(e) => vec![("caller", A(e.caller)), ("newFee", U(e.newFee))],
+        IVaultEvents::SetFeeRecipient(e) => vec![("newFeeRecipient", A(e.newFeeRecipient))],
+        IVaultEvents::SetGuardian(e) => vec![("caller", A(e.caller)), ("guardian", A(e.guardian))],
+        IVaultEvents::SetIsAllocator(e) => vec![
+            ("allocator", A(e.allocator)),
+            ("isAllocator", Bool(e.isAllocator)),
+        ],
+        IVaultEvents::SetSkimRecipient(e) => vec![("newSkimRecipient", A(e.newSkimRecipient))],
+        IVaultEvents::SetSupplyQueue(e) => vec![
+            ("caller", A(e.caller)),
+            ("newSupplyQueue", Bytes32List(e.newSupplyQueue.clone())),
+        ],
+        IVaultEvents::SetTimelock(e) => {
+            vec![("caller", A(e.caller)), ("newTimelock", U(e.newTimelock))]
+        }
+        IVaultEvents::SetWithdrawQueue(e) => vec![
+            ("caller", A(e.caller)),
+            ("newWithdrawQueue", Bytes32List(e.newWithdrawQueue.clone())),
+        ],
+        IVaultEvents::Skim(e) => vec![
+            ("caller", A(e.caller)),
+            ("token", A(e.token)),
+            ("amount", U(e.amount)),
+        ],
+        IVaultEvents::SubmitCap(e) => {
+            vec![("caller", A(e.caller)), ("id", B(e.id)), ("cap", U(e.cap))]
+        }
+        IVaultEvents::SubmitGuardian(e) => vec![("newGuardian", A(e.newGuardian))],
+        IVaultEvents::SubmitMarketRemoval(e) => vec![("caller", A(e.caller)), ("id", B(e.id))],
+        IVaultEvents::SubmitTimelock(e) => vec![("newTimelock", U(e.newTimelock))],
+        IVaultEvents::Transfer(e) => {
+            vec![("from", A(e.from)), ("to", A(e.to)), ("value", U(e.value))]
+        }
+        IVaultEvents::UpdateLastTotalAssets(e) => {
+            vec![("updatedTotalAssets", U(e.updatedTotalAssets))]
+        }
+        IVaultEvents::Withdraw(e) => vec![
+            ("sender", A(e.sender)),
+            ("receiver", A(e.receiver)),
+            ("owner", A(e.owner)),
+            ("assets", U(e.assets)),
+            ("shares", U(e.shares)),
+        ],
+    }
+}
+
+// Market of a vault event, None for the events which are not specific to a market
+pub fn vault_event_market_id(event: &IVaultEvents) -> Option<B256> {
+    match event {
+        IVaultEvents::ReallocateSupply(e) => Some(e.id),
+        IVaultEvents::ReallocateWithdraw(e) => Some(e.id),
+        IVaultEvents::RevokePendingCap(e) => Some(e.id),
+        IVaultEvents::RevokePendingMarketRemoval(e) => Some(e.id),
+        IVaultEvents::SetCap(e) => Some(e.id),
+        IVaultEvents::SubmitCap(e) => Some(e.id),
+        IVaultEvents::SubmitMarketRemoval(e) => Some(e.id),
+        _ => None,
+    }
+}
+
+// Amount fields of a vault event, in underlying asset units and in vault share units
+pub fn vault_event_amount_fields(
+    event: &IVaultEvents,
+) -> (&'static [&'static str], &'static [&'static str]) {
+    match event {
+        IVaultEvents::AccrueInterest(_) => (&["newTotalAssets"], &["feeShares"]),
+        IVaultEvents::Approval(_) | IVaultEvents::Transfer(_) => (&[], &["value"]),
+        IVaultEvents::Deposit(_) | IVaultEvents::Withdraw(_) => (&["assets"], &["shares"]),
+        IVaultEvents::ReallocateSupply(_) => (&["suppliedAssets"], &[]),
+        IVaultEvents::ReallocateWithdraw(_) => (&["withdrawnAssets"], &[]),
+        IVaultEvents::SetCap(_) | IVaultEvents::SubmitCap(_) => (&["cap"], &[]),
+        IVaultEvents::UpdateLastTotalAssets(_) => (&["updatedTotalAssets"], &[]),
+        _ => (&[], &[]),
+    }
+}
+
+// Human readable description of the vault deposits, withdrawals, transfers and interest accruals
+pub fn describe_vault_event(event: &IVaultEvents) -> Option<String> {
+    Some(match event {
+        IVaultEvents::Deposit(e) => format!(
+            "User {:#20x} deposited {} assets for {} shares",
+            e.owner, e.assets, e.shares
+        ),
+        IVaultEvents::Withdraw(e) => format!(
+            "User {:#20x} withdrew {} assets for {} shares",
+            e.owner, e.assets, e.shares
+        ),
+        IVaultEvents::Transfer(e) => format!(
+            "User {:#20x} transfered {} shares to user {:#20x}",
+            e.from, e.value, e.to
+        ),
+        IVaultEvents::UpdateLastTotalAssets(e) => {
+            format!("Vault updated its total assets to {}", e.updatedTotalAssets)
+        }
+        IVaultEvents::AccrueInterest(e) => format!(
+            "Vault accrued interest: total assets {}, {} fee shares minted",
+            e.newTotalAssets, e.feeShares
+        ),
+        _ => return None,
+    })
+}
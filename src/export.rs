@@ -0,0 +1,435 @@
+use crate::error::{Error, Result};
+use crate::events::FieldValue;
+use crate::indexer::{ContractKind, EventQuery, IndexedLog, Indexer};
+use alloy::rpc::types::Log;
+use arrow_array::{
+    builder::{
+        BooleanBuilder, FixedSizeBinaryBuilder, ListBuilder, StringBuilder, TimestampSecondBuilder,
+        UInt64Builder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{Field, Schema};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Encoding of the uint256 event parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UintEncoding {
+    // Decimal strings, cast with `::HUGEINT` or `::DOUBLE` in DuckDB
+    Decimal,
+    // 32 bytes big-endian, sortable and half the size
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    // Blocks per file, partitions start at multiples of it
+    pub partition_blocks: u64,
+    pub uint_encoding: UintEncoding,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            // About two weeks of mainnet blocks
+            partition_blocks: 100_000,
+            uint_encoding: UintEncoding::Decimal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedFile {
+    pub path: PathBuf,
+    pub kind: ContractKind,
+    pub event: &'static str,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub rows: usize,
+}
+
+// Writes the indexed events as Parquet files, one per contract kind, event and block range:
+// `<dir>/kind=morpho/event=Supply/blocks=21000000-21099999.parquet`. The directory names are Hive
+// partitions, e.g. `read_parquet('<dir>/kind=morpho/event=Supply/*.parquet')` in DuckDB.
+// Every file holds all the events of its range, so `from_block` must start a partition and `to_block`
+// end one (or reach the last indexed block). The files of the exported ranges are replaced, including
+// those of events no longer indexed, so a range can be refreshed after a reorg.
+pub fn export_events(
+    indexer: &Indexer,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    dir: impl AsRef<Path>,
+    options: &ExportOptions,
+) -> Result<Vec<ExportedFile>> {
+    let Some((first_block, last_block)) = indexer.event_block_range()? else {
+        return Ok(Vec::new());
+    };
+    let partition_blocks = options.partition_blocks.max(1);
+    if from_block.is_some_and(|block| block % partition_blocks != 0) {
+        return Err(Error::Config(format!(
+            "--from must be a multiple of the {} blocks partitions",
+            partition_blocks
+        )));
+    }
+    if to_block.is_some_and(|block| block < last_block && (block + 1) % partition_blocks != 0) {
+        return Err(Error::Config(format!(
+            "--to must end a {} blocks partition or reach the last indexed block {}",
+            partition_blocks, last_block
+        )));
+    }
+    let from_block = from_block.unwrap_or(first_block).max(first_block);
+    let to_block = to_block.unwrap_or(last_block).min(last_block);
+    if from_block > to_block {
+        return Ok(Vec::new());
+    }
+
+    let first_start = from_block - from_block % partition_blocks;
+    let last_end = (to_block - to_block % partition_blocks).saturating_add(partition_blocks - 1);
+    remove_exported_files(dir.as_ref(), first_start, last_end)?;
+
+    // One partition is read at a time, so the whole history is never held in memory
+    let mut files = Vec::new();
+    let mut start = first_start;
+    while start <= to_block {
+        let end = start.saturating_add(partition_blocks - 1);
+        let logs = indexer.logs(&EventQuery {
+            from_block: Some(start),
+            to_block: Some(end.min(to_block)),
+            ..Default::default()
+        })?;
+
+        let mut partitions: BTreeMap<(&'static str, &'static str), Partition> = BTreeMap::new();
+        for (kind, log) in &logs {
+            let event = IndexedLog::decode(*kind, log)?;
+            let fields = event.field_values();
+            partitions
+                .entry((kind.as_str(), event.name()))
+                .or_insert_with(|| Partition::new(*kind, event.name(), &fields, options))
+                .push(log, fields)?;
+        }
+        for partition in partitions.into_values() {
+            files.push(partition.write(dir.as_ref(), start, end)?);
+        }
+
+        start = match end.checked_add(1) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(files)
+}
+
+// Removes the files of the partitions in `from_block..=to_block`. Files of another partition size
+// that straddle the range are refused, rewriting only part of them would duplicate events.
+fn remove_exported_files(dir: &Path, from_block: u64, to_block: u64) -> Result<()> {
+    let mut stale = Vec::new();
+    for kind_dir in partition_dirs(dir, "kind=")? {
+        for event_dir in partition_dirs(&kind_dir, "event=")? {
+            for entry in fs::read_dir(&event_dir)? {
+                let path = entry?.path();
+                let Some((start, end)) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix("blocks="))
+                    .and_then(|name| name.strip_suffix(".parquet"))
+                    .and_then(|range| range.split_once('-'))
+                    .and_then(|(start, end)| {
+                        Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?))
+                    })
+                else {
+                    continue;
+                };
+                if end < from_block || start > to_block {
+                    continue;
+                }
+                if start < from_block || end > to_block {
+                    return Err(Error::Config(format!(
+                        "{} was exported with another partition size",
+                        path.display()
+                    )));
+                }
+                stale.push(path);
+            }
+        }
+    }
+    for path in stale {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn partition_dirs(dir: &Path, prefix: &str) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_partition = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(prefix));
+        if is_partition && path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+// Arrow builder of an event parameter, typed after its ABI type
+enum Column {
+    // Addresses (checksummed), hashes (hex), strings and decimal uints
+    Utf8(StringBuilder),
+    Uint(FixedSizeBinaryBuilder),
+    Bool(BooleanBuilder),
+    Bytes32List(ListBuilder<StringBuilder>),
+}
+
+impl Column {
+    fn new(value: &FieldValue, uint_encoding: UintEncoding) -> Self {
+        match (value, uint_encoding) {
+            (FieldValue::Uint(_), UintEncoding::Binary) => {
+                Column::Uint(FixedSizeBinaryBuilder::new(32))
+            }
+            (FieldValue::Bool(_), _) => Column::Bool(BooleanBuilder::new()),
+            (FieldValue::Bytes32List(_), _) => {
+                Column::Bytes32List(ListBuilder::new(StringBuilder::new()))
+            }
+            _ => Column::Utf8(StringBuilder::new()),
+        }
+    }
+
+    fn push(&mut self, value: FieldValue) -> Result<()> {
+        match (self, value) {
+            (Column::Utf8(builder), FieldValue::Address(address)) => {
+                builder.append_value(address.to_checksum(None))
+            }
+            (Column::Utf8(builder), FieldValue::Uint(value)) => {
+                builder.append_value(value.to_string())
+            }
+            (Column::Utf8(builder), FieldValue::Bytes32(value)) => {
+                builder.append_value(value.to_string())
+            }
+            (Column::Utf8(builder), FieldValue::String(value)) => builder.append_value(value),
+            (Column::Uint(builder), FieldValue::Uint(value)) => {
+                builder.append_value(value.to_be_bytes::<32>())?
+            }
+            (Column::Bool(builder), FieldValue::Bool(value)) => builder.append_value(value),
+            (Column::Bytes32List(builder), FieldValue::Bytes32List(values)) => {
+                for value in values {
+                    builder.values().append_value(value.to_string());
+                }
+                builder.append(true);
+            }
+            (_, value) => {
+                return Err(Error::AbiDecode(format!(
+                    "unexpected {:?} in an exported column",
+                    value
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Utf8(builder) => Arc::new(builder.finish()),
+            Column::Uint(builder) => Arc::new(builder.finish()),
+            Column::Bool(builder) => Arc::new(builder.finish()),
+            Column::Bytes32List(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+// Rows of one event in one block range: the log position then the event parameters
+struct Partition {
+    kind: ContractKind,
+    event: &'static str,
+    address: StringBuilder,
+    block_number: UInt64Builder,
+    block_hash: StringBuilder,
+    block_timestamp: TimestampSecondBuilder,
+    transaction_hash: StringBuilder,
+    log_index: UInt64Builder,
+    fields: Vec<(&'static str, Column)>,
+    rows: usize,
+}
+
+impl Partition {
+    fn new(
+        kind: ContractKind,
+        event: &'static str,
+        fields: &[(&'static str, FieldValue)],
+        options: &ExportOptions,
+    ) -> Self {
+        Partition {
+            kind,
+            event,
+            address: StringBuilder::new(),
+            block_number: UInt64Builder::new(),
+            block_hash: StringBuilder::new(),
+            block_timestamp: TimestampSecondBuilder::new().with_timezone("UTC"),
+            transaction_hash: StringBuilder::new(),
+            log_index: UInt64Builder::new(),
+            fields: fields
+                .iter()
+                .map(|(name, value)| (*name, Column::new(value, options.uint_encoding)))
+                .collect(),
+            rows: 0,
+        }
+    }
+
+    fn push(&mut self, log: &Log, fields: Vec<(&'static str, FieldValue)>) -> Result<()> {
+        self.address.append_value(log.address().to_checksum(None));
+        self.block_number.append_option(log.block_number);
+        self.block_hash
+            .append_option(log.block_hash.map(|hash| hash.to_string()));
+        self.block_timestamp.append_option(
+            log.block_timestamp
+                .map(|timestamp| timestamp.try_into().unwrap_or(i64::MAX)),
+        );
+        self.transaction_hash
+            .append_option(log.transaction_hash.map(|hash| hash.to_string()));
+        self.log_index.append_option(log.log_index);
+        for ((_, column), (_, value)) in self.fields.iter_mut().zip(fields) {
+            column.push(value)?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn write(mut self, dir: &Path, from_block: u64, to_block: u64) -> Result<ExportedFile> {
+        let mut columns: Vec<(&str, ArrayRef, bool)> = vec![
+            ("address", Arc::new(self.address.finish()), false),
+            ("blockNumber", Arc::new(self.block_number.finish()), false),
+            ("blockHash", Arc::new(self.block_hash.finish()), false),
+            (
+                "blockTimestamp",
+                Arc::new(self.block_timestamp.finish()),
+                true,
+            ),
+            (
+                "transactionHash",
+                Arc::new(self.transaction_hash.finish()),
+                true,
+            ),
+            ("logIndex", Arc::new(self.log_index.finish()), false),
+        ];
+        for (name, column) in &mut self.fields {
+            columns.push((*name, column.finish(), false));
+        }
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(name, array, nullable)| {
+                    Field::new(*name, array.data_type().clone(), *nullable)
+                })
+                .collect::<Vec<_>>(),
+        ));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            columns.into_iter().map(|(_, array, _)| array).collect(),
+        )?;
+
+        let partition_dir = dir
+            .join(format!("kind={}", self.kind.as_str()))
+            .join(format!("event={}", self.event));
+        fs::create_dir_all(&partition_dir)?;
+        let path = partition_dir.join(format!("blocks={}-{}.parquet", from_block, to_block));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut writer = ArrowWriter::try_new(fs::File::create(&path)?, schema, Some(properties))?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok(ExportedFile {
+            path,
+            kind: self.kind,
+            event: self.event,
+            from_block,
+            to_block,
+            rows: self.rows,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Recording;
+
+    fn indexed_recording() -> Indexer {
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        let recording = Recording::load("data/recordings/synthetic.json").unwrap();
+        indexer.replay(&recording).unwrap();
+        indexer
+    }
+
+    fn export_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("morpho-export-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn replaces_the_files_of_the_exported_partitions() {
+        let indexer = indexed_recording();
+        let dir = export_dir("replace");
+        let options = ExportOptions {
+            partition_blocks: 10,
+            ..Default::default()
+        };
+
+        let files = export_events(&indexer, None, None, &dir, &options).unwrap();
+        let rows: usize = files.iter().map(|file| file.rows).sum();
+        assert_eq!(rows, indexer.events(&EventQuery::default()).unwrap().len());
+
+        // An event file of the range that is no longer indexed is removed, earlier ranges are kept
+        let stale = dir.join("kind=morpho/event=Withdraw/blocks=21000010-21000019.parquet");
+        let kept = dir.join("kind=morpho/event=Borrow/blocks=21000000-21000009.parquet");
+        fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        fs::write(&stale, b"").unwrap();
+        let files = export_events(&indexer, Some(21_000_010), None, &dir, &options).unwrap();
+        assert!(files.iter().all(|file| file.from_block == 21_000_010));
+        assert!(!stale.exists());
+        assert!(kept.exists());
+
+        // A file of a larger partition would be partly rewritten
+        let straddling = dir.join("kind=morpho/event=Borrow/blocks=21000000-21000099.parquet");
+        fs::write(&straddling, b"").unwrap();
+        let result = export_events(&indexer, None, None, &dir, &options);
+        assert!(matches!(result, Err(Error::Config(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_ranges_that_clip_a_partition() {
+        let indexer = indexed_recording();
+        let dir = export_dir("clip");
+        let options = ExportOptions {
+            partition_blocks: 10,
+            ..Default::default()
+        };
+
+        let result = export_events(&indexer, Some(21_000_005), None, &dir, &options);
+        assert!(matches!(result, Err(Error::Config(_))));
+        let result = export_events(&indexer, None, Some(21_000_012), &dir, &options);
+        assert!(matches!(result, Err(Error::Config(_))));
+        assert!(!dir.exists());
+
+        // Past the last indexed block the last partition is complete
+        let files = export_events(&indexer, None, Some(21_000_100), &dir, &options).unwrap();
+        assert!(files.iter().any(|file| file.to_block == 21_000_019));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,520 @@
+use crate::error::Result;
+use crate::events::get_logs_in_ranges;
+use crate::output::{checksummed, decimal};
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
+use crate::vault::IVault;
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "marketId")]
+pub enum GovernanceAction {
+    Cap(B256),
+    Timelock,
+    Guardian,
+    MarketRemoval(B256),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ActionStatus {
+    Submitted,
+    Executed,
+    Revoked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum ActionValue {
+    Amount(#[serde(serialize_with = "decimal")] U256),
+    Address(#[serde(serialize_with = "checksummed")] Address),
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GovernanceEvent {
+    pub block_number: Option<u64>,
+    pub transaction_hash: Option<B256>,
+    pub action: GovernanceAction,
+    pub status: ActionStatus,
+    pub value: ActionValue,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingAction {
+    #[serde(serialize_with = "checksummed")]
+    pub vault: Address,
+    pub action: GovernanceAction,
+    pub value: ActionValue,
+    // Timestamp from which the action can be accepted
+    pub valid_at: u64,
+}
+
+pub const GOVERNANCE_EVENTS: [B256; 12] = [
+    IVault::SubmitCap::SIGNATURE_HASH,
+    IVault::SetCap::SIGNATURE_HASH,
+    IVault::RevokePendingCap::SIGNATURE_HASH,
+    IVault::SubmitTimelock::SIGNATURE_HASH,
+    IVault::SetTimelock::SIGNATURE_HASH,
+    IVault::RevokePendingTimelock::SIGNATURE_HASH,
+    IVault::SubmitGuardian::SIGNATURE_HASH,
+    IVault::SetGuardian::SIGNATURE_HASH,
+    IVault::RevokePendingGuardian::SIGNATURE_HASH,
+    IVault::SubmitMarketRemoval::SIGNATURE_HASH,
+    IVault::RevokePendingMarketRemoval::SIGNATURE_HASH,
+    // Accepting a market removal is done through the withdraw queue update
+    IVault::SetWithdrawQueue::SIGNATURE_HASH,
+];
+
+pub fn decode_governance_log(log: &Log) -> Result<Option<GovernanceEvent>> {
+    let (action, status, value) = match log.topic0() {
+        Some(&IVault::SubmitCap::SIGNATURE_HASH) => {
+            let IVault::SubmitCap { caller: _, id, cap } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Cap(id),
+                ActionStatus::Submitted,
+                ActionValue::Amount(cap),
+            )
+        }
+        Some(&IVault::SetCap::SIGNATURE_HASH) => {
+            let IVault::SetCap { caller: _, id, cap } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Cap(id),
+                ActionStatus::Executed,
+                ActionValue::Amount(cap),
+            )
+        }
+        Some(&IVault::RevokePendingCap::SIGNATURE_HASH) => {
+            let IVault::RevokePendingCap { caller: _, id } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Cap(id),
+                ActionStatus::Revoked,
+                ActionValue::None,
+            )
+        }
+        Some(&IVault::SubmitTimelock::SIGNATURE_HASH) => {
+            let IVault::SubmitTimelock { newTimelock } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Timelock,
+                ActionStatus::Submitted,
+                ActionValue::Amount(newTimelock),
+            )
+        }
+        Some(&IVault::SetTimelock::SIGNATURE_HASH) => {
+            let IVault::SetTimelock {
+                caller: _,
+                newTimelock,
+            } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Timelock,
+                ActionStatus::Executed,
+                ActionValue::Amount(newTimelock),
+            )
+        }
+        Some(&IVault::RevokePendingTimelock::SIGNATURE_HASH) => (
+            GovernanceAction::Timelock,
+            ActionStatus::Revoked,
+            ActionValue::None,
+        ),
+        Some(&IVault::SubmitGuardian::SIGNATURE_HASH) => {
+            let IVault::SubmitGuardian { newGuardian } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Guardian,
+                ActionStatus::Submitted,
+                ActionValue::Address(newGuardian),
+            )
+        }
+        Some(&IVault::SetGuardian::SIGNATURE_HASH) => {
+            let IVault::SetGuardian {
+                caller: _,
+                guardian,
+            } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::Guardian,
+                ActionStatus::Executed,
+                ActionValue::Address(guardian),
+            )
+        }
+        Some(&IVault::RevokePendingGuardian::SIGNATURE_HASH) => (
+            GovernanceAction::Guardian,
+            ActionStatus::Revoked,
+            ActionValue::None,
+        ),
+        Some(&IVault::SubmitMarketRemoval::SIGNATURE_HASH) => {
+            let IVault::SubmitMarketRemoval { caller: _, id } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::MarketRemoval(id),
+                ActionStatus::Submitted,
+                ActionValue::None,
+            )
+        }
+        Some(&IVault::RevokePendingMarketRemoval::SIGNATURE_HASH) => {
+            let IVault::RevokePendingMarketRemoval { caller: _, id } = log.log_decode()?.inner.data;
+            (
+                GovernanceAction::MarketRemoval(id),
+                ActionStatus::Revoked,
+                ActionValue::None,
+            )
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(GovernanceEvent {
+        block_number: log.block_number,
+        transaction_hash: log.transaction_hash,
+        action,
+        status,
+        value,
+    }))
+}
+
+// Governance events of a vault, scanned up to `next_block` and extended by `update_governance_history`
+#[derive(Debug, Clone)]
+pub struct GovernanceHistory {
+    pub events: Vec<GovernanceEvent>,
+    // First block not scanned yet
+    pub next_block: u64,
+    // Removals submitted and neither executed nor revoked yet
+    removals: BTreeSet<B256>,
+}
+
+impl GovernanceHistory {
+    pub fn new(from_block: u64) -> Self {
+        GovernanceHistory {
+            events: Vec::new(),
+            next_block: from_block,
+            removals: BTreeSet::new(),
+        }
+    }
+
+    fn push_log(&mut self, log: &Log) -> Result<()> {
+        if let Some(event) = decode_governance_log(log)? {
+            match (event.action, event.status) {
+                (GovernanceAction::MarketRemoval(id), ActionStatus::Submitted) => {
+                    self.removals.insert(id);
+                }
+                (GovernanceAction::MarketRemoval(id), ActionStatus::Revoked) => {
+                    self.removals.remove(&id);
+                }
+                _ => (),
+            }
+            self.events.push(event);
+        } else if log.topic0() == Some(&IVault::SetWithdrawQueue::SIGNATURE_HASH) {
+            // A pending removal is executed when its market leaves the withdraw queue
+            let IVault::SetWithdrawQueue {
+                caller: _,
+                newWithdrawQueue,
+            } = log.log_decode()?.inner.data;
+            let removed: Vec<B256> = self
+                .removals
+                .iter()
+                .filter(|id| !newWithdrawQueue.contains(*id))
+                .copied()
+                .collect();
+            for id in removed {
+                self.removals.remove(&id);
+                self.events.push(GovernanceEvent {
+                    block_number: log.block_number,
+                    transaction_hash: log.transaction_hash,
+                    action: GovernanceAction::MarketRemoval(id),
+                    status: ActionStatus::Executed,
+                    value: ActionValue::None,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Adds the governance events emitted since the last update, scanning the logs in ranges. The history
+// is left as it was if a request fails.
+pub async fn update_governance_history(
+    rpc_url: Url,
+    vault_address: Address,
+    history: &mut GovernanceHistory,
+) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let latest = provider.get_block_number().await?;
+    if latest < history.next_block {
+        return Ok(());
+    }
+
+    let filter = Filter::new()
+        .address(vault_address)
+        .events(GOVERNANCE_EVENTS);
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        history.next_block,
+        latest,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+    let mut updated = history.clone();
+    for log in &logs {
+        updated.push_log(log)?;
+    }
+    updated.next_block = latest + 1;
+    *history = updated;
+    Ok(())
+}
+
+pub async fn retrieve_governance_history(
+    rpc_url: Url,
+    vault_address: Address,
+    from_block: u64,
+) -> Result<Vec<GovernanceEvent>> {
+    let mut history = GovernanceHistory::new(from_block);
+    update_governance_history(rpc_url, vault_address, &mut history).await?;
+    Ok(history.events)
+}
+
+pub async fn retrieve_pending_actions(
+    rpc_url: Url,
+    vault_address: Address,
+    history: &[GovernanceEvent],
+) -> Result<Vec<PendingAction>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let vault = IVault::new(vault_address, provider.clone());
+
+    let mut pending = Vec::new();
+
+    let pending_timelock = vault.pendingTimelock().call().await?;
+    if pending_timelock.validAt != 0 {
+        pending.push(PendingAction {
+            vault: vault_address,
+            action: GovernanceAction::Timelock,
+            value: ActionValue::Amount(U256::from(pending_timelock.value)),
+            valid_at: pending_timelock.validAt,
+        });
+    }
+
+    let pending_guardian = vault.pendingGuardian().call().await?;
+    if pending_guardian.validAt != 0 {
+        pending.push(PendingAction {
+            vault: vault_address,
+            action: GovernanceAction::Guardian,
+            value: ActionValue::Address(pending_guardian.value),
+            valid_at: pending_guardian.validAt,
+        });
+    }
+
+    // Pending caps and removals are stored per market, so we only check the markets seen in the history
+    let mut cap_ids = BTreeSet::new();
+    let mut removal_ids = BTreeSet::new();
+    for event in history
+        .iter()
+        .filter(|e| e.status == ActionStatus::Submitted)
+    {
+        match event.action {
+            GovernanceAction::Cap(id) => {
+                cap_ids.insert(id);
+            }
+            GovernanceAction::MarketRemoval(id) => {
+                removal_ids.insert(id);
+            }
+            _ => (),
+        }
+    }
+
+    for id in cap_ids {
+        let pending_cap = vault.pendingCap(id).call().await?;
+        if pending_cap.validAt != 0 {
+            pending.push(PendingAction {
+                vault: vault_address,
+                action: GovernanceAction::Cap(id),
+                value: ActionValue::Amount(U256::from(pending_cap.value)),
+                valid_at: pending_cap.validAt,
+            });
+        }
+    }
+
+    for id in removal_ids {
+        let config = vault.config(id).call().await?;
+        if config.removableAt != 0 {
+            pending.push(PendingAction {
+                vault: vault_address,
+                action: GovernanceAction::MarketRemoval(id),
+                value: ActionValue::None,
+                valid_at: config.removableAt,
+            });
+        }
+    }
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    const VAULT: Address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    const CURATOR: Address = address!("000000000000000000000000000000000000C0DE");
+
+    fn log<E: SolEvent>(block_number: u64, event: E) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: VAULT,
+                data: event.encode_log_data(),
+            },
+            block_number: Some(block_number),
+            ..Default::default()
+        }
+    }
+
+    fn history(logs: &[Log]) -> Vec<(u64, GovernanceAction, ActionStatus)> {
+        let mut history = GovernanceHistory::new(0);
+        for log in logs {
+            history.push_log(log).unwrap();
+        }
+        history
+            .events
+            .iter()
+            .map(|event| (event.block_number.unwrap(), event.action, event.status))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_submitted_and_executed_caps() {
+        let id = B256::repeat_byte(1);
+        let cap = U256::from(1_000_000);
+        let submitted = decode_governance_log(&log(
+            1,
+            IVault::SubmitCap {
+                caller: CURATOR,
+                id,
+                cap,
+            },
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(submitted.action, GovernanceAction::Cap(id));
+        assert_eq!(submitted.status, ActionStatus::Submitted);
+        assert_eq!(submitted.value, ActionValue::Amount(cap));
+
+        let executed = decode_governance_log(&log(
+            2,
+            IVault::SetCap {
+                caller: CURATOR,
+                id,
+                cap,
+            },
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(executed.status, ActionStatus::Executed);
+
+        // Queue updates are only interpreted by the history
+        let queue = log(
+            3,
+            IVault::SetWithdrawQueue {
+                caller: CURATOR,
+                newWithdrawQueue: vec![id],
+            },
+        );
+        assert!(decode_governance_log(&queue).unwrap().is_none());
+    }
+
+    #[test]
+    fn revoked_removals_are_not_executed_by_a_queue_update() {
+        let id = B256::repeat_byte(1);
+        let logs = [
+            log(
+                1,
+                IVault::SubmitMarketRemoval {
+                    caller: CURATOR,
+                    id,
+                },
+            ),
+            log(
+                2,
+                IVault::RevokePendingMarketRemoval {
+                    caller: CURATOR,
+                    id,
+                },
+            ),
+            log(
+                3,
+                IVault::SetWithdrawQueue {
+                    caller: CURATOR,
+                    newWithdrawQueue: vec![],
+                },
+            ),
+        ];
+        assert_eq!(
+            history(&logs),
+            [
+                (
+                    1,
+                    GovernanceAction::MarketRemoval(id),
+                    ActionStatus::Submitted
+                ),
+                (
+                    2,
+                    GovernanceAction::MarketRemoval(id),
+                    ActionStatus::Revoked
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn executes_the_removals_left_out_of_the_withdraw_queue() {
+        let removed = B256::repeat_byte(1);
+        let kept = B256::repeat_byte(2);
+        let logs = [
+            log(
+                1,
+                IVault::SubmitMarketRemoval {
+                    caller: CURATOR,
+                    id: removed,
+                },
+            ),
+            // A queue still holding the market does not execute the removal
+            log(
+                2,
+                IVault::SetWithdrawQueue {
+                    caller: CURATOR,
+                    newWithdrawQueue: vec![kept, removed],
+                },
+            ),
+            log(
+                3,
+                IVault::SetWithdrawQueue {
+                    caller: CURATOR,
+                    newWithdrawQueue: vec![kept],
+                },
+            ),
+            // Executed once only
+            log(
+                4,
+                IVault::SetWithdrawQueue {
+                    caller: CURATOR,
+                    newWithdrawQueue: vec![kept],
+                },
+            ),
+        ];
+        assert_eq!(
+            history(&logs),
+            [
+                (
+                    1,
+                    GovernanceAction::MarketRemoval(removed),
+                    ActionStatus::Submitted
+                ),
+                (
+                    3,
+                    GovernanceAction::MarketRemoval(removed),
+                    ActionStatus::Executed
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::arithmetic::health_factor;
+use crate::error::Result;
+use crate::market::{fetch_oracle_price, IMorpho, MORPHO_ADDRESS};
+use crate::output::{checksummed, decimal};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, U256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketSnapshot {
+    pub block_number: u64,
+    #[serde(serialize_with = "decimal")]
+    pub oracle_price: U256,
+    #[serde(serialize_with = "decimal")]
+    pub total_supply_assets: U256,
+    #[serde(serialize_with = "decimal")]
+    pub total_supply_shares: U256,
+    #[serde(serialize_with = "decimal")]
+    pub total_borrow_assets: U256,
+    #[serde(serialize_with = "decimal")]
+    pub total_borrow_shares: U256,
+    pub last_update: u64,
+    #[serde(serialize_with = "decimal")]
+    pub fee: U256,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionSnapshot {
+    pub block_number: u64,
+    #[serde(serialize_with = "checksummed")]
+    pub user: Address,
+    #[serde(serialize_with = "decimal")]
+    pub supply_shares: U256,
+    #[serde(serialize_with = "decimal")]
+    pub borrow_shares: U256,
+    #[serde(serialize_with = "decimal")]
+    pub collateral: U256,
+    // Computed with the market state at the block, without the interest accrued since its last update
+    pub health_factor: Option<f64>,
+}
+
+pub async fn read_oracle_price_at(rpc_url: Url, oracle: Address, block: BlockId) -> Result<U256> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    fetch_oracle_price(&provider, oracle, block).await
+}
+
+pub async fn read_market_at(
+    rpc_url: Url,
+    market_id: B256,
+    block_number: u64,
+) -> Result<MarketSnapshot> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    let block = BlockId::number(block_number);
+    let market_params = morpho
+        .idToMarketParams(market_id)
+        .block(block)
+        .call()
+        .await?;
+    let market_data = morpho.market(market_id).block(block).call().await?;
+
+    Ok(MarketSnapshot {
+        block_number,
+        oracle_price: read_oracle_price_at(rpc_url, market_params.oracle, block).await?,
+        total_supply_assets: U256::from(market_data.totalSupplyAssets),
+        total_supply_shares: U256::from(market_data.totalSupplyShares),
+        total_borrow_assets: U256::from(market_data.totalBorrowAssets),
+        total_borrow_shares: U256::from(market_data.totalBorrowShares),
+        last_update: market_data.lastUpdate as u64,
+        fee: U256::from(market_data.fee),
+    })
+}
+
+pub async fn read_position_at(
+    rpc_url: Url,
+    market_id: B256,
+    user: Address,
+    block_number: u64,
+) -> Result<(MarketSnapshot, PositionSnapshot)> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    let block = BlockId::number(block_number);
+    let lltv = morpho
+        .idToMarketParams(market_id)
+        .block(block)
+        .call()
+        .await?
+        .lltv;
+    let market = read_market_at(rpc_url, market_id, block_number).await?;
+    let position = morpho.position(market_id, user).block(block).call().await?;
+
+    let borrow_shares = U256::from(position.borrowShares);
+    let collateral = U256::from(position.collateral);
+    let position = PositionSnapshot {
+        block_number,
+        user,
+        supply_shares: position.supplyShares,
+        borrow_shares,
+        collateral,
+        health_factor: health_factor(
+            collateral,
+            borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            market.oracle_price,
+            lltv,
+        ),
+    };
+    Ok((market, position))
+}
+
+// Samples an oracle price every `interval` blocks
+pub async fn sample_oracle_prices(
+    rpc_url: Url,
+    oracle: Address,
+    from_block: u64,
+    to_block: u64,
+    interval: u64,
+) -> Result<Vec<(u64, U256)>> {
+    let mut prices = Vec::new();
+    let mut block_number = from_block;
+    while block_number <= to_block {
+        let price =
+            read_oracle_price_at(rpc_url.clone(), oracle, BlockId::number(block_number)).await?;
+        prices.push((block_number, price));
+        block_number += interval.max(1);
+    }
+    Ok(prices)
+}
+
+// Samples a position (and its market) every `interval` blocks
+pub async fn sample_position_history(
+    rpc_url: Url,
+    market_id: B256,
+    user: Address,
+    from_block: u64,
+    to_block: u64,
+    interval: u64,
+) -> Result<Vec<(MarketSnapshot, PositionSnapshot)>> {
+    let mut snapshots = Vec::new();
+    let mut block_number = from_block;
+    while block_number <= to_block {
+        snapshots.push(read_position_at(rpc_url.clone(), market_id, user, block_number).await?);
+        block_number += interval.max(1);
+    }
+    Ok(snapshots)
+}
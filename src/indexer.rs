@@ -0,0 +1,1247 @@
+use crate::error::{Error, Result};
+use crate::events::{morpho_event_market_id, vault_event_market_id, EventFields, FieldValue};
+use crate::market::{
+    morpho_deployment_block,
+    IMorpho::{self, IMorphoEvents},
+    MarketRecord, Position, MORPHO_ADDRESS,
+};
+use crate::output::{checksummed, decimal};
+use crate::registry::{VaultRecord, VaultRegistry, VAULT_FACTORIES};
+use crate::vault::{IVault::IVaultEvents, IVaultFactory};
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{BlockTransactionsKind, Filter, Log},
+    sol_types::{SolEvent, SolEventInterface},
+    transports::http::reqwest::Url,
+};
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs, io,
+    path::Path,
+    str::FromStr,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS checkpoints (
+    address TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    -- Last indexed block
+    block_number INTEGER NOT NULL
+);
+-- Hashes of the indexed blocks (blocks with logs and batch ends), to detect reorgs
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS events (
+    block_number INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    block_hash TEXT NOT NULL,
+    block_timestamp INTEGER,
+    transaction_hash TEXT,
+    event TEXT NOT NULL,
+    market_id TEXT,
+    -- Event parameters as a JSON object
+    fields TEXT NOT NULL,
+    -- Raw log as JSON, replayed to rebuild the derived tables after a reorg
+    log TEXT NOT NULL,
+    PRIMARY KEY (block_number, log_index)
+);
+CREATE INDEX IF NOT EXISTS events_by_address ON events (address, block_number);
+CREATE INDEX IF NOT EXISTS events_by_market ON events (market_id, block_number);
+CREATE TABLE IF NOT EXISTS markets (
+    id TEXT PRIMARY KEY,
+    loan_token TEXT NOT NULL,
+    collateral_token TEXT NOT NULL,
+    oracle TEXT NOT NULL,
+    irm TEXT NOT NULL,
+    lltv TEXT NOT NULL,
+    created_at_block INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS vaults (
+    address TEXT PRIMARY KEY,
+    factory TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    name TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    created_at_block INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS positions (
+    market_id TEXT NOT NULL,
+    user TEXT NOT NULL,
+    supply_shares TEXT NOT NULL,
+    borrow_shares TEXT NOT NULL,
+    collateral TEXT NOT NULL,
+    PRIMARY KEY (market_id, user)
+);
+CREATE TABLE IF NOT EXISTS vault_balances (
+    vault TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    shares TEXT NOT NULL,
+    PRIMARY KEY (vault, owner)
+);
+-- Protocol settings needed to derive positions, e.g. the fee recipient
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+-- Vault registries (see registry.rs), refreshed from their own checkpoints and not rolled back
+CREATE TABLE IF NOT EXISTS registry_vaults (
+    chain_id INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    -- VaultRecord as JSON
+    record TEXT NOT NULL,
+    PRIMARY KEY (chain_id, address)
+);
+CREATE TABLE IF NOT EXISTS registry_checkpoints (
+    chain_id INTEGER NOT NULL,
+    factory TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, factory)
+);
+";
+
+// Derived tables, rebuilt from the events after a rollback
+const DERIVED_TABLES: [&str; 5] = ["markets", "vaults", "positions", "vault_balances", "state"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContractKind {
+    Morpho,
+    // Only its CreateMetaMorpho events are indexed, the created vaults are then indexed too
+    VaultFactory,
+    Vault,
+}
+
+impl ContractKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ContractKind::Morpho => "morpho",
+            ContractKind::VaultFactory => "vaultFactory",
+            ContractKind::Vault => "vault",
+        }
+    }
+}
+
+impl FromStr for ContractKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "morpho" => Ok(ContractKind::Morpho),
+            "vaultFactory" => Ok(ContractKind::VaultFactory),
+            "vault" => Ok(ContractKind::Vault),
+            _ => Err(Error::Config(format!("unknown contract kind {}", kind))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    pub kind: ContractKind,
+    pub block_number: u64,
+}
+
+// Logs of a contract on a block range, as fetched by `Indexer::sync` or replayed from a `Recording`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Batch {
+    pub address: Address,
+    pub kind: ContractKind,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub to_block_hash: B256,
+    pub logs: Vec<Log>,
+}
+
+// Batches recorded by `Indexer::sync`, which can be ingested again without any RPC
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub batches: Vec<Batch>,
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::from)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self).map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyncOptions {
+    // Blocks left out at the tip of the chain, fewer reorgs have to be rolled back
+    pub confirmations: u64,
+    // Largest block range of one eth_getLogs request
+    pub max_block_range: u64,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            confirmations: 3,
+            max_block_range: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedVault {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub factory: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub asset: Address,
+    pub name: String,
+    pub symbol: String,
+    pub created_at_block: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultBalance {
+    #[serde(serialize_with = "checksummed")]
+    pub vault: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub owner: Address,
+    #[serde(serialize_with = "decimal")]
+    pub shares: U256,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedEvent {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    pub kind: ContractKind,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub block_timestamp: Option<u64>,
+    pub transaction_hash: Option<B256>,
+    pub log_index: u64,
+    pub event: String,
+    pub market_id: Option<B256>,
+    pub fields: Value,
+}
+
+// Filters of `Indexer::events`, every field is optional
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    pub address: Option<Address>,
+    pub event: Option<String>,
+    pub market_id: Option<B256>,
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+// A log decoded with the ABI of the contract kind
+pub(crate) enum IndexedLog {
+    Morpho(IMorphoEvents),
+    CreateMetaMorpho(IVaultFactory::CreateMetaMorpho),
+    Vault(IVaultEvents),
+}
+
+impl IndexedLog {
+    pub(crate) fn decode(kind: ContractKind, log: &Log) -> Result<Self> {
+        Ok(match kind {
+            ContractKind::Morpho => {
+                IndexedLog::Morpho(IMorphoEvents::decode_log(&log.inner, true)?.data)
+            }
+            ContractKind::VaultFactory => IndexedLog::CreateMetaMorpho(
+                IVaultFactory::CreateMetaMorpho::decode_log(&log.inner, true)?.data,
+            ),
+            ContractKind::Vault => {
+                IndexedLog::Vault(IVaultEvents::decode_log(&log.inner, true)?.data)
+            }
+        })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            IndexedLog::Morpho(event) => event.name(),
+            IndexedLog::CreateMetaMorpho(_) => "CreateMetaMorpho",
+            IndexedLog::Vault(event) => event.name(),
+        }
+    }
+
+    fn market_id(&self) -> Option<B256> {
+        match self {
+            IndexedLog::Morpho(event) => morpho_event_market_id(event),
+            IndexedLog::CreateMetaMorpho(_) => None,
+            IndexedLog::Vault(event) => vault_event_market_id(event),
+        }
+    }
+
+    pub(crate) fn field_values(&self) -> Vec<(&'static str, FieldValue)> {
+        match self {
+            IndexedLog::Morpho(event) => event.fields(),
+            IndexedLog::CreateMetaMorpho(event) => vec![
+                ("metaMorpho", FieldValue::Address(event.metaMorpho)),
+                ("caller", FieldValue::Address(event.caller)),
+                ("initialOwner", FieldValue::Address(event.initialOwner)),
+                ("initialTimelock", FieldValue::Uint(event.initialTimelock)),
+                ("asset", FieldValue::Address(event.asset)),
+                ("name", FieldValue::String(event.name.clone())),
+                ("symbol", FieldValue::String(event.symbol.clone())),
+                ("salt", FieldValue::Bytes32(event.salt)),
+            ],
+            IndexedLog::Vault(event) => event.fields(),
+        }
+    }
+
+    fn fields(&self) -> Result<Value> {
+        let mut object = Map::new();
+        for (name, value) in self.field_values() {
+            object.insert(
+                name.to_string(),
+                serde_json::to_value(value).map_err(io::Error::from)?,
+            );
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+// Stores decoded Morpho, vault factory and vault events in SQLite, with the markets, vaults,
+// positions and vault balances derived from them
+pub struct Indexer {
+    conn: Connection,
+}
+
+impl Indexer {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Indexer { conn })
+    }
+
+    // Registers a contract to index from `from_block`, a no-op if it is already indexed
+    pub fn track(&self, address: Address, kind: ContractKind, from_block: u64) -> Result<()> {
+        track(&self.conn, address, kind, from_block)
+    }
+
+    // Morpho and the known vault factories of a chain, from their deployment
+    pub fn track_defaults(&self, chain_id: u64) -> Result<()> {
+        self.track(
+            MORPHO_ADDRESS,
+            ContractKind::Morpho,
+            morpho_deployment_block(chain_id)?,
+        )?;
+        for factory in VAULT_FACTORIES.iter().filter(|f| f.chain_id == chain_id) {
+            self.track(
+                factory.address,
+                ContractKind::VaultFactory,
+                factory.deployment_block,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn checkpoints(&self) -> Result<Vec<Checkpoint>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT address, kind, block_number FROM checkpoints ORDER BY address")?;
+        let rows = statement.query_map([], |row| {
+            Ok((parse(row, 0)?, row.get::<_, String>(1)?, row.get(2)?))
+        })?;
+        let mut checkpoints = Vec::new();
+        for row in rows {
+            let (address, kind, block_number) = row?;
+            checkpoints.push(Checkpoint {
+                address,
+                kind: kind.parse()?,
+                block_number,
+            });
+        }
+        Ok(checkpoints)
+    }
+
+    // Stores the logs of a batch and moves the checkpoint of its contract, and returns the number of
+    // new events. Logs which are already indexed are skipped, so batches can be ingested again.
+    // A reorg rollback and the new logs are committed together, a failure leaves the index untouched.
+    pub fn ingest(&mut self, batch: &Batch) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        // A different hash for an indexed block means the chain reorganized from that block
+        let mut hashes: Vec<(u64, B256)> = batch
+            .logs
+            .iter()
+            .filter_map(|log| Some((log.block_number?, log.block_hash?)))
+            .collect();
+        hashes.push((batch.to_block, batch.to_block_hash));
+        if let Some(fork_block) = first_conflict(&tx, &hashes)? {
+            rollback(&tx, fork_block.saturating_sub(1))?;
+        }
+
+        track(&tx, batch.address, batch.kind, batch.from_block)?;
+        let checkpoint: u64 = tx.query_row(
+            "SELECT block_number FROM checkpoints WHERE address = ?1",
+            [batch.address.to_string()],
+            |row| row.get(0),
+        )?;
+        if batch.from_block > checkpoint + 1 {
+            return Err(Error::Config(format!(
+                "batch of {} starts at block {} but the contract is indexed up to block {}",
+                batch.address, batch.from_block, checkpoint
+            )));
+        }
+
+        let mut added = 0;
+        for log in &batch.logs {
+            if !log.removed && insert_event(&tx, batch.address, batch.kind, log)? {
+                added += 1;
+            }
+        }
+        for (number, hash) in hashes {
+            tx.execute(
+                "INSERT OR REPLACE INTO blocks (number, hash) VALUES (?1, ?2)",
+                params![number, hash.to_string()],
+            )?;
+        }
+        tx.execute(
+            "UPDATE checkpoints SET block_number = MAX(block_number, ?1) WHERE address = ?2",
+            params![batch.to_block, batch.address.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    // Ingests every batch of a recording, in order
+    pub fn replay(&mut self, recording: &Recording) -> Result<usize> {
+        let mut added = 0;
+        for batch in &recording.batches {
+            added += self.ingest(batch)?;
+        }
+        Ok(added)
+    }
+
+    // Removes everything indexed after `block` and rebuilds the derived tables
+    pub fn rollback(&mut self, block: u64) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        rollback(&tx, block)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Indexes every tracked contract up to the latest block minus the confirmations, rolling back
+    // first if the chain reorganized since the last sync. Batches are appended to `recording` if given.
+    pub async fn sync(
+        &mut self,
+        rpc_url: Url,
+        options: SyncOptions,
+        mut recording: Option<&mut Recording>,
+    ) -> Result<usize> {
+        let provider = ProviderBuilder::new().on_http(rpc_url);
+        let target = provider
+            .get_block_number()
+            .await?
+            .saturating_sub(options.confirmations);
+
+        // Walk back the indexed blocks until one still has the same hash on chain
+        let indexed_blocks: Vec<(u64, String)> = {
+            let mut statement = self
+                .conn
+                .prepare("SELECT number, hash FROM blocks ORDER BY number DESC")?;
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut common_ancestor = None;
+        for (number, hash) in &indexed_blocks {
+            let block = provider
+                .get_block_by_number(
+                    BlockNumberOrTag::Number(*number),
+                    BlockTransactionsKind::Hashes,
+                )
+                .await?;
+            if block.is_some_and(|block| block.header.hash.to_string() == *hash) {
+                common_ancestor = Some(*number);
+                break;
+            }
+        }
+        let latest_indexed = indexed_blocks.first().map(|(number, _)| *number);
+        if latest_indexed != common_ancestor {
+            // No indexed block is left on chain when the common ancestor is older than all of them
+            let lowest_indexed = indexed_blocks.last().map_or(0, |(number, _)| *number);
+            self.rollback(common_ancestor.unwrap_or(lowest_indexed.saturating_sub(1)))?;
+        }
+
+        let mut added = 0;
+        // Vaults created by the factories are tracked while syncing, so checkpoints are read again after every batch
+        while let Some(checkpoint) = self
+            .checkpoints()?
+            .into_iter()
+            .find(|checkpoint| checkpoint.block_number < target)
+        {
+            let from_block = checkpoint.block_number + 1;
+            let to_block = target.min(from_block + options.max_block_range.max(1) - 1);
+
+            let mut filter = Filter::new()
+                .address(checkpoint.address)
+                .from_block(BlockNumberOrTag::Number(from_block))
+                .to_block(BlockNumberOrTag::Number(to_block));
+            if checkpoint.kind == ContractKind::VaultFactory {
+                filter = filter.event_signature(IVaultFactory::CreateMetaMorpho::SIGNATURE_HASH);
+            }
+            let mut logs = provider.get_logs(&filter).await?;
+            // Most nodes leave out the block timestamp of the logs, it is read from the block headers
+            let mut timestamps = HashMap::new();
+            for log in &mut logs {
+                let (None, Some(block_number)) = (log.block_timestamp, log.block_number) else {
+                    continue;
+                };
+                if let Entry::Vacant(entry) = timestamps.entry(block_number) {
+                    let block = provider
+                        .get_block_by_number(
+                            BlockNumberOrTag::Number(block_number),
+                            BlockTransactionsKind::Hashes,
+                        )
+                        .await?
+                        .ok_or_else(|| Error::NotFound(format!("block {}", block_number)))?;
+                    entry.insert(block.header.timestamp);
+                }
+                log.block_timestamp = timestamps.get(&block_number).copied();
+            }
+            let to_block_hash = provider
+                .get_block_by_number(
+                    BlockNumberOrTag::Number(to_block),
+                    BlockTransactionsKind::Hashes,
+                )
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("block {}", to_block)))?
+                .header
+                .hash;
+
+            let batch = Batch {
+                address: checkpoint.address,
+                kind: checkpoint.kind,
+                from_block,
+                to_block,
+                to_block_hash,
+                logs,
+            };
+            added += self.ingest(&batch)?;
+            if let Some(recording) = recording.as_deref_mut() {
+                recording.batches.push(batch);
+            }
+        }
+        Ok(added)
+    }
+
+    // Vault registry of a chain as last saved, empty if it never was
+    pub fn load_registry(&self, chain_id: u64) -> Result<VaultRegistry> {
+        let mut registry = VaultRegistry::new(chain_id);
+        let mut statement = self
+            .conn
+            .prepare("SELECT record FROM registry_vaults WHERE chain_id = ?1")?;
+        let rows = statement.query_map([chain_id], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            let record: VaultRecord = serde_json::from_str(&row?).map_err(io::Error::from)?;
+            registry.vaults.insert(record.address, record);
+        }
+        let mut statement = self.conn.prepare(
+            "SELECT factory, block_number FROM registry_checkpoints WHERE chain_id = ?1",
+        )?;
+        let rows = statement.query_map([chain_id], |row| Ok((parse(row, 0)?, row.get(1)?)))?;
+        for row in rows {
+            let (factory, block_number) = row?;
+            registry.checkpoints.insert(factory, block_number);
+        }
+        Ok(registry)
+    }
+
+    pub fn save_registry(&mut self, registry: &VaultRegistry) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for record in registry.vaults.values() {
+            tx.execute(
+                "INSERT OR REPLACE INTO registry_vaults (chain_id, address, record) VALUES (?1, ?2, ?3)",
+                params![
+                    registry.chain_id,
+                    record.address.to_string(),
+                    serde_json::to_string(record).map_err(io::Error::from)?
+                ],
+            )?;
+        }
+        for (factory, block_number) in &registry.checkpoints {
+            tx.execute(
+                "INSERT OR REPLACE INTO registry_checkpoints (chain_id, factory, block_number) VALUES (?1, ?2, ?3)",
+                params![registry.chain_id, factory.to_string(), block_number],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn markets(&self) -> Result<Vec<MarketRecord>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, loan_token, collateral_token, oracle, irm, lltv FROM markets ORDER BY created_at_block, id",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(MarketRecord {
+                id: parse(row, 0)?,
+                params: IMorpho::MarketParams {
+                    loanToken: parse(row, 1)?,
+                    collateralToken: parse(row, 2)?,
+                    oracle: parse(row, 3)?,
+                    irm: parse(row, 4)?,
+                    lltv: parse(row, 5)?,
+                },
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn vaults(&self) -> Result<Vec<IndexedVault>> {
+        let mut statement = self.conn.prepare(
+            "SELECT address, factory, asset, name, symbol, created_at_block FROM vaults ORDER BY created_at_block, address",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(IndexedVault {
+                address: parse(row, 0)?,
+                factory: parse(row, 1)?,
+                asset: parse(row, 2)?,
+                name: row.get(3)?,
+                symbol: row.get(4)?,
+                created_at_block: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Positions derived from the indexed events, optionally on one market or of one user
+    pub fn positions(
+        &self,
+        market_id: Option<B256>,
+        user: Option<Address>,
+    ) -> Result<Vec<Position>> {
+        let mut statement = self.conn.prepare(
+            "SELECT market_id, user, supply_shares, borrow_shares, collateral FROM positions
+            WHERE (?1 IS NULL OR market_id = ?1) AND (?2 IS NULL OR user = ?2)
+            ORDER BY market_id, user",
+        )?;
+        let rows = statement.query_map(
+            params![
+                market_id.map(|id| id.to_string()),
+                user.map(|user| user.to_string())
+            ],
+            |row| {
+                Ok(Position {
+                    market_id: parse(row, 0)?,
+                    user: parse(row, 1)?,
+                    supply_shares: parse(row, 2)?,
+                    borrow_shares: parse::<U256>(row, 3)?.saturating_to(),
+                    collateral: parse::<U256>(row, 4)?.saturating_to(),
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn vault_balances(&self, vault: Address) -> Result<Vec<VaultBalance>> {
+        let mut statement = self.conn.prepare(
+            "SELECT vault, owner, shares FROM vault_balances WHERE vault = ?1 ORDER BY owner",
+        )?;
+        let rows = statement.query_map([vault.to_string()], |row| {
+            Ok(VaultBalance {
+                vault: parse(row, 0)?,
+                owner: parse(row, 1)?,
+                shares: parse(row, 2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn events(&self, query: &EventQuery) -> Result<Vec<IndexedEvent>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT address, kind, block_number, block_hash, block_timestamp, transaction_hash, log_index, event, market_id, fields
+            FROM events {}",
+            EVENT_FILTER
+        ))?;
+        let rows = statement.query_map(query.params(), |row| {
+            Ok((
+                parse(row, 0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                parse(row, 3)?,
+                row.get(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get(6)?,
+                row.get(7)?,
+                row.get::<_, Option<String>>(8)?,
+                row.get::<_, String>(9)?,
+            ))
+        })?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            let (
+                address,
+                kind,
+                block_number,
+                block_hash,
+                block_timestamp,
+                transaction_hash,
+                log_index,
+                event,
+                market_id,
+                fields,
+            ) = row?;
+            events.push(IndexedEvent {
+                address,
+                kind: kind.parse()?,
+                block_number,
+                block_hash,
+                block_timestamp,
+                transaction_hash: transaction_hash.map(|hash| hash.parse()).transpose()?,
+                log_index,
+                event,
+                market_id: market_id.map(|id| id.parse()).transpose()?,
+                fields: serde_json::from_str(&fields).map_err(io::Error::from)?,
+            });
+        }
+        Ok(events)
+    }
+
+    // Stored raw logs matching `query` with the kind of their contract, in chain order
+    pub fn logs(&self, query: &EventQuery) -> Result<Vec<(ContractKind, Log)>> {
+        let mut statement = self
+            .conn
+            .prepare(&format!("SELECT kind, log FROM events {}", EVENT_FILTER))?;
+        let rows = statement.query_map(query.params(), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut logs = Vec::new();
+        for row in rows {
+            let (kind, log) = row?;
+            logs.push((
+                kind.parse()?,
+                serde_json::from_str(&log).map_err(io::Error::from)?,
+            ));
+        }
+        Ok(logs)
+    }
+
+    // Lowest and highest blocks with an indexed event
+    pub fn event_block_range(&self) -> Result<Option<(u64, u64)>> {
+        let range: (Option<u64>, Option<u64>) = self.conn.query_row(
+            "SELECT MIN(block_number), MAX(block_number) FROM events",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(range.0.zip(range.1))
+    }
+}
+
+// WHERE clause of the event queries, bound with `EventQuery::params`
+const EVENT_FILTER: &str = "WHERE (?1 IS NULL OR address = ?1)
+    AND (?2 IS NULL OR event = ?2)
+    AND (?3 IS NULL OR market_id = ?3)
+    AND (?4 IS NULL OR block_number >= ?4)
+    AND (?5 IS NULL OR block_number <= ?5)
+ORDER BY block_number, log_index
+LIMIT COALESCE(?6, -1)";
+
+impl EventQuery {
+    fn params(&self) -> impl rusqlite::Params + '_ {
+        (
+            self.address.map(|address| address.to_string()),
+            self.event.as_deref(),
+            self.market_id.map(|id| id.to_string()),
+            self.from_block,
+            self.to_block,
+            self.limit,
+        )
+    }
+}
+
+// Values are stored as text: checksummed addresses, hex hashes and decimal amounts
+fn parse<T>(row: &Row, index: usize) -> rusqlite::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    row.get::<_, String>(index)?.parse().map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(error))
+    })
+}
+
+// Stores a log and applies it to the derived tables, returns false if it was already stored
+fn insert_event(tx: &Transaction, address: Address, kind: ContractKind, log: &Log) -> Result<bool> {
+    let (Some(block_number), Some(block_hash), Some(log_index)) =
+        (log.block_number, log.block_hash, log.log_index)
+    else {
+        // Pending logs have no position in the chain yet
+        return Ok(false);
+    };
+    let decoded = IndexedLog::decode(kind, log)?;
+
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO events
+        (block_number, log_index, address, kind, block_hash, block_timestamp, transaction_hash, event, market_id, fields, log)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            block_number,
+            log_index,
+            address.to_string(),
+            kind.as_str(),
+            block_hash.to_string(),
+            log.block_timestamp,
+            log.transaction_hash.map(|hash| hash.to_string()),
+            decoded.name(),
+            decoded.market_id().map(|id| id.to_string()),
+            decoded.fields()?.to_string(),
+            serde_json::to_string(log).map_err(io::Error::from)?,
+        ],
+    )?;
+    if inserted == 0 {
+        return Ok(false);
+    }
+    apply(tx, address, block_number, &decoded)?;
+    Ok(true)
+}
+
+// Registers the checkpoint of a contract so that indexing starts at `from_block`, a known contract keeps its own
+fn track(conn: &Connection, address: Address, kind: ContractKind, from_block: u64) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO checkpoints (address, kind, block_number) VALUES (?1, ?2, ?3)",
+        params![
+            address.to_string(),
+            kind.as_str(),
+            from_block.saturating_sub(1)
+        ],
+    )?;
+    Ok(())
+}
+
+// Lowest block of `hashes` indexed with a different hash
+fn first_conflict(conn: &Connection, hashes: &[(u64, B256)]) -> Result<Option<u64>> {
+    let mut fork_block: Option<u64> = None;
+    for (number, hash) in hashes {
+        let indexed: Option<String> = conn
+            .query_row(
+                "SELECT hash FROM blocks WHERE number = ?1",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if indexed.is_some_and(|indexed| indexed != hash.to_string()) {
+            fork_block = Some(fork_block.map_or(*number, |fork| fork.min(*number)));
+        }
+    }
+    Ok(fork_block)
+}
+
+fn rollback(tx: &Transaction, block: u64) -> Result<()> {
+    // Vaults created after the fork are no longer known
+    tx.execute(
+        "DELETE FROM checkpoints WHERE address IN (SELECT address FROM vaults WHERE created_at_block > ?1)",
+        [block],
+    )?;
+    tx.execute(
+        "UPDATE checkpoints SET block_number = MIN(block_number, ?1)",
+        [block],
+    )?;
+    tx.execute("DELETE FROM events WHERE block_number > ?1", [block])?;
+    tx.execute("DELETE FROM blocks WHERE number > ?1", [block])?;
+    rebuild(tx)
+}
+
+// Clears the derived tables and replays the stored events
+fn rebuild(tx: &Transaction) -> Result<()> {
+    for table in DERIVED_TABLES {
+        tx.execute(&format!("DELETE FROM {}", table), [])?;
+    }
+
+    let mut statement = tx.prepare(
+        "SELECT address, kind, block_number, log FROM events ORDER BY block_number, log_index",
+    )?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            parse::<Address>(row, 0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, u64>(2)?,
+            row.get::<_, String>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (address, kind, block_number, log) = row?;
+        let log: Log = serde_json::from_str(&log).map_err(io::Error::from)?;
+        let decoded = IndexedLog::decode(kind.parse()?, &log)?;
+        apply(tx, address, block_number, &decoded)?;
+    }
+    Ok(())
+}
+
+fn apply(
+    tx: &Transaction,
+    address: Address,
+    block_number: u64,
+    decoded: &IndexedLog,
+) -> Result<()> {
+    match decoded {
+        IndexedLog::Morpho(event) => apply_morpho_event(tx, block_number, event),
+        IndexedLog::CreateMetaMorpho(event) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO vaults (address, factory, asset, name, symbol, created_at_block)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    event.metaMorpho.to_string(),
+                    address.to_string(),
+                    event.asset.to_string(),
+                    event.name,
+                    event.symbol,
+                    block_number
+                ],
+            )?;
+            // The vault is indexed from its creation
+            tx.execute(
+                "INSERT OR IGNORE INTO checkpoints (address, kind, block_number) VALUES (?1, ?2, ?3)",
+                params![
+                    event.metaMorpho.to_string(),
+                    ContractKind::Vault.as_str(),
+                    block_number.saturating_sub(1)
+                ],
+            )?;
+            Ok(())
+        }
+        IndexedLog::Vault(IVaultEvents::Transfer(event)) => {
+            // Mints and burns are transfers from and to the zero address
+            if !event.from.is_zero() {
+                update_balance(tx, address, event.from, |shares| {
+                    shares.saturating_sub(event.value)
+                })?;
+            }
+            if !event.to.is_zero() {
+                update_balance(tx, address, event.to, |shares| shares + event.value)?;
+            }
+            Ok(())
+        }
+        IndexedLog::Vault(_) => Ok(()),
+    }
+}
+
+// Position fields, in the order of the positions table
+const SUPPLY_SHARES: usize = 0;
+const BORROW_SHARES: usize = 1;
+const COLLATERAL: usize = 2;
+
+fn apply_morpho_event(tx: &Transaction, block_number: u64, event: &IMorphoEvents) -> Result<()> {
+    match event {
+        IMorphoEvents::CreateMarket(IMorpho::CreateMarket { id, marketParams }) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO markets (id, loan_token, collateral_token, oracle, irm, lltv, created_at_block)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    id.to_string(),
+                    marketParams.loanToken.to_string(),
+                    marketParams.collateralToken.to_string(),
+                    marketParams.oracle.to_string(),
+                    marketParams.irm.to_string(),
+                    marketParams.lltv.to_string(),
+                    block_number
+                ],
+            )?;
+        }
+        IMorphoEvents::Supply(e) => {
+            update_position(tx, e.id, e.onBehalf, |p| p[SUPPLY_SHARES] += e.shares)?
+        }
+        IMorphoEvents::Withdraw(e) => update_position(tx, e.id, e.onBehalf, |p| {
+            p[SUPPLY_SHARES] = p[SUPPLY_SHARES].saturating_sub(e.shares)
+        })?,
+        IMorphoEvents::Borrow(e) => {
+            update_position(tx, e.id, e.onBehalf, |p| p[BORROW_SHARES] += e.shares)?
+        }
+        IMorphoEvents::Repay(e) => update_position(tx, e.id, e.onBehalf, |p| {
+            p[BORROW_SHARES] = p[BORROW_SHARES].saturating_sub(e.shares)
+        })?,
+        IMorphoEvents::SupplyCollateral(e) => {
+            update_position(tx, e.id, e.onBehalf, |p| p[COLLATERAL] += e.assets)?
+        }
+        IMorphoEvents::WithdrawCollateral(e) => update_position(tx, e.id, e.onBehalf, |p| {
+            p[COLLATERAL] = p[COLLATERAL].saturating_sub(e.assets)
+        })?,
+        // Bad debt shares are removed from the borrower on top of the repaid shares
+        IMorphoEvents::Liquidate(e) => update_position(tx, e.id, e.borrower, |p| {
+            p[BORROW_SHARES] = p[BORROW_SHARES].saturating_sub(e.repaidShares + e.badDebtShares);
+            p[COLLATERAL] = p[COLLATERAL].saturating_sub(e.seizedAssets);
+        })?,
+        // Fee shares are supplied on behalf of the fee recipient
+        IMorphoEvents::AccrueInterest(e) if !e.feeShares.is_zero() => {
+            let fee_recipient: Option<String> = tx
+                .query_row(
+                    "SELECT value FROM state WHERE key = 'fee_recipient'",
+                    [],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(fee_recipient) = fee_recipient {
+                update_position(tx, e.id, fee_recipient.parse()?, |p| {
+                    p[SUPPLY_SHARES] += e.feeShares
+                })?;
+            }
+        }
+        IMorphoEvents::SetFeeRecipient(e) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO state (key, value) VALUES ('fee_recipient', ?1)",
+                [e.newFeeRecipient.to_string()],
+            )?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Positions are replayed from the indexed events only, so amounts are floored at zero when indexing
+// starts after the deployment of Morpho
+fn update_position(
+    tx: &Transaction,
+    id: B256,
+    user: Address,
+    update: impl FnOnce(&mut [U256; 3]),
+) -> Result<()> {
+    let mut position = tx
+        .query_row(
+            "SELECT supply_shares, borrow_shares, collateral FROM positions WHERE market_id = ?1 AND user = ?2",
+            [id.to_string(), user.to_string()],
+            |row| Ok([parse(row, 0)?, parse(row, 1)?, parse(row, 2)?]),
+        )
+        .optional()?
+        .unwrap_or_default();
+    update(&mut position);
+    tx.execute(
+        "INSERT OR REPLACE INTO positions (market_id, user, supply_shares, borrow_shares, collateral)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            id.to_string(),
+            user.to_string(),
+            position[SUPPLY_SHARES].to_string(),
+            position[BORROW_SHARES].to_string(),
+            position[COLLATERAL].to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_balance(
+    tx: &Transaction,
+    vault: Address,
+    owner: Address,
+    update: impl FnOnce(U256) -> U256,
+) -> Result<()> {
+    let shares: U256 = tx
+        .query_row(
+            "SELECT shares FROM vault_balances WHERE vault = ?1 AND owner = ?2",
+            [vault.to_string(), owner.to_string()],
+            |row| parse(row, 0),
+        )
+        .optional()?
+        .unwrap_or_default();
+    tx.execute(
+        "INSERT OR REPLACE INTO vault_balances (vault, owner, shares) VALUES (?1, ?2, ?3)",
+        params![
+            vault.to_string(),
+            owner.to_string(),
+            update(shares).to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, b256};
+
+    #[test]
+    fn saves_and_loads_the_vault_registry() {
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        let factory = VAULT_FACTORIES[0];
+        let record = VaultRecord {
+            chain_id: 1,
+            address: address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB"),
+            factory: factory.address,
+            version: factory.version.to_string(),
+            created_at_block: Some(18_940_000),
+            caller: address!("0000000000000000000000000000000000000001"),
+            initial_owner: address!("0000000000000000000000000000000000000002"),
+            initial_timelock: U256::from(86_400),
+            asset: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            name: "Steakhouse USDC".to_string(),
+            symbol: "steakUSDC".to_string(),
+            salt: B256::repeat_byte(1),
+            owner: address!("0000000000000000000000000000000000000003"),
+            curator: address!("0000000000000000000000000000000000000004"),
+        };
+        let mut registry = VaultRegistry::new(1);
+        registry.vaults.insert(record.address, record.clone());
+        registry.checkpoints.insert(factory.address, 21_000_000);
+        indexer.save_registry(&registry).unwrap();
+
+        let loaded = indexer.load_registry(1).unwrap();
+        assert_eq!(loaded.vaults.values().collect::<Vec<_>>(), [&record]);
+        assert_eq!(loaded.checkpoints, registry.checkpoints);
+        // Registries are kept per chain
+        assert!(indexer.load_registry(8453).unwrap().vaults.is_empty());
+    }
+
+    const MARKET: B256 = b256!("de3125c2745c8d766cdd3747e7af880450f39aa3ec30e93aa180c7a79280be95");
+    const VAULT: Address = address!("5a5A5a5a5A5a5a5a5a5A5a5A5A5a5a5A5A5A5A5A");
+    const BORROWER: Address = address!("B0B0b0B0B0B0B0b0B0B0B0b0b0b0b0B0b0b0B0B0");
+
+    fn synthetic() -> Recording {
+        Recording::load("data/recordings/synthetic.json").unwrap()
+    }
+
+    fn borrower_position(indexer: &Indexer) -> (u128, u128) {
+        let positions = indexer.positions(Some(MARKET), Some(BORROWER)).unwrap();
+        (positions[0].borrow_shares, positions[0].collateral)
+    }
+
+    fn liquidation_blocks(indexer: &Indexer) -> Vec<u64> {
+        let query = EventQuery {
+            event: Some("Liquidate".to_string()),
+            ..Default::default()
+        };
+        let events = indexer.events(&query).unwrap();
+        events.iter().map(|event| event.block_number).collect()
+    }
+
+    #[test]
+    fn derives_markets_vaults_and_positions_from_a_recording() {
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        indexer.replay(&synthetic()).unwrap();
+
+        let markets = indexer.markets().unwrap();
+        assert_eq!(markets.len(), 1);
+        assert_eq!(markets[0].id, MARKET);
+        assert_eq!(markets[0].params.id(), MARKET);
+
+        let vaults = indexer.vaults().unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(vaults[0].address, VAULT);
+        assert_eq!(vaults[0].symbol, "sUSDC");
+        assert_eq!(vaults[0].created_at_block, 21_000_001);
+
+        // The vault supplied, the borrower was partially liquidated after a repay
+        let positions = indexer.positions(Some(MARKET), None).unwrap();
+        assert_eq!(positions.len(), 3);
+        let vault_position = indexer.positions(None, Some(VAULT)).unwrap();
+        assert_eq!(
+            vault_position[0].supply_shares,
+            U256::from(1_000_000_000_000_000_000u128)
+        );
+        assert_eq!(
+            borrower_position(&indexer),
+            (502_000_000_000_000_000, 460_000_000_000_000_000_000)
+        );
+        let balances: Vec<U256> = indexer
+            .vault_balances(VAULT)
+            .unwrap()
+            .iter()
+            .map(|balance| balance.shares)
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                U256::from(750_000_000_000_000_000_000_000u128),
+                U256::from(250_000_000_000_000_000_000_000u128)
+            ]
+        );
+
+        let checkpoints = indexer.checkpoints().unwrap();
+        assert_eq!(checkpoints.len(), 3);
+        assert!(checkpoints
+            .iter()
+            .all(|checkpoint| checkpoint.block_number == 21_000_019));
+    }
+
+    #[test]
+    fn ingests_a_batch_again_without_new_events() {
+        let recording = synthetic();
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        indexer.replay(&recording).unwrap();
+        let events = indexer.events(&EventQuery::default()).unwrap().len();
+
+        for batch in &recording.batches[4..] {
+            assert_eq!(indexer.ingest(batch).unwrap(), 0);
+        }
+        assert_eq!(
+            indexer.events(&EventQuery::default()).unwrap().len(),
+            events
+        );
+        assert_eq!(
+            borrower_position(&indexer),
+            (502_000_000_000_000_000, 460_000_000_000_000_000_000)
+        );
+    }
+
+    #[test]
+    fn rolls_back_reorganized_blocks() {
+        let recording = synthetic();
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        for batch in &recording.batches[..4] {
+            indexer.ingest(batch).unwrap();
+        }
+        assert_eq!(liquidation_blocks(&indexer), [21_000_015]);
+        assert_eq!(borrower_position(&indexer).0, 402_000_000_000_000_000);
+
+        // The same range with other hashes replaces the liquidation of the dropped fork
+        assert_eq!(indexer.ingest(&recording.batches[4]).unwrap(), 2);
+        assert_eq!(liquidation_blocks(&indexer), [21_000_014]);
+        assert_eq!(borrower_position(&indexer).0, 502_000_000_000_000_000);
+    }
+
+    #[test]
+    fn keeps_the_index_when_a_reorganized_batch_is_rejected() {
+        let recording = synthetic();
+        let mut indexer = Indexer::open_in_memory().unwrap();
+        for batch in &recording.batches[..4] {
+            indexer.ingest(batch).unwrap();
+        }
+
+        // The batch conflicts with the indexed hashes but leaves a gap, so the rollback is not committed
+        let mut batch = recording.batches[4].clone();
+        batch.from_block = 21_000_030;
+        assert!(matches!(indexer.ingest(&batch), Err(Error::Config(_))));
+        assert_eq!(liquidation_blocks(&indexer), [21_000_015]);
+        assert_eq!(borrower_position(&indexer).0, 402_000_000_000_000_000);
+    }
+
+    #[test]
+    fn tracks_morpho_from_its_deployment_on_the_chain() {
+        let indexer = Indexer::open_in_memory().unwrap();
+        indexer.track_defaults(8453).unwrap();
+        let checkpoints = indexer.checkpoints().unwrap();
+        let morpho = checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.kind == ContractKind::Morpho)
+            .unwrap();
+        assert_eq!(morpho.block_number, 13_977_147);
+        assert_eq!(checkpoints.len(), 3);
+
+        assert!(matches!(indexer.track_defaults(10), Err(Error::Config(_))));
+    }
+}
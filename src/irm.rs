@@ -0,0 +1,99 @@
+use alloy::primitives::{I256, U256};
+use serde::Serialize;
+
+// Offline model of the AdaptiveCurveIRM (equivalent of AdaptiveCurveIrm.sol and ConstantsLib.sol)
+// Rates are expressed per second, utilizations and errors as plain fractions
+pub const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+pub const CURVE_STEEPNESS: f64 = 4.0;
+pub const TARGET_UTILIZATION: f64 = 0.9;
+pub const INITIAL_RATE_AT_TARGET: f64 = 0.04 / SECONDS_PER_YEAR;
+
+pub fn wad_to_f64(x: U256) -> f64 {
+    f64::from(x) / 1e18
+}
+
+pub fn signed_wad_to_f64(x: I256) -> f64 {
+    let (sign, abs) = x.into_sign_and_abs();
+    if sign.is_negative() {
+        -wad_to_f64(abs)
+    } else {
+        wad_to_f64(abs)
+    }
+}
+
+pub fn utilization(total_supply_assets: f64, total_borrow_assets: f64) -> f64 {
+    if total_supply_assets > 0.0 {
+        total_borrow_assets / total_supply_assets
+    } else {
+        0.0
+    }
+}
+
+// Normalized distance between the utilization and the target, in [-1, 1]
+pub fn err(utilization: f64) -> f64 {
+    let err_norm_factor = if utilization > TARGET_UTILIZATION {
+        1.0 - TARGET_UTILIZATION
+    } else {
+        TARGET_UTILIZATION
+    };
+    (utilization - TARGET_UTILIZATION) / err_norm_factor
+}
+
+pub fn curve(rate_at_target: f64, err: f64) -> f64 {
+    let coeff = if err < 0.0 {
+        1.0 - 1.0 / CURVE_STEEPNESS
+    } else {
+        CURVE_STEEPNESS - 1.0
+    };
+    (coeff * err + 1.0) * rate_at_target
+}
+
+pub fn borrow_rate(rate_at_target: f64, utilization: f64) -> f64 {
+    if rate_at_target == 0.0 {
+        curve(INITIAL_RATE_AT_TARGET, err(utilization))
+    } else {
+        curve(rate_at_target, err(utilization))
+    }
+}
+
+pub fn supply_rate(rate_at_target: f64, utilization: f64, fee: f64) -> f64 {
+    borrow_rate(rate_at_target, utilization) * utilization * (1.0 - fee)
+}
+
+// Interest is compounded continuously on Morpho
+pub fn rate_to_apy(rate_per_second: f64) -> f64 {
+    (rate_per_second * SECONDS_PER_YEAR).exp() - 1.0
+}
+
+// Snapshot of a market rate curve, enough to evaluate its rates after a supply change
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketCurve {
+    pub total_supply_assets: f64,
+    pub total_borrow_assets: f64,
+    pub rate_at_target: f64,
+    pub fee: f64,
+}
+
+impl MarketCurve {
+    pub fn utilization_with(&self, supply_delta: f64) -> f64 {
+        utilization(
+            self.total_supply_assets + supply_delta,
+            self.total_borrow_assets,
+        )
+    }
+
+    pub fn supply_apy_with(&self, supply_delta: f64) -> f64 {
+        rate_to_apy(supply_rate(
+            self.rate_at_target,
+            self.utilization_with(supply_delta),
+            self.fee,
+        ))
+    }
+    pub fn borrow_apy_with(&self, supply_delta: f64) -> f64 {
+        rate_to_apy(borrow_rate(
+            self.rate_at_target,
+            self.utilization_with(supply_delta),
+        ))
+    }
+}
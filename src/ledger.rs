@@ -0,0 +1,390 @@
+use crate::arithmetic::mul_div_down;
+use crate::error::Result;
+use crate::events::get_logs_in_ranges;
+use crate::output::{checksummed_keys, decimal};
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
+use crate::vault::IVault;
+use alloy::{
+    primitives::{Address, I256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerPosition {
+    #[serde(serialize_with = "decimal")]
+    pub shares: U256,
+    // Underlying assets paid for the shares currently held
+    #[serde(serialize_with = "decimal")]
+    pub cost_basis: U256,
+    // Deposited minus withdrawn assets
+    #[serde(serialize_with = "decimal")]
+    pub net_deposited: I256,
+    #[serde(serialize_with = "decimal")]
+    pub realized_pnl: I256,
+}
+
+impl OwnerPosition {
+    // Underlying assets (raw units) paid per vault share (10^vault decimals raw units)
+    pub fn average_entry_price(&self, decimals_offset: u8) -> Option<f64> {
+        if self.shares.is_zero() {
+            return None;
+        }
+        Some(
+            f64::from(self.cost_basis) / f64::from(self.shares)
+                * 10f64.powi(decimals_offset as i32),
+        )
+    }
+
+    pub fn unrealized_pnl(&self, current_value: U256) -> I256 {
+        I256::from_raw(current_value) - I256::from_raw(self.cost_basis)
+    }
+
+    // Removes shares from the position at the average entry price and returns their cost
+    fn remove_shares(&mut self, shares: U256) -> U256 {
+        let shares = shares.min(self.shares);
+        if shares.is_zero() {
+            return U256::ZERO;
+        }
+        let cost = mul_div_down(self.cost_basis, shares, self.shares);
+        self.shares -= shares;
+        self.cost_basis -= cost;
+        cost
+    }
+}
+
+// Per-owner accounting of a vault, built by replaying its Deposit, Withdraw, Transfer, AccrueInterest
+// and SetFeeRecipient logs
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultLedger {
+    #[serde(serialize_with = "checksummed_keys")]
+    pub positions: BTreeMap<Address, OwnerPosition>,
+    // Known once a SetFeeRecipient log is replayed, which is always the case from the vault creation
+    #[serde(skip)]
+    fee_recipient: Option<Address>,
+    // Last minted shares, which are fee shares if an AccrueInterest follows (only used while the
+    // fee recipient is unknown)
+    #[serde(skip)]
+    pending_mint: Option<(Address, U256)>,
+}
+
+impl VaultLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply_log(&mut self, log: &Log) -> Result<()> {
+        match log.topic0() {
+            Some(&IVault::Deposit::SIGNATURE_HASH) => {
+                let IVault::Deposit {
+                    sender: _sender,
+                    owner,
+                    assets,
+                    shares,
+                } = log.log_decode()?.inner.data;
+                self.pending_mint = None;
+                let position = self.positions.entry(owner).or_default();
+                position.shares += shares;
+                position.cost_basis += assets;
+                position.net_deposited += I256::from_raw(assets);
+            }
+            Some(&IVault::Withdraw::SIGNATURE_HASH) => {
+                let IVault::Withdraw {
+                    sender: _sender,
+                    receiver: _receiver,
+                    owner,
+                    assets,
+                    shares,
+                } = log.log_decode()?.inner.data;
+                let position = self.positions.entry(owner).or_default();
+                let cost = position.remove_shares(shares);
+                position.realized_pnl += I256::from_raw(assets) - I256::from_raw(cost);
+                position.net_deposited -= I256::from_raw(assets);
+            }
+            Some(&IVault::Transfer::SIGNATURE_HASH) => {
+                let IVault::Transfer { from, to, value } = log.log_decode()?.inner.data;
+                if from == Address::ZERO {
+                    // Deposits are accounted with their Deposit log
+                    self.pending_mint = Some((to, value));
+                } else if to != Address::ZERO {
+                    // Shares move with their cost basis, nothing is realized
+                    let cost = self.positions.entry(from).or_default().remove_shares(value);
+                    let receiver = self.positions.entry(to).or_default();
+                    receiver.shares += value;
+                    receiver.cost_basis += cost;
+                }
+                // Burns are accounted with their Withdraw log
+            }
+            Some(&IVault::AccrueInterest::SIGNATURE_HASH) => {
+                let IVault::AccrueInterest {
+                    newTotalAssets: _new_total_assets,
+                    feeShares,
+                } = log.log_decode()?.inner.data;
+                // Fee shares are minted to the fee recipient for free. Their Transfer log comes right
+                // before the AccrueInterest log, which only matters when the fee recipient is unknown.
+                let pending_mint = self.pending_mint.take();
+                if feeShares.is_zero() {
+                    return Ok(());
+                }
+                let fee_recipient = match (self.fee_recipient, pending_mint) {
+                    (Some(fee_recipient), _) => fee_recipient,
+                    (None, Some((to, minted))) if minted == feeShares => to,
+                    _ => return Ok(()),
+                };
+                self.positions.entry(fee_recipient).or_default().shares += feeShares;
+            }
+            Some(&IVault::SetFeeRecipient::SIGNATURE_HASH) => {
+                let IVault::SetFeeRecipient { newFeeRecipient } = log.log_decode()?.inner.data;
+                self.fee_recipient = Some(newFeeRecipient);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+// Same rounding as ERC4626.convertToAssets
+pub fn convert_to_assets(
+    shares: U256,
+    total_assets: U256,
+    total_supply: U256,
+    decimals_offset: u8,
+) -> U256 {
+    mul_div_down(
+        shares,
+        total_assets + U256::from(1),
+        total_supply + U256::from(10).pow(U256::from(decimals_offset)),
+    )
+}
+
+pub async fn build_vault_ledger(
+    rpc_url: Url,
+    vault_address: Address,
+    from_block: u64,
+) -> Result<VaultLedger> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+
+    let to_block = provider.get_block_number().await?;
+    let filter = Filter::new().address(vault_address).events([
+        IVault::Deposit::SIGNATURE_HASH,
+        IVault::Withdraw::SIGNATURE_HASH,
+        IVault::Transfer::SIGNATURE_HASH,
+        IVault::AccrueInterest::SIGNATURE_HASH,
+        IVault::SetFeeRecipient::SIGNATURE_HASH,
+    ]);
+
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        from_block,
+        to_block,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+
+    let mut ledger = VaultLedger::new();
+    for log in logs.iter() {
+        ledger.apply_log(log)?;
+    }
+    Ok(ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    const VAULT: Address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    const ALICE: Address = address!("00000000000000000000000000000000000A11CE");
+    const BOB: Address = address!("0000000000000000000000000000000000000B0B");
+    const FEE_RECIPIENT: Address = address!("000000000000000000000000000000000000FEE5");
+
+    fn log<E: SolEvent>(event: E) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: VAULT,
+                data: event.encode_log_data(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn deposit(owner: Address, assets: u64, shares: u64) -> [Log; 2] {
+        [
+            log(IVault::Transfer {
+                from: Address::ZERO,
+                to: owner,
+                value: U256::from(shares),
+            }),
+            log(IVault::Deposit {
+                sender: owner,
+                owner,
+                assets: U256::from(assets),
+                shares: U256::from(shares),
+            }),
+        ]
+    }
+
+    fn withdraw(owner: Address, assets: u64, shares: u64) -> [Log; 2] {
+        [
+            log(IVault::Transfer {
+                from: owner,
+                to: Address::ZERO,
+                value: U256::from(shares),
+            }),
+            log(IVault::Withdraw {
+                sender: owner,
+                receiver: owner,
+                owner,
+                assets: U256::from(assets),
+                shares: U256::from(shares),
+            }),
+        ]
+    }
+
+    // MetaMorpho._accrueInterest mints the fee shares before emitting AccrueInterest
+    fn accrue_interest(fee_shares: u64) -> Vec<Log> {
+        let mut logs = Vec::new();
+        if fee_shares > 0 {
+            logs.push(log(IVault::Transfer {
+                from: Address::ZERO,
+                to: FEE_RECIPIENT,
+                value: U256::from(fee_shares),
+            }));
+        }
+        logs.push(log(IVault::AccrueInterest {
+            newTotalAssets: U256::ZERO,
+            feeShares: U256::from(fee_shares),
+        }));
+        logs
+    }
+
+    fn replay<'a>(logs: impl IntoIterator<Item = &'a Log>) -> VaultLedger {
+        let mut ledger = VaultLedger::new();
+        for log in logs {
+            ledger.apply_log(log).unwrap();
+        }
+        ledger
+    }
+
+    #[test]
+    fn accumulates_cost_basis() {
+        let logs = [deposit(ALICE, 1_000, 1_000), deposit(ALICE, 500, 250)].concat();
+        let ledger = replay(&logs);
+
+        let alice = &ledger.positions[&ALICE];
+        assert_eq!(alice.shares, U256::from(1_250));
+        assert_eq!(alice.cost_basis, U256::from(1_500));
+        assert_eq!(alice.net_deposited, I256::try_from(1_500).unwrap());
+        assert_eq!(alice.average_entry_price(0), Some(1.2));
+        assert_eq!(
+            alice.unrealized_pnl(U256::from(1_400)),
+            I256::try_from(-100).unwrap()
+        );
+    }
+
+    #[test]
+    fn realizes_pnl_at_the_average_entry_price() {
+        let logs = [
+            deposit(ALICE, 1_000, 1_000),
+            deposit(ALICE, 500, 250),
+            withdraw(ALICE, 1_000, 625),
+        ]
+        .concat();
+        let ledger = replay(&logs);
+
+        // Half of the shares cost half of the 1_500 assets paid
+        let alice = &ledger.positions[&ALICE];
+        assert_eq!(alice.shares, U256::from(625));
+        assert_eq!(alice.cost_basis, U256::from(750));
+        assert_eq!(alice.realized_pnl, I256::try_from(250).unwrap());
+        assert_eq!(alice.net_deposited, I256::try_from(500).unwrap());
+    }
+
+    #[test]
+    fn transfers_carry_cost_basis() {
+        let mut logs = deposit(ALICE, 1_000, 500).to_vec();
+        logs.push(log(IVault::Transfer {
+            from: ALICE,
+            to: BOB,
+            value: U256::from(100),
+        }));
+        logs.extend(withdraw(BOB, 300, 100));
+        let ledger = replay(&logs);
+
+        let alice = &ledger.positions[&ALICE];
+        assert_eq!(alice.shares, U256::from(400));
+        assert_eq!(alice.cost_basis, U256::from(800));
+        assert_eq!(alice.realized_pnl, I256::ZERO);
+
+        // Bob did not deposit but realizes the gain on the 200 assets of cost he received
+        let bob = &ledger.positions[&BOB];
+        assert_eq!(bob.shares, U256::ZERO);
+        assert_eq!(bob.cost_basis, U256::ZERO);
+        assert_eq!(bob.realized_pnl, I256::try_from(100).unwrap());
+        assert_eq!(bob.net_deposited, I256::try_from(-300).unwrap());
+    }
+
+    #[test]
+    fn credits_fee_shares_in_metamorpho_log_order() {
+        // A deposit accrues interest first: fee mint, AccrueInterest, deposit mint, Deposit
+        let logs = [
+            deposit(ALICE, 1_000, 1_000).to_vec(),
+            accrue_interest(10),
+            deposit(ALICE, 1_000, 990).to_vec(),
+            accrue_interest(0),
+            withdraw(ALICE, 100, 100).to_vec(),
+        ]
+        .concat();
+        let ledger = replay(&logs);
+
+        let fee_recipient = &ledger.positions[&FEE_RECIPIENT];
+        assert_eq!(fee_recipient.shares, U256::from(10));
+        assert_eq!(fee_recipient.cost_basis, U256::ZERO);
+        assert_eq!(ledger.positions[&ALICE].shares, U256::from(1_890));
+    }
+
+    #[test]
+    fn credits_fee_shares_to_the_known_fee_recipient() {
+        // Once the fee recipient is known the fee shares do not depend on the Transfer log
+        let logs = [
+            log(IVault::SetFeeRecipient {
+                newFeeRecipient: FEE_RECIPIENT,
+            }),
+            log(IVault::AccrueInterest {
+                newTotalAssets: U256::ZERO,
+                feeShares: U256::from(10),
+            }),
+            log(IVault::Transfer {
+                from: Address::ZERO,
+                to: FEE_RECIPIENT,
+                value: U256::from(10),
+            }),
+        ];
+        let ledger = replay(&logs);
+
+        assert_eq!(ledger.positions[&FEE_RECIPIENT].shares, U256::from(10));
+    }
+
+    #[test]
+    fn does_not_count_deposit_mints_as_fee_shares() {
+        // The deposit mint is followed by its Deposit log, not by an AccrueInterest
+        let logs = [
+            deposit(ALICE, 1_000, 10).to_vec(),
+            accrue_interest(0),
+            deposit(BOB, 1_000, 10).to_vec(),
+        ]
+        .concat();
+        let ledger = replay(&logs);
+
+        assert!(!ledger.positions.contains_key(&FEE_RECIPIENT));
+        assert_eq!(ledger.positions[&ALICE].shares, U256::from(10));
+        assert_eq!(ledger.positions[&BOB].shares, U256::from(10));
+    }
+}
@@ -0,0 +1,35 @@
+//! Morpho Blue and MetaMorpho snippets as a library: typed reads of markets, vaults, events and API prices,
+//! transaction builders and the protocol math.
+
+pub mod alerts;
+pub mod allocation;
+pub mod api;
+pub mod arithmetic;
+pub mod authorization;
+pub mod deviation;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod governance;
+pub mod history;
+pub mod indexer;
+pub mod irm;
+pub mod ledger;
+pub mod market;
+pub mod market_id;
+pub mod metrics;
+pub mod multicall;
+pub mod oracle;
+pub mod output;
+pub mod performance;
+pub mod registry;
+pub mod revert;
+pub mod server;
+pub mod storage;
+pub mod transactions;
+pub mod units;
+pub mod vault;
+pub mod vault_transactions;
+pub mod views;
+
+pub use error::{Error, Result};
@@ -1,28 +1,741 @@
-use alloy::transports::http::reqwest::Url;
-use eyre::Result;
-mod api;
-mod arithmetic;
-mod market;
-mod vault;
+mod snippets;
+
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    primitives::{Address, B256},
+    providers::{Provider, ProviderBuilder},
+    transports::http::reqwest::Url,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use eyre::{eyre, Result};
+use morpho_rust_snippets::{
+    alerts::{self, AlertConfig, AlertEngine, Fixture},
+    api, events,
+    export::{export_events, ExportOptions, UintEncoding},
+    indexer::{EventQuery, Indexer, Recording, SyncOptions},
+    market::{self, MarketInfo, Position},
+    metrics::MetricsTargets,
+    output::{Format, Output},
+    server::{self, ServeOptions},
+    units, vault,
+};
+use serde::Serialize;
+use std::{collections::HashMap, io, net::SocketAddr, path::PathBuf, time::Duration};
+
+// lltv and fees are WAD-scaled
+const WAD_DECIMALS: u8 = 18;
+
+// You can change the RPC provider
+const DEFAULT_RPC_URL: &str =
+    "https://eth-mainnet.g.alchemy.com/v2/YOTcUDy_k90iZVmkAtMgxzgWOtcc_z3J";
+
+#[derive(Parser)]
+#[command(
+    name = "morpho",
+    about = "Query Morpho Blue markets, MetaMorpho vaults and their events"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// RPC endpoint, `watch` needs a websocket one (http URLs are switched to ws)
+    #[arg(long, global = true, env = "MORPHO_RPC_URL", default_value = DEFAULT_RPC_URL)]
+    rpc_url: String,
+
+    /// Chain id, checked against the RPC
+    #[arg(long, global = true, default_value_t = 1)]
+    chain: u64,
+
+    /// Output format: text, table, json (one object per line) or csv
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Render amounts in token units instead of raw units (table, json and csv outputs, not watch)
+    #[arg(long, global = true)]
+    scale: bool,
+
+    /// Block of historical queries (latest by default), `vault list` only reads the latest state
+    #[arg(long, global = true)]
+    block: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Table,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    // None for the human readable text
+    fn format(self) -> Option<Format> {
+        match self {
+            OutputFormat::Text => None,
+            OutputFormat::Table => Some(Format::Table),
+            OutputFormat::Json => Some(Format::JsonLines),
+            OutputFormat::Csv => Some(Format::Csv),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Morpho Blue markets
+    Market {
+        #[command(subcommand)]
+        command: MarketCommand,
+    },
+    /// Morpho Blue events on a block range
+    Events {
+        #[arg(long)]
+        from: u64,
+        /// Defaults to --block, or the latest block
+        #[arg(long)]
+        to: Option<u64>,
+    },
+    /// MetaMorpho vaults
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// USD prices of the assets listed by the Morpho API
+    Prices,
+    /// Print Morpho Blue events as they are emitted
+    Watch,
+    /// Index events, markets, vaults and positions in SQLite, and query them
+    Index {
+        /// SQLite database
+        #[arg(long, default_value = "morpho.db")]
+        db: PathBuf,
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// Serve markets, positions, vaults and prices as JSON over HTTP
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Seconds after which a cached response is fetched again
+        #[arg(long, default_value_t = 60)]
+        max_age: u64,
+        /// Market published on /metrics, can be repeated
+        #[arg(long = "market")]
+        markets: Vec<B256>,
+        /// Vault published on /metrics, can be repeated
+        #[arg(long = "vault")]
+        vaults: Vec<Address>,
+        /// Position published on /metrics as <MARKET_ID>:<USER>, can be repeated
+        #[arg(long = "position", value_parser = parse_position)]
+        positions: Vec<(B256, Address)>,
+    },
+    /// Raise alerts from rules on the Morpho and vault events and the market states
+    Alerts {
+        /// Rules and sinks, in TOML
+        #[arg(long, default_value = "alerts.toml")]
+        rules: PathBuf,
+        #[command(subcommand)]
+        command: AlertsCommand,
+    },
+    /// Run every snippet with its example arguments
+    Snippets,
+}
+
+#[derive(Subcommand)]
+enum AlertsCommand {
+    /// Evaluate the rules on the live events and periodic snapshots of the markets
+    Run,
+    /// Evaluate the rules on scripted events and snapshots, without any RPC, and check the raised alerts
+    Replay { fixture: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Index Morpho, the vault factories of the chain and their vaults up to the latest block
+    Sync {
+        #[arg(long, default_value_t = 3)]
+        confirmations: u64,
+        #[arg(long, default_value_t = 10_000)]
+        max_block_range: u64,
+        /// Also write the fetched batches to a recording, to replay them offline
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Ingest a recording, without any RPC
+    Replay { path: PathBuf },
+    /// Remove everything indexed after a block
+    Rollback { block: u64 },
+    /// Last indexed block of every contract
+    Checkpoints,
+    /// Indexed markets
+    Markets,
+    /// Indexed vaults
+    Vaults,
+    /// Positions derived from the indexed events
+    Positions {
+        #[arg(long)]
+        market: Option<B256>,
+        #[arg(long)]
+        user: Option<Address>,
+    },
+    /// Vault share balances derived from the indexed transfers
+    Balances { vault: Address },
+    /// Indexed events
+    Events {
+        #[arg(long)]
+        address: Option<Address>,
+        /// Event name, e.g. Liquidate
+        #[arg(long)]
+        event: Option<String>,
+        #[arg(long)]
+        market: Option<B256>,
+        #[arg(long)]
+        from: Option<u64>,
+        #[arg(long)]
+        to: Option<u64>,
+        #[arg(long)]
+        limit: Option<u64>,
+    },
+    /// Write the indexed events as Parquet files partitioned by contract kind, event and block range
+    Export {
+        /// Output directory, files of the exported ranges are replaced
+        #[arg(long, default_value = "events")]
+        dir: PathBuf,
+        /// First block, at the start of a partition
+        #[arg(long)]
+        from: Option<u64>,
+        /// Last block, at the end of a partition
+        #[arg(long)]
+        to: Option<u64>,
+        /// Blocks per file
+        #[arg(long, default_value_t = 100_000)]
+        partition_blocks: u64,
+        /// Store uint256 parameters as 32 bytes big-endian instead of decimal strings
+        #[arg(long)]
+        binary_uints: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MarketCommand {
+    /// Params, state, oracle price and borrow rate of a market, and optionally a position
+    Info {
+        id: B256,
+        #[arg(long)]
+        user: Option<Address>,
+    },
+    /// Every market created on Morpho Blue
+    List,
+}
+
+#[derive(Subcommand)]
+enum VaultCommand {
+    /// State and roles of a vault
+    Info { address: Address },
+    /// Vaults created by the known factories of the chain
+    List {
+        /// SQLite database keeping the registry, only the vaults created since the last run are then fetched
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// Deposits, withdrawals, transfers and interest accruals of a vault
+    Activity {
+        address: Address,
+        #[arg(long)]
+        from: u64,
+        #[arg(long)]
+        to: Option<u64>,
+    },
+}
+
+impl Cli {
+    fn block_id(&self) -> BlockId {
+        self.block.map(BlockId::number).unwrap_or(BlockId::latest())
+    }
+
+    fn block_tag(&self) -> BlockNumberOrTag {
+        self.block
+            .map(BlockNumberOrTag::Number)
+            .unwrap_or(BlockNumberOrTag::Latest)
+    }
+
+    // Checks that the RPC serves the requested chain
+    async fn http_url(&self) -> Result<Url> {
+        let rpc_url: Url = self.rpc_url.parse()?;
+        let chain_id = ProviderBuilder::new()
+            .on_http(rpc_url.clone())
+            .get_chain_id()
+            .await?;
+        if chain_id != self.chain {
+            return Err(eyre!(
+                "the RPC serves chain {} instead of {}",
+                chain_id,
+                self.chain
+            ));
+        }
+        Ok(rpc_url)
+    }
+
+    fn ws_url(&self) -> String {
+        self.rpc_url
+            .replacen("https://", "wss://", 1)
+            .replacen("http://", "ws://", 1)
+    }
+
+    async fn to_block(&self, rpc_url: &Url, to: Option<u64>) -> Result<u64> {
+        match to.or(self.block) {
+            Some(to) => Ok(to),
+            None => Ok(ProviderBuilder::new()
+                .on_http(rpc_url.clone())
+                .get_block_number()
+                .await?),
+        }
+    }
+}
+
+// <MARKET_ID>:<USER>
+fn parse_position(value: &str) -> std::result::Result<(B256, Address), String> {
+    let (market_id, user) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected <MARKET_ID>:<USER>, got {}", value))?;
+    Ok((
+        market_id
+            .parse()
+            .map_err(|e| format!("invalid market id: {}", e))?,
+        user.parse().map_err(|e| format!("invalid user: {}", e))?,
+    ))
+}
+
+// A market and optionally a position on it, as one record
+#[derive(Serialize)]
+struct MarketWithPosition<'a> {
+    #[serde(flatten)]
+    market: &'a MarketInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<&'a Position>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // You can change the RPC provider
-    let rpc_url: Url =
-        "https://eth-mainnet.g.alchemy.com/v2/YOTcUDy_k90iZVmkAtMgxzgWOtcc_z3J".parse()?;
-    let wss_url = "wss://eth-mainnet.g.alchemy.com/v2/YOTcUDy_k90iZVmkAtMgxzgWOtcc_z3J";
-
-    market::retrieve_market_info(rpc_url.clone()).await?;
-    market::retrieve_markets(rpc_url.clone()).await?;
-    market::retrieve_events_with_logs(rpc_url.clone()).await?;
-    vault::retrieve_vault_details(rpc_url.clone()).await?;
-    vault::retrieve_vaults(rpc_url.clone()).await?;
-    vault::retrieve_vault_activity_details(rpc_url.clone()).await?;
+    let cli = Cli::parse();
+    let mut out = cli
+        .output
+        .format()
+        .map(|format| Output::new(io::stdout().lock(), format));
 
-    let prices = api::get_usd_prices().await?;
-    println!("{:?}", prices);
+    match &cli.command {
+        Command::Market {
+            command: MarketCommand::Info { id, user },
+        } => {
+            let rpc_url = cli.http_url().await?;
+            let info = market::fetch_market_info(rpc_url.clone(), *id, cli.block_id()).await?;
+            let position = match user {
+                Some(user) => {
+                    Some(market::fetch_position(rpc_url.clone(), *id, *user, cli.block_id()).await?)
+                }
+                None => None,
+            };
+            match &mut out {
+                None => {
+                    println!(
+                        "Market {:#32x}\n- Loan asset: {:#20x}\n- Collateral asset: {:#20x}\n- Oracle: {:#20x}\n- IRM: {:#20x}\n- LLTV: {}\n- Total supply assets: {}\n- Total borrow assets: {}\n- Fee: {}\n- Last update: {}\n- Oracle price: {}\n- Borrow rate: {}",
+                        info.id,
+                        info.params.loanToken,
+                        info.params.collateralToken,
+                        info.params.oracle,
+                        info.params.irm,
+                        info.params.lltv,
+                        info.market.totalSupplyAssets,
+                        info.market.totalBorrowAssets,
+                        info.market.fee,
+                        info.market.lastUpdate,
+                        info.price,
+                        info.borrow_rate
+                    );
+                    if let Some(position) = &position {
+                        println!(
+                            "User {:#20x} position:\n- Collateral: {}\n- Borrow shares: {}\n- Supply shares: {}",
+                            position.user, position.collateral, position.borrow_shares, position.supply_shares
+                        );
+                    }
+                }
+                Some(out) => {
+                    let mut scale = vec![];
+                    if cli.scale {
+                        let (loan, collateral) =
+                            units::retrieve_market_tokens(rpc_url, *id).await?;
+                        scale = vec![
+                            ("lltv", WAD_DECIMALS),
+                            ("fee", WAD_DECIMALS),
+                            ("totalSupplyAssets", loan.decimals),
+                            ("totalBorrowAssets", loan.decimals),
+                            ("position.collateral", collateral.decimals),
+                        ];
+                    }
+                    let record = MarketWithPosition {
+                        market: &info,
+                        position: position.as_ref(),
+                    };
+                    out.write(&record, &scale)?;
+                }
+            }
+        }
+        Command::Market {
+            command: MarketCommand::List,
+        } => {
+            let rpc_url = cli.http_url().await?;
+            let scale: &[(&str, u8)] = if cli.scale {
+                &[("lltv", WAD_DECIMALS)]
+            } else {
+                &[]
+            };
+            for record in market::fetch_markets(rpc_url, cli.block_tag()).await? {
+                match &mut out {
+                    None => println!(
+                        "Market {:#32x}: collateral {:#20x}, loan {:#20x}, lltv {}, oracle {:#20x}, irm {:#20x}",
+                        record.id, record.params.collateralToken, record.params.loanToken, record.params.lltv, record.params.oracle, record.params.irm
+                    ),
+                    Some(out) => out.write(&record, scale)?,
+                }
+            }
+        }
+        Command::Events { from, to } => {
+            let rpc_url = cli.http_url().await?;
+            let to = cli.to_block(&rpc_url, *to).await?;
+            // (loan, collateral) decimals of the markets seen so far
+            let mut decimals: HashMap<B256, (u8, u8)> = HashMap::new();
+            for log in events::fetch_morpho_events(rpc_url.clone(), *from, to).await? {
+                match &mut out {
+                    None => {
+                        if let Some(description) = events::describe_morpho_event(&log.event) {
+                            println!("Block {:?}: {}", log.block_number, description);
+                        }
+                    }
+                    Some(out) => {
+                        let mut scale = vec![];
+                        if let (true, Some(id)) =
+                            (cli.scale, events::morpho_event_market_id(&log.event))
+                        {
+                            let (loan, collateral) = match decimals.get(&id) {
+                                Some(known) => *known,
+                                None => {
+                                    let (loan, collateral) =
+                                        units::retrieve_market_tokens(rpc_url.clone(), id).await?;
+                                    decimals.insert(id, (loan.decimals, collateral.decimals));
+                                    (loan.decimals, collateral.decimals)
+                                }
+                            };
+                            let (loan_fields, collateral_fields) =
+                                events::morpho_event_amount_fields(&log.event);
+                            scale.extend(loan_fields.iter().map(|field| (*field, loan)));
+                            scale
+                                .extend(collateral_fields.iter().map(|field| (*field, collateral)));
+                        }
+                        out.write(&log, &scale)?;
+                    }
+                }
+            }
+        }
+        Command::Vault {
+            command: VaultCommand::Info { address },
+        } => {
+            let rpc_url = cli.http_url().await?;
+            let info = vault::fetch_vault_info(rpc_url.clone(), *address, cli.block_id()).await?;
+            match &mut out {
+                None => println!(
+                    "Vault {} ({}) at {:#20x}\n- Asset: {:#20x}\n- Total assets: {}\n- Total supply: {}\n- Owner: {:#20x}\n- Curator: {:#20x}\n- Guardian: {:#20x}\n- Timelock: {}",
+                    info.name,
+                    info.symbol,
+                    info.address,
+                    info.asset,
+                    info.total_assets,
+                    info.total_supply,
+                    info.owner,
+                    info.curator,
+                    info.guardian,
+                    info.timelock
+                ),
+                Some(out) => {
+                    let mut scale = vec![];
+                    if cli.scale {
+                        let asset = units::retrieve_token_info(rpc_url, info.asset).await?;
+                        scale = vec![
+                            ("totalAssets", asset.decimals),
+                            ("totalSupply", info.decimals),
+                        ];
+                    }
+                    out.write(&info, &scale)?;
+                }
+            }
+        }
+        Command::Vault {
+            command: VaultCommand::List { db },
+        } => {
+            // The registry is always refreshed up to the latest block and reads the current roles
+            if cli.block.is_some() {
+                return Err(eyre!("vault list does not support --block"));
+            }
+            let rpc_url = cli.http_url().await?;
+            let records = match db {
+                Some(db) => {
+                    let mut indexer = Indexer::open(db)?;
+                    let mut registry = indexer.load_registry(cli.chain)?;
+                    let refreshed = registry.refresh(rpc_url).await;
+                    // The ranges indexed before a failure are kept
+                    indexer.save_registry(&registry)?;
+                    refreshed?;
+                    registry.vaults.into_values().collect()
+                }
+                None => vault::fetch_vaults(rpc_url, cli.chain).await?,
+            };
+            for record in records {
+                match &mut out {
+                    None => println!(
+                        "Vault {} ({}) {} at {:#20x} for asset {:#20x}, curated by {:#20x}",
+                        record.name,
+                        record.symbol,
+                        record.version,
+                        record.address,
+                        record.asset,
+                        record.curator
+                    ),
+                    Some(out) => out.write(&record, &[])?,
+                }
+            }
+        }
+        Command::Vault {
+            command: VaultCommand::Activity { address, from, to },
+        } => {
+            let rpc_url = cli.http_url().await?;
+            let to = cli.to_block(&rpc_url, *to).await?;
+            // (asset, share) decimals
+            let mut decimals = None;
+            if cli.scale && out.is_some() {
+                let info =
+                    vault::fetch_vault_info(rpc_url.clone(), *address, cli.block_id()).await?;
+                let asset = units::retrieve_token_info(rpc_url.clone(), info.asset).await?;
+                decimals = Some((asset.decimals, info.decimals));
+            }
+            for log in events::fetch_vault_events(rpc_url, *address, *from, to).await? {
+                match &mut out {
+                    None => {
+                        if let Some(description) = events::describe_vault_event(&log.event) {
+                            println!("Block {:?}: {}", log.block_number, description);
+                        }
+                    }
+                    Some(out) => {
+                        let mut scale = vec![];
+                        if let Some((asset, shares)) = decimals {
+                            let (asset_fields, share_fields) =
+                                events::vault_event_amount_fields(&log.event);
+                            scale.extend(asset_fields.iter().map(|field| (*field, asset)));
+                            scale.extend(share_fields.iter().map(|field| (*field, shares)));
+                        }
+                        out.write(&log, &scale)?;
+                    }
+                }
+            }
+        }
+        Command::Prices => {
+            // Prices come from the API, which has no historical or RPC parameter
+            for asset in api::get_usd_prices().await? {
+                if asset.chain != cli.chain {
+                    continue;
+                }
+                match &mut out {
+                    None => println!(
+                        "{} ({:#20x}): {:?} USD",
+                        asset.symbol, asset.token, asset.price
+                    ),
+                    Some(out) => out.write(&asset, &[])?,
+                }
+            }
+        }
+        Command::Watch => {
+            // Tables and CSV are written once every record is known, which never happens for a stream
+            if !matches!(cli.output, OutputFormat::Text | OutputFormat::Json) {
+                return Err(eyre!("watch streams events, use --output text or json"));
+            }
+            let mut error = None;
+            events::watch_morpho_events(&cli.ws_url(), |log| match &mut out {
+                None => {
+                    if let Some(description) = events::describe_morpho_event(&log.event) {
+                        println!("{}", description);
+                    }
+                }
+                Some(out) => {
+                    if let Err(e) = out.write(&log, &[]) {
+                        error.get_or_insert(e);
+                    }
+                }
+            })
+            .await?;
+            if let Some(error) = error {
+                return Err(error.into());
+            }
+        }
+        Command::Index { db, command } => {
+            let mut indexer = Indexer::open(db)?;
+            // The indexed data is printed as a table by default
+            let out = out.get_or_insert_with(|| Output::new(io::stdout().lock(), Format::Table));
+            match command {
+                IndexCommand::Sync {
+                    confirmations,
+                    max_block_range,
+                    record,
+                } => {
+                    let rpc_url = cli.http_url().await?;
+                    indexer.track_defaults(cli.chain)?;
+                    let options = SyncOptions {
+                        confirmations: *confirmations,
+                        max_block_range: *max_block_range,
+                    };
+                    let mut recording = Recording::default();
+                    let added = indexer
+                        .sync(rpc_url, options, record.as_ref().map(|_| &mut recording))
+                        .await?;
+                    if let Some(path) = record {
+                        recording.save(path)?;
+                    }
+                    eprintln!("Indexed {} new events", added);
+                    for checkpoint in indexer.checkpoints()? {
+                        out.write(&checkpoint, &[])?;
+                    }
+                }
+                IndexCommand::Replay { path } => {
+                    let added = indexer.replay(&Recording::load(path)?)?;
+                    eprintln!("Indexed {} new events", added);
+                    for checkpoint in indexer.checkpoints()? {
+                        out.write(&checkpoint, &[])?;
+                    }
+                }
+                IndexCommand::Rollback { block } => {
+                    indexer.rollback(*block)?;
+                    for checkpoint in indexer.checkpoints()? {
+                        out.write(&checkpoint, &[])?;
+                    }
+                }
+                IndexCommand::Checkpoints => {
+                    for checkpoint in indexer.checkpoints()? {
+                        out.write(&checkpoint, &[])?;
+                    }
+                }
+                IndexCommand::Markets => {
+                    for market in indexer.markets()? {
+                        out.write(&market, &[])?;
+                    }
+                }
+                IndexCommand::Vaults => {
+                    for vault in indexer.vaults()? {
+                        out.write(&vault, &[])?;
+                    }
+                }
+                IndexCommand::Positions { market, user } => {
+                    for position in indexer.positions(*market, *user)? {
+                        out.write(&position, &[])?;
+                    }
+                }
+                IndexCommand::Balances { vault } => {
+                    for balance in indexer.vault_balances(*vault)? {
+                        out.write(&balance, &[])?;
+                    }
+                }
+                IndexCommand::Events {
+                    address,
+                    event,
+                    market,
+                    from,
+                    to,
+                    limit,
+                } => {
+                    let query = EventQuery {
+                        address: *address,
+                        event: event.clone(),
+                        market_id: *market,
+                        from_block: *from,
+                        to_block: *to,
+                        limit: *limit,
+                    };
+                    for event in indexer.events(&query)? {
+                        out.write(&event, &[])?;
+                    }
+                }
+                IndexCommand::Export {
+                    dir,
+                    from,
+                    to,
+                    partition_blocks,
+                    binary_uints,
+                } => {
+                    let options = ExportOptions {
+                        partition_blocks: *partition_blocks,
+                        uint_encoding: if *binary_uints {
+                            UintEncoding::Binary
+                        } else {
+                            UintEncoding::Decimal
+                        },
+                    };
+                    for file in export_events(&indexer, *from, *to, dir, &options)? {
+                        out.write(&file, &[])?;
+                    }
+                }
+            }
+        }
+        Command::Serve {
+            listen,
+            max_age,
+            markets,
+            vaults,
+            positions,
+        } => {
+            eprintln!("Serving on http://{}", listen);
+            server::serve(
+                cli.http_url().await?,
+                cli.ws_url(),
+                cli.chain,
+                *listen,
+                ServeOptions {
+                    max_age: Duration::from_secs(*max_age),
+                    metrics: MetricsTargets {
+                        markets: markets.clone(),
+                        vaults: vaults.clone(),
+                        positions: positions.clone(),
+                    },
+                },
+            )
+            .await?
+        }
+        Command::Alerts { rules, command } => {
+            let config = AlertConfig::load(rules)?;
+            match command {
+                AlertsCommand::Run => {
+                    alerts::run_alerts(cli.http_url().await?, cli.ws_url(), config).await?
+                }
+                AlertsCommand::Replay { fixture } => {
+                    let fixture = Fixture::load(fixture)?;
+                    let mut sinks = alerts::open_sinks(&config.sinks)?;
+                    let mut engine = AlertEngine::new(config.rules);
+                    let raised = alerts::replay_fixture(&mut engine, &fixture)?;
+                    for alert in &raised {
+                        alerts::deliver(&mut sinks, alert).await;
+                    }
 
-    market::subscribe_to_event_logs(wss_url).await?;
+                    let rules: Vec<&str> = raised.iter().map(|alert| alert.rule.as_str()).collect();
+                    if rules != fixture.expected {
+                        return Err(eyre!(
+                            "expected alerts {:?}, got {:?}",
+                            fixture.expected,
+                            rules
+                        ));
+                    }
+                    eprintln!("{} alerts raised as expected", raised.len());
+                }
+            }
+        }
+        Command::Snippets => snippets::run(cli.http_url().await?, &cli.ws_url()).await?,
+    }
 
+    if let Some(out) = out {
+        out.finish()?;
+    }
     Ok(())
 }
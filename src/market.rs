@@ -1,17 +1,20 @@
-use crate::arithmetic::{w_mul_down, w_taylor_compounded};
-use alloy::providers::WsConnect;
+use crate::arithmetic::{to_shares_down, w_mul_down, w_taylor_compounded};
+use crate::error::{Error, Result};
+use crate::events::get_logs_in_ranges;
+use crate::output::{checksummed, decimal, decimal_option};
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
 use alloy::transports::http::reqwest::Url;
 use alloy::{
-    eips::BlockNumberOrTag,
-    primitives::{address, B256, U256},
+    eips::{BlockId, BlockNumberOrTag},
+    network::Ethereum,
+    primitives::{address, Address, B256, U256},
     providers::{Provider, ProviderBuilder},
-    rpc::types::Filter,
+    rpc::types::{BlockTransactionsKind, Filter},
     sol,
     sol_types::SolEvent,
+    transports::Transport,
 };
-use eyre::Result;
-use futures_util::stream::StreamExt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Serializer};
 use IIRM::{Market, MarketParams};
 
 // Code gen
@@ -32,385 +35,404 @@ sol!(
     "data/abis/adaptive_curve_irm.json"
 );
 
-pub async fn retrieve_market_info(rpc_url: Url) -> Result<()> {
-    let provider = ProviderBuilder::new().on_http(rpc_url);
+sol!(
+    #[sol(rpc)]
+    IERC20,
+    "data/abis/erc20.json"
+);
+
+// The morpho contract address (it contains all positions)
+pub const MORPHO_ADDRESS: Address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+
+#[derive(Debug, Clone, Copy)]
+pub struct MorphoDeployment {
+    pub chain_id: u64,
+    pub deployment_block: u64,
+}
+
+// Chains where Morpho is deployed at MORPHO_ADDRESS, logs are scanned from the deployment block
+pub const MORPHO_DEPLOYMENTS: [MorphoDeployment; 2] = [
+    MorphoDeployment {
+        chain_id: 1,
+        deployment_block: 18_883_124,
+    },
+    MorphoDeployment {
+        chain_id: 8453,
+        deployment_block: 13_977_148,
+    },
+];
+
+pub fn morpho_deployment_block(chain_id: u64) -> Result<u64> {
+    MORPHO_DEPLOYMENTS
+        .iter()
+        .find(|deployment| deployment.chain_id == chain_id)
+        .map(|deployment| deployment.deployment_block)
+        .ok_or_else(|| Error::Config(format!("no known Morpho deployment on chain {}", chain_id)))
+}
 
-    // The morpho contract address (it contains all positions)
-    let morpho_address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
-    let morpho = IMorpho::new(morpho_address, provider.clone());
+// Number of a block given by number or by tag (latest, finalized, ...)
+pub(crate) async fn resolve_block_number<T, P>(provider: &P, block: BlockNumberOrTag) -> Result<u64>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum>,
+{
+    match block {
+        BlockNumberOrTag::Number(number) => Ok(number),
+        tag => Ok(provider
+            .get_block_by_number(tag, BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("block {}", tag)))?
+            .header
+            .number),
+    }
+}
+
+#[derive(Clone)]
+pub struct MarketInfo {
+    pub id: B256,
+    pub params: IMorpho::MarketParams,
+    // Note that some interest might be lacking, see `accrued_interest`
+    pub market: IMorpho::marketReturn,
+    pub price: U256,
+    // Average borrow rate per second since the last update
+    pub borrow_rate: U256,
+}
+
+// Flat serialized form of a market, shared by `MarketInfo`, `MarketRecord` and `multicall::MarketState`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MarketRow {
+    id: B256,
+    #[serde(serialize_with = "checksummed")]
+    loan_token: Address,
+    #[serde(serialize_with = "checksummed")]
+    collateral_token: Address,
+    #[serde(serialize_with = "checksummed")]
+    oracle: Address,
+    #[serde(serialize_with = "checksummed")]
+    irm: Address,
+    #[serde(serialize_with = "decimal")]
+    lltv: U256,
+    #[serde(flatten)]
+    state: Option<MarketStateRow>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MarketStateRow {
+    #[serde(serialize_with = "decimal")]
+    total_supply_assets: u128,
+    #[serde(serialize_with = "decimal")]
+    total_supply_shares: u128,
+    #[serde(serialize_with = "decimal")]
+    total_borrow_assets: u128,
+    #[serde(serialize_with = "decimal")]
+    total_borrow_shares: u128,
+    #[serde(serialize_with = "decimal")]
+    last_update: u128,
+    #[serde(serialize_with = "decimal")]
+    fee: u128,
+    #[serde(serialize_with = "decimal_option")]
+    price: Option<U256>,
+    #[serde(serialize_with = "decimal_option")]
+    borrow_rate: Option<U256>,
+}
 
-    // Getting market information
-    // You can change this market id
-    let market_id: B256 =
-        "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?; // USD0++/USDC (86%) with AdaptiveCurve
-    let market_params = morpho.idToMarketParams(market_id).call().await?;
-    let market_data = morpho.market(market_id).call().await?;
+impl MarketRow {
+    pub(crate) fn new(id: B256, params: &IMorpho::MarketParams) -> Self {
+        MarketRow {
+            id,
+            loan_token: params.loanToken,
+            collateral_token: params.collateralToken,
+            oracle: params.oracle,
+            irm: params.irm,
+            lltv: params.lltv,
+            state: None,
+        }
+    }
 
-    println!(
-        "Market with id {:#32x} was updated for the last time at timestamp {}",
-        market_id, market_data.lastUpdate
+    pub(crate) fn with_state(
+        mut self,
+        market: &IMorpho::marketReturn,
+        price: Option<U256>,
+        borrow_rate: Option<U256>,
+    ) -> Self {
+        self.state = Some(MarketStateRow {
+            total_supply_assets: market.totalSupplyAssets,
+            total_supply_shares: market.totalSupplyShares,
+            total_borrow_assets: market.totalBorrowAssets,
+            total_borrow_shares: market.totalBorrowShares,
+            last_update: market.lastUpdate,
+            fee: market.fee,
+            price,
+            borrow_rate,
+        });
+        self
+    }
+}
+
+impl Serialize for MarketInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        MarketRow::new(self.id, &self.params)
+            .with_state(&self.market, Some(self.price), Some(self.borrow_rate))
+            .serialize(serializer)
+    }
+}
+
+// A market created on Morpho, from its CreateMarket event
+#[derive(Clone)]
+pub struct MarketRecord {
+    pub id: B256,
+    pub params: IMorpho::MarketParams,
+}
+
+impl Serialize for MarketRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        MarketRow::new(self.id, &self.params).serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub market_id: B256,
+    #[serde(serialize_with = "checksummed")]
+    pub user: Address,
+    #[serde(serialize_with = "decimal")]
+    pub supply_shares: U256,
+    #[serde(serialize_with = "decimal")]
+    pub borrow_shares: u128,
+    #[serde(serialize_with = "decimal")]
+    pub collateral: u128,
+}
+
+impl MarketInfo {
+    // Interest accrued between the last update and `timestamp`
+    pub fn accrued_interest(&self, timestamp: u64) -> U256 {
+        let elapsed = U256::from(timestamp).saturating_sub(U256::from(self.market.lastUpdate));
+        w_mul_down(
+            U256::from(self.market.totalBorrowAssets),
+            w_taylor_compounded(self.borrow_rate, elapsed),
+        )
+    }
+}
+
+// Market state once the interest until `timestamp` is accrued, with the fee shares, like Morpho._accrueInterest.
+// `borrow_rate` is the IRM rate per second (zero for markets without IRM).
+pub fn accrue_interest(
+    market: &IMorpho::marketReturn,
+    borrow_rate: U256,
+    timestamp: u64,
+) -> IMorpho::marketReturn {
+    let elapsed = U256::from(timestamp).saturating_sub(U256::from(market.lastUpdate));
+    let interest = w_mul_down(
+        U256::from(market.totalBorrowAssets),
+        w_taylor_compounded(borrow_rate, elapsed),
     );
-    println!(
-        "Market Params:\n- Collateral asset: {:#20x} \n- Loan asset: {:#20x} \n- LLTV: {} \n- Oracle: {:#20x} \n- IRM: {:#20x}",
-        market_params.collateralToken,
-        market_params.loanToken,
-        market_params.lltv,
-        market_params.oracle,
-        market_params.irm
+    let total_supply_assets = U256::from(market.totalSupplyAssets) + interest;
+    let fee_amount = w_mul_down(interest, U256::from(market.fee));
+    let fee_shares = to_shares_down(
+        fee_amount,
+        total_supply_assets - fee_amount,
+        U256::from(market.totalSupplyShares),
     );
+    IMorpho::marketReturn {
+        totalSupplyAssets: total_supply_assets.saturating_to(),
+        totalSupplyShares: (U256::from(market.totalSupplyShares) + fee_shares).saturating_to(),
+        totalBorrowAssets: (U256::from(market.totalBorrowAssets) + interest).saturating_to(),
+        totalBorrowShares: market.totalBorrowShares,
+        lastUpdate: market.lastUpdate.max(timestamp as u128),
+        fee: market.fee,
+    }
+}
 
-    // Note that some interest might be lacking
-    println!(
-        "Market Data:\n- Fee: {} \n- Total borrow assets: {} \n- Total borrow shares: {} \n- Total supply assets: {} \n- Total supply shares: {}",
-        market_data.fee,
-        market_data.totalBorrowAssets,
-        market_data.totalBorrowShares,
-        market_data.totalSupplyAssets,
-        market_data.totalSupplyShares
-    );
+async fn fetch_market_info_with<T, P>(
+    provider: &P,
+    market_id: B256,
+    block: BlockId,
+) -> Result<MarketInfo>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum> + Clone,
+{
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+    let market_data = morpho.market(market_id).block(block).call().await?;
+    // createMarket sets the last update, it is zero for unknown ids
+    if market_data.lastUpdate == 0 {
+        return Err(Error::NotFound(format!("market {:#32x}", market_id)));
+    }
+    let market_params = morpho
+        .idToMarketParams(market_id)
+        .block(block)
+        .call()
+        .await?;
+
+    let params = IMorpho::MarketParams {
+        loanToken: market_params.loanToken,
+        collateralToken: market_params.collateralToken,
+        oracle: market_params.oracle,
+        irm: market_params.irm,
+        lltv: market_params.lltv,
+    };
+    let price = fetch_oracle_price(provider, params.oracle, block).await?;
+    let borrow_rate = fetch_borrow_rate(provider, &params, &market_data, block).await?;
 
-    // Getting the price of the oracle
-    let oracle = IOracle::new(market_params.oracle, provider.clone());
-    let price = oracle.price().call().await?._0;
-    println!("Current price of market oracle is {}", price);
+    Ok(MarketInfo {
+        id: market_id,
+        params,
+        market: market_data,
+        price,
+        borrow_rate,
+    })
+}
 
-    // Getting current rate from the IRM
+// Idle markets have no oracle, their price is zero instead of a call to the zero address
+pub(crate) async fn fetch_oracle_price<T, P>(
+    provider: &P,
+    oracle: Address,
+    block: BlockId,
+) -> Result<U256>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum> + Clone,
+{
+    if oracle == Address::ZERO {
+        return Ok(U256::ZERO);
+    }
+    let oracle = IOracle::new(oracle, provider.clone());
+    Ok(oracle.price().block(block).call().await?._0)
+}
+
+// Borrow rate per second, zero for markets without IRM
+async fn fetch_borrow_rate<T, P>(
+    provider: &P,
+    params: &IMorpho::MarketParams,
+    market: &IMorpho::marketReturn,
+    block: BlockId,
+) -> Result<U256>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum> + Clone,
+{
+    if params.irm == Address::ZERO {
+        return Ok(U256::ZERO);
+    }
     // Note we assume the IRM of this market to be the AdaptiveCurveIRM
-    let irm = IIRM::new(market_params.irm, provider.clone());
-    let borrow_rate = irm
+    let irm = IIRM::new(params.irm, provider.clone());
+    Ok(irm
         .borrowRateView(
             MarketParams {
-                collateralToken: market_params.collateralToken,
-                loanToken: market_params.loanToken,
-                lltv: market_params.lltv,
-                oracle: market_params.oracle,
-                irm: market_params.irm,
+                collateralToken: params.collateralToken,
+                loanToken: params.loanToken,
+                lltv: params.lltv,
+                oracle: params.oracle,
+                irm: params.irm,
             },
             Market {
-                totalSupplyAssets: market_data.totalSupplyAssets,
-                totalSupplyShares: market_data.totalSupplyShares,
-                totalBorrowAssets: market_data.totalBorrowAssets,
-                totalBorrowShares: market_data.totalBorrowShares,
-                fee: market_data.fee,
-                lastUpdate: market_data.lastUpdate,
+                totalSupplyAssets: market.totalSupplyAssets,
+                totalSupplyShares: market.totalSupplyShares,
+                totalBorrowAssets: market.totalBorrowAssets,
+                totalBorrowShares: market.totalBorrowShares,
+                fee: market.fee,
+                lastUpdate: market.lastUpdate,
             },
         )
+        .block(block)
         .call()
         .await?
-        ._0;
-    println!(
-        "Current average rate since last update for this market is {}",
-        borrow_rate
-    );
-
-    // Compute updated market total with interest
-    let current_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let elapsed = U256::from(current_time) - U256::from(market_data.lastUpdate);
-    let interest = w_mul_down(
-        U256::from(market_data.totalBorrowAssets),
-        w_taylor_compounded(borrow_rate, elapsed),
-    );
-
-    println!(
-        "Market has {} pending interest since last update at {}, now it's {} so total supply asset will be {} and total borrow asset will be {}",
-         interest,
-         market_data.lastUpdate,
-        current_time,
-         U256::from(market_data.totalSupplyAssets)+interest,
-         U256::from(market_data.totalBorrowAssets)+interest
-    );
-
-    // Getting a user position on this market
-    // You can change this user
-    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
-    let position = morpho.position(market_id, user).call().await?;
-
-    println!(
-        "User {:#20x} position on this market:\n- Collateral: {}\n- Borrow shares: {}\n- Supply shares: {}",
-        user, position.collateral, position.borrowShares, position.supplyShares
-    );
-    Ok(())
+        ._0)
 }
 
-pub async fn retrieve_events_with_logs(rpc_url: Url) -> Result<()> {
+// Params, state, oracle price and borrow rate of a market at a block (BlockId::latest() for the current state)
+pub async fn fetch_market_info(
+    rpc_url: Url,
+    market_id: B256,
+    block: BlockId,
+) -> Result<MarketInfo> {
     let provider = ProviderBuilder::new().on_http(rpc_url);
-
-    // The morpho contract address
-    let morpho_address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
-
-    // You can change the block range (the script fails if there is too much logs on a range)
-    let filter = Filter::new()
-        .address(morpho_address)
-        .from_block(BlockNumberOrTag::Number(21_250_000))
-        .to_block(BlockNumberOrTag::Number(21_260_000));
-
-    let logs = provider.get_logs(&filter).await?;
-
-    println!("Got {} logs", logs.len());
-    for log in logs {
-        match log.topic0() {
-            Some(&IMorpho::CreateMarket::SIGNATURE_HASH) => {
-                let IMorpho::CreateMarket { id, marketParams } = log.log_decode()?.inner.data;
-                println!(
-                    "Market with id {:#32x} was created with params: {:#20x}, {:#20x}, {}, {:#20x}, {:#20x}",
-                    id, marketParams.collateralToken, marketParams.loanToken, marketParams.lltv, marketParams.oracle, marketParams.irm
-                );
-            }
-            Some(&IMorpho::Supply::SIGNATURE_HASH) => {
-                let IMorpho::Supply {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} supplied {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Withdraw::SIGNATURE_HASH) => {
-                let IMorpho::Withdraw {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} withdrew {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Borrow::SIGNATURE_HASH) => {
-                let IMorpho::Borrow {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} borrowed {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Repay::SIGNATURE_HASH) => {
-                let IMorpho::Repay {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} repaid {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::SupplyCollateral::SIGNATURE_HASH) => {
-                let IMorpho::SupplyCollateral {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} supplied {} assets as collateral on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::WithdrawCollateral::SIGNATURE_HASH) => {
-                let IMorpho::WithdrawCollateral {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} withdrew {} assets as collateral on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Liquidate::SIGNATURE_HASH) => {
-                let IMorpho::Liquidate {
-                    id,
-                    caller,
-                    borrower,
-                    repaidAssets: _repaid_assets,
-                    repaidShares: _repaid_shares,
-                    seizedAssets: _seized_assets,
-                    badDebtAssets: _bad_debt_assets,
-                    badDebtShares: _bad_debt_shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} was liquidated by user {:#20x} on market {:#32x}",
-                    borrower, caller, id
-                );
-            }
-            // Miss SetOwner, SetFee, SetFeeRecipient, EnableIrm, EnableLltv, FlashLoan, SetAuthorization, IncrementNonce, AccrueInterest events
-            _ => (),
-        }
-    }
-    Ok(())
+    fetch_market_info_with(&provider, market_id, block).await
 }
 
-pub async fn retrieve_markets(rpc_url: Url) -> Result<()> {
+pub async fn fetch_position(
+    rpc_url: Url,
+    market_id: B256,
+    user: Address,
+    block: BlockId,
+) -> Result<Position> {
     let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+    let position = morpho.position(market_id, user).block(block).call().await?;
+    Ok(Position {
+        market_id,
+        user,
+        supply_shares: position.supplyShares,
+        borrow_shares: position.borrowShares,
+        collateral: position.collateral,
+    })
+}
 
-    // The morpho contract address
-    let morpho_address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+// Every market created on Morpho up to a block, with its params. The logs are scanned from the deployment
+// block of the chain served by the RPC.
+pub async fn fetch_markets(rpc_url: Url, to_block: BlockNumberOrTag) -> Result<Vec<MarketRecord>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let from_block = morpho_deployment_block(provider.get_chain_id().await?)?;
+    let to_block = resolve_block_number(&provider, to_block).await?;
 
     let filter = Filter::new()
-        .address(morpho_address)
-        .from_block(BlockNumberOrTag::Number(18_883_124))
+        .address(MORPHO_ADDRESS)
         .event_signature(IMorpho::CreateMarket::SIGNATURE_HASH);
-
-    let logs = provider.get_logs(&filter).await?;
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        from_block,
+        to_block,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+    let mut markets = Vec::with_capacity(logs.len());
     for log in logs {
-        match log.topic0() {
-            Some(&IMorpho::CreateMarket::SIGNATURE_HASH) => {
-                let IMorpho::CreateMarket { id, marketParams } = log.log_decode()?.inner.data;
-                println!(
-                    "Market with id {:#32x} was created with params: {:#20x}, {:#20x}, {}, {:#20x}, {:#20x}",
-                    id, marketParams.collateralToken, marketParams.loanToken, marketParams.lltv, marketParams.oracle, marketParams.irm
-                );
-            }
-            _ => (),
-        }
+        let IMorpho::CreateMarket { id, marketParams } = log.log_decode()?.inner.data;
+        markets.push(MarketRecord {
+            id,
+            params: marketParams,
+        });
     }
-    Ok(())
+    Ok(markets)
 }
 
-pub async fn subscribe_to_event_logs(rpc_url: &str) -> Result<()> {
-    //let ws = WsConnect::new(rpc_url);
-    let provider = ProviderBuilder::new()
-        .on_ws(WsConnect::new(rpc_url))
-        .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // The morpho contract address
-    let morpho_address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+    #[tokio::test]
+    async fn idle_markets_do_not_call_the_zero_address() {
+        // Nothing listens on this port, any call would fail
+        let provider = ProviderBuilder::new().on_http("http://127.0.0.1:1".parse().unwrap());
+        let params = IMorpho::MarketParams {
+            loanToken: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            collateralToken: Address::ZERO,
+            oracle: Address::ZERO,
+            irm: Address::ZERO,
+            lltv: U256::ZERO,
+        };
+        let market = IMorpho::marketReturn {
+            totalSupplyAssets: 1_000_000,
+            totalSupplyShares: 1_000_000_000_000,
+            totalBorrowAssets: 0,
+            totalBorrowShares: 0,
+            lastUpdate: 1_700_000_000,
+            fee: 0,
+        };
 
-    let filter = Filter::new()
-        .address(morpho_address)
-        .from_block(BlockNumberOrTag::Latest);
-
-    let sub = provider.subscribe_logs(&filter).await?;
-    let mut stream = sub.into_stream();
-
-    while let Some(log) = stream.next().await {
-        match log.topic0() {
-            Some(&IMorpho::CreateMarket::SIGNATURE_HASH) => {
-                let IMorpho::CreateMarket { id, marketParams } = log.log_decode()?.inner.data;
-                println!(
-                    "Market with id {:#32x} was created with params: {:#20x}, {:#20x}, {}, {:#20x}, {:#20x}",
-                    id, marketParams.collateralToken, marketParams.loanToken, marketParams.lltv, marketParams.oracle, marketParams.irm
-                );
-            }
-            Some(&IMorpho::Supply::SIGNATURE_HASH) => {
-                let IMorpho::Supply {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} supplied {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Withdraw::SIGNATURE_HASH) => {
-                let IMorpho::Withdraw {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} withdrew {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Borrow::SIGNATURE_HASH) => {
-                let IMorpho::Borrow {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} borrowed {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Repay::SIGNATURE_HASH) => {
-                let IMorpho::Repay {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                    shares: _shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} repaid {} assets on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::SupplyCollateral::SIGNATURE_HASH) => {
-                let IMorpho::SupplyCollateral {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    assets,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} supplied {} assets as collateral on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::WithdrawCollateral::SIGNATURE_HASH) => {
-                let IMorpho::WithdrawCollateral {
-                    id,
-                    caller: _caller,
-                    onBehalf,
-                    receiver: _receiver,
-                    assets,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} withdrew {} assets as collateral on market {:#32x}",
-                    onBehalf, assets, id
-                );
-            }
-            Some(&IMorpho::Liquidate::SIGNATURE_HASH) => {
-                let IMorpho::Liquidate {
-                    id,
-                    caller,
-                    borrower,
-                    repaidAssets: _repaid_assets,
-                    repaidShares: _repaid_shares,
-                    seizedAssets: _seized_assets,
-                    badDebtAssets: _bad_debt_assets,
-                    badDebtShares: _bad_debt_shares,
-                } = log.log_decode()?.inner.data;
-                println!(
-                    "User {:#20x} was liquidated by user {:#20x} on market {:#32x}",
-                    borrower, caller, id
-                );
-            }
-            // Miss SetOwner, SetFee, SetFeeRecipient, EnableIrm, EnableLltv, FlashLoan, SetAuthorization, IncrementNonce, AccrueInterest events
-            _ => (),
-        }
+        let price = fetch_oracle_price(&provider, params.oracle, BlockId::latest()).await;
+        assert_eq!(price.unwrap(), U256::ZERO);
+        let rate = fetch_borrow_rate(&provider, &params, &market, BlockId::latest()).await;
+        assert_eq!(rate.unwrap(), U256::ZERO);
+
+        // A market with an oracle does reach the provider
+        let oracle = address!("48F7E36EB6B826B2dF4B2E630B62Cd25e89E40e2");
+        assert!(fetch_oracle_price(&provider, oracle, BlockId::latest())
+            .await
+            .is_err());
     }
-    Ok(())
 }
@@ -0,0 +1,173 @@
+use crate::error::{Error, Result};
+use crate::market::{IMorpho, MORPHO_ADDRESS};
+use crate::output::decimal;
+use alloy::{
+    primitives::{keccak256, utils::parse_units, Address, B256, U256},
+    providers::ProviderBuilder,
+    sol_types::SolValue,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use IMorpho::MarketParams;
+
+impl MarketParams {
+    // Equivalent of MarketParamsLib.id: keccak256(abi.encode(loanToken, collateralToken, oracle, irm, lltv))
+    pub fn id(&self) -> B256 {
+        keccak256(self.abi_encode())
+    }
+}
+
+// Checks that the id matches the params, and that Morpho stores the same params under this id
+pub async fn validate_market_id(
+    rpc_url: Url,
+    id: B256,
+    market_params: &MarketParams,
+) -> Result<bool> {
+    if market_params.id() != id {
+        return Ok(false);
+    }
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+    let stored = morpho.idToMarketParams(id).call().await?;
+    Ok(stored.loanToken == market_params.loanToken
+        && stored.collateralToken == market_params.collateralToken
+        && stored.oracle == market_params.oracle
+        && stored.irm == market_params.irm
+        && stored.lltv == market_params.lltv)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketSpec {
+    pub collateral_symbol: String,
+    pub loan_symbol: String,
+    #[serde(serialize_with = "decimal")]
+    pub lltv: U256,
+}
+
+// Parses specs like "wstETH/USDC 86% lltv" or "USD0++/USDC 86%" (collateral/loan, LLTV in percent)
+pub fn parse_market_spec(spec: &str) -> Result<MarketSpec> {
+    let mut parts = spec.split_whitespace();
+    let pair = parts
+        .next()
+        .ok_or_else(|| Error::Config("empty market spec".to_string()))?;
+    let (collateral_symbol, loan_symbol) = pair
+        .split_once('/')
+        .ok_or_else(|| Error::Config(format!("expected collateral/loan, got {}", pair)))?;
+    let lltv = parts
+        .next()
+        .ok_or_else(|| Error::Config(format!("missing lltv in {}", spec)))?
+        .trim_end_matches('%');
+    match parts.next() {
+        None => (),
+        Some(word) if word.eq_ignore_ascii_case("lltv") => (),
+        Some(word) => return Err(Error::Config(format!("unexpected {} in {}", word, spec))),
+    }
+
+    // 1% is 1e16 in WAD
+    let lltv = parse_units(lltv, 16u8)?;
+    if lltv.is_negative() {
+        return Err(Error::Config(format!("negative lltv in {}", spec)));
+    }
+    let lltv: U256 = lltv.into();
+    Ok(MarketSpec {
+        collateral_symbol: collateral_symbol.to_string(),
+        loan_symbol: loan_symbol.to_string(),
+        lltv,
+    })
+}
+
+// Finds the markets matching a spec among known markets. Tokens are matched in order, so "wstETH/USDC"
+// only resolves to markets lending USDC against wstETH. Symbols are case insensitive.
+pub fn resolve_market_spec(
+    spec: &MarketSpec,
+    markets: &[MarketParams],
+    symbols: &HashMap<Address, String>,
+) -> Vec<MarketParams> {
+    let symbol_is = |token: &Address, expected: &str| {
+        symbols
+            .get(token)
+            .is_some_and(|symbol| symbol.eq_ignore_ascii_case(expected))
+    };
+    markets
+        .iter()
+        .filter(|market| market.lltv == spec.lltv)
+        .filter(|market| {
+            symbol_is(&market.collateralToken, &spec.collateral_symbol)
+                && symbol_is(&market.loanToken, &spec.loan_symbol)
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, b256};
+
+    fn ids(markets: Vec<MarketParams>) -> Vec<B256> {
+        markets.iter().map(MarketParams::id).collect()
+    }
+
+    #[test]
+    fn computes_the_id_of_an_existing_market() {
+        // wstETH/USDC (86%) with AdaptiveCurve on Ethereum
+        let market = MarketParams {
+            loanToken: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            collateralToken: address!("7f39C581F595B53c5cb19bD0b3f8dA6c935E2Ca0"),
+            oracle: address!("48F7E36EB6B826B2dF4B2E630B62Cd25e89E40e2"),
+            irm: address!("870aC11D48B15DB9a138Cf899d20F13F79Ba00BC"),
+            lltv: U256::from(860_000_000_000_000_000u64),
+        };
+        assert_eq!(
+            market.id(),
+            b256!("b323495f7e4148be5643a4ea4a8221eef163e4bccfdedc2a6f4696baacbc86cc")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        assert!(matches!(
+            parse_market_spec("wstETH/USDC -86%"),
+            Err(Error::Config(_))
+        ));
+        assert!(parse_market_spec("wstETH USDC 86%").is_err());
+        assert!(parse_market_spec("wstETH/USDC").is_err());
+        assert!(parse_market_spec("wstETH/USDC 86% ltv").is_err());
+        assert_eq!(
+            parse_market_spec("wstETH/USDC 91.5%").unwrap().lltv,
+            U256::from(915_000_000_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn resolves_collateral_and_loan_in_order() {
+        let wsteth = address!("7f39C581F595B53c5cb19bD0b3f8dA6c935E2Ca0");
+        let usdc = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let market = |collateral_token, loan_token| MarketParams {
+            loanToken: loan_token,
+            collateralToken: collateral_token,
+            oracle: Address::ZERO,
+            irm: Address::ZERO,
+            lltv: U256::from(860_000_000_000_000_000u64),
+        };
+        let wsteth_usdc = market(wsteth, usdc);
+        let usdc_wsteth = market(usdc, wsteth);
+        let symbols = HashMap::from([(wsteth, "wstETH".to_string()), (usdc, "USDC".to_string())]);
+        let markets = [wsteth_usdc.clone(), usdc_wsteth.clone()];
+
+        let spec = parse_market_spec("wsteth/usdc 86% lltv").unwrap();
+        assert_eq!(
+            ids(resolve_market_spec(&spec, &markets, &symbols)),
+            [wsteth_usdc.id()]
+        );
+        let spec = parse_market_spec("USDC/wstETH 86%").unwrap();
+        assert_eq!(
+            ids(resolve_market_spec(&spec, &markets, &symbols)),
+            [usdc_wsteth.id()]
+        );
+        let spec = parse_market_spec("wstETH/USDC 77%").unwrap();
+        assert!(resolve_market_spec(&spec, &markets, &symbols).is_empty());
+    }
+}
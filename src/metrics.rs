@@ -0,0 +1,418 @@
+use crate::error::{Error, Result};
+use crate::irm::wad_to_f64;
+use crate::oracle::{FeedInfo, OracleReport};
+use crate::views::{MarketView, PositionView, VaultView};
+use alloy::primitives::{Address, B256, U256};
+use prometheus::{GaugeVec, IntCounter, Opts, Registry, TextEncoder};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+const ORACLE_FEEDS: [&str; 4] = ["baseFeed1", "baseFeed2", "quoteFeed1", "quoteFeed2"];
+
+// Markets, vaults and positions published on /metrics
+#[derive(Debug, Clone, Default)]
+pub struct MetricsTargets {
+    pub markets: Vec<B256>,
+    pub vaults: Vec<Address>,
+    // (market id, user)
+    pub positions: Vec<(B256, Address)>,
+}
+
+// Gauges labelled by market id, vault address and user. Amounts are in raw token units, rates
+// per second are WAD-scaled values converted to floats and APYs are fractions (0.05 is 5%).
+// The gauges of a target that fails to be read are removed, so stale values are never published.
+pub struct Metrics {
+    registry: Registry,
+    market_total_supply_assets: GaugeVec,
+    market_total_borrow_assets: GaugeVec,
+    market_utilization: GaugeVec,
+    market_borrow_rate: GaugeVec,
+    market_rate_at_target: GaugeVec,
+    market_supply_apy: GaugeVec,
+    market_borrow_apy: GaugeVec,
+    market_oracle_price: GaugeVec,
+    market_last_update: GaugeVec,
+    oracle_feed_updated_at: GaugeVec,
+    vault_total_assets: GaugeVec,
+    vault_liquidity: GaugeVec,
+    vault_apy: GaugeVec,
+    vault_cap_usage: GaugeVec,
+    position_health_factor: GaugeVec,
+    position_borrow_assets: GaugeVec,
+    position_collateral: GaugeVec,
+    errors: IntCounter,
+    // Markets published in vault_cap_usage for each vault
+    vault_cap_markets: Mutex<HashMap<Address, BTreeSet<B256>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let gauge = |name: &str, help: &str, labels: &[&str]| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let market = &["market_id"];
+        let vault = &["vault"];
+        let position = &["market_id", "user"];
+
+        let metrics = Metrics {
+            market_total_supply_assets: gauge(
+                "morpho_market_total_supply_assets",
+                "Assets supplied to the market, in loan token units",
+                market,
+            )?,
+            market_total_borrow_assets: gauge(
+                "morpho_market_total_borrow_assets",
+                "Assets borrowed from the market, in loan token units",
+                market,
+            )?,
+            market_utilization: gauge(
+                "morpho_market_utilization",
+                "Borrowed over supplied assets",
+                market,
+            )?,
+            market_borrow_rate: gauge(
+                "morpho_market_borrow_rate",
+                "Average borrow rate per second since the last market update",
+                market,
+            )?,
+            market_rate_at_target: gauge(
+                "morpho_market_rate_at_target",
+                "Borrow rate per second of the AdaptiveCurveIRM at the target utilization",
+                market,
+            )?,
+            market_supply_apy: gauge(
+                "morpho_market_supply_apy",
+                "Supply APY at the current utilization",
+                market,
+            )?,
+            market_borrow_apy: gauge(
+                "morpho_market_borrow_apy",
+                "Borrow APY at the current utilization",
+                market,
+            )?,
+            market_oracle_price: gauge(
+                "morpho_market_oracle_price",
+                "Oracle price of the collateral in loan token, scaled by 1e36",
+                market,
+            )?,
+            market_last_update: gauge(
+                "morpho_market_last_update",
+                "Timestamp of the last interest accrual",
+                market,
+            )?,
+            oracle_feed_updated_at: gauge(
+                "morpho_oracle_feed_updated_at",
+                "Timestamp of the last answer of a Chainlink feed of the market oracle",
+                &["market_id", "feed"],
+            )?,
+            vault_total_assets: gauge(
+                "morpho_vault_total_assets",
+                "Assets managed by the vault, in asset units",
+                vault,
+            )?,
+            vault_liquidity: gauge(
+                "morpho_vault_liquidity",
+                "Assets the vault can withdraw now from its markets, in asset units",
+                vault,
+            )?,
+            vault_apy: gauge(
+                "morpho_vault_apy",
+                "Supply APY of the vault allocation, before the vault fee",
+                vault,
+            )?,
+            vault_cap_usage: gauge(
+                "morpho_vault_cap_usage",
+                "Assets supplied by the vault to a market over its supply cap",
+                &["vault", "market_id"],
+            )?,
+            position_health_factor: gauge(
+                "morpho_position_health_factor",
+                "Max borrow over borrowed assets, the position is liquidatable below 1",
+                position,
+            )?,
+            position_borrow_assets: gauge(
+                "morpho_position_borrow_assets",
+                "Assets borrowed by the position, in loan token units",
+                position,
+            )?,
+            position_collateral: gauge(
+                "morpho_position_collateral",
+                "Collateral of the position, in collateral token units",
+                position,
+            )?,
+            errors: IntCounter::new(
+                "morpho_metrics_errors_total",
+                "Failed reads of a market, vault, oracle or position",
+            )?,
+            vault_cap_markets: Mutex::new(HashMap::new()),
+            registry,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.errors.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn update_market(&self, market: &MarketView) {
+        let id = market.info.id.to_string();
+        let labels = &[id.as_str()];
+        let state = &market.info.market;
+        self.market_total_supply_assets
+            .with_label_values(labels)
+            .set(state.totalSupplyAssets as f64);
+        self.market_total_borrow_assets
+            .with_label_values(labels)
+            .set(state.totalBorrowAssets as f64);
+        self.market_utilization
+            .with_label_values(labels)
+            .set(market.utilization);
+        self.market_borrow_rate
+            .with_label_values(labels)
+            .set(wad_to_f64(market.info.borrow_rate));
+        self.market_rate_at_target
+            .with_label_values(labels)
+            .set(market.rate_at_target);
+        self.market_supply_apy
+            .with_label_values(labels)
+            .set(market.supply_apy);
+        self.market_borrow_apy
+            .with_label_values(labels)
+            .set(market.borrow_apy);
+        self.market_oracle_price
+            .with_label_values(labels)
+            .set(f64::from(market.info.price));
+        self.market_last_update
+            .with_label_values(labels)
+            .set(state.lastUpdate as f64);
+    }
+
+    pub fn remove_market(&self, market_id: B256) {
+        let id = market_id.to_string();
+        let labels = &[id.as_str()];
+        for gauge in [
+            &self.market_total_supply_assets,
+            &self.market_total_borrow_assets,
+            &self.market_utilization,
+            &self.market_borrow_rate,
+            &self.market_rate_at_target,
+            &self.market_supply_apy,
+            &self.market_borrow_apy,
+            &self.market_oracle_price,
+            &self.market_last_update,
+        ] {
+            let _ = gauge.remove_label_values(labels);
+        }
+        self.remove_oracle(market_id);
+    }
+
+    // Only Chainlink oracles (MorphoChainlinkOracleV2) have feeds
+    pub fn update_oracle(&self, market_id: B256, report: &OracleReport) {
+        let id = market_id.to_string();
+        let feeds: [&Option<FeedInfo>; 4] = [
+            &report.base_feed_1,
+            &report.base_feed_2,
+            &report.quote_feed_1,
+            &report.quote_feed_2,
+        ];
+        for (name, feed) in ORACLE_FEEDS.into_iter().zip(feeds) {
+            let labels = &[id.as_str(), name];
+            match feed {
+                Some(feed) => self
+                    .oracle_feed_updated_at
+                    .with_label_values(labels)
+                    .set(feed.updated_at as f64),
+                None => {
+                    let _ = self.oracle_feed_updated_at.remove_label_values(labels);
+                }
+            }
+        }
+    }
+
+    pub fn remove_oracle(&self, market_id: B256) {
+        let id = market_id.to_string();
+        for name in ORACLE_FEEDS {
+            let _ = self
+                .oracle_feed_updated_at
+                .remove_label_values(&[id.as_str(), name]);
+        }
+    }
+
+    pub fn update_vault(&self, vault: &VaultView) {
+        let address = vault.info.address.to_checksum(None);
+        let labels = &[address.as_str()];
+        self.vault_total_assets
+            .with_label_values(labels)
+            .set(f64::from(vault.info.total_assets));
+        self.vault_apy.with_label_values(labels).set(vault.apy);
+
+        // The vault cannot withdraw more than the idle liquidity of each market
+        let liquidity: f64 = vault
+            .allocation
+            .iter()
+            .map(|market| {
+                let idle = market.curve.total_supply_assets - market.curve.total_borrow_assets;
+                market.current_assets.min(idle.max(0.0))
+            })
+            .sum();
+        self.vault_liquidity
+            .with_label_values(labels)
+            .set(liquidity);
+
+        // Markets being removed have a zero cap, their usage is no longer published
+        let mut published = BTreeSet::new();
+        for market in vault.allocation.iter().filter(|market| market.cap > 0.0) {
+            self.vault_cap_usage
+                .with_label_values(&[address.as_str(), market.id.to_string().as_str()])
+                .set(market.current_assets / market.cap);
+            published.insert(market.id);
+        }
+        let previous = self
+            .vault_cap_markets
+            .lock()
+            .unwrap()
+            .insert(vault.info.address, published.clone())
+            .unwrap_or_default();
+        for id in previous.difference(&published) {
+            let _ = self
+                .vault_cap_usage
+                .remove_label_values(&[address.as_str(), id.to_string().as_str()]);
+        }
+    }
+
+    pub fn remove_vault(&self, vault: Address) {
+        let address = vault.to_checksum(None);
+        let labels = &[address.as_str()];
+        for gauge in [
+            &self.vault_total_assets,
+            &self.vault_liquidity,
+            &self.vault_apy,
+        ] {
+            let _ = gauge.remove_label_values(labels);
+        }
+        let published = self.vault_cap_markets.lock().unwrap().remove(&vault);
+        for id in published.unwrap_or_default() {
+            let _ = self
+                .vault_cap_usage
+                .remove_label_values(&[address.as_str(), id.to_string().as_str()]);
+        }
+    }
+
+    pub fn update_position(&self, position: &PositionView) {
+        let market_id = position.position.market_id.to_string();
+        let user = position.position.user.to_checksum(None);
+        let labels = &[market_id.as_str(), user.as_str()];
+        match position.health_factor {
+            Some(health_factor) => self
+                .position_health_factor
+                .with_label_values(labels)
+                .set(health_factor),
+            // Without any borrow the position cannot be liquidated
+            None => {
+                let _ = self.position_health_factor.remove_label_values(labels);
+            }
+        }
+        self.position_borrow_assets
+            .with_label_values(labels)
+            .set(f64::from(position.borrow_assets));
+        self.position_collateral
+            .with_label_values(labels)
+            .set(f64::from(U256::from(position.position.collateral)));
+    }
+
+    pub fn remove_position(&self, market_id: B256, user: Address) {
+        let market_id = market_id.to_string();
+        let user = user.to_checksum(None);
+        let labels = &[market_id.as_str(), user.as_str()];
+        for gauge in [
+            &self.position_health_factor,
+            &self.position_borrow_assets,
+            &self.position_collateral,
+        ] {
+            let _ = gauge.remove_label_values(labels);
+        }
+    }
+
+    pub fn record_error(&self, target: &str, error: &Error) {
+        self.errors.inc();
+        eprintln!("Failed to read metrics of {}: {}", target, error);
+    }
+
+    // Prometheus text exposition format
+    pub fn encode(&self) -> Result<String> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::MarketAllocation;
+    use crate::irm::MarketCurve;
+    use crate::vault::VaultInfo;
+
+    fn allocation(id: B256, cap: f64) -> MarketAllocation {
+        MarketAllocation {
+            id,
+            cap,
+            current_assets: 50.0,
+            curve: MarketCurve {
+                total_supply_assets: 100.0,
+                total_borrow_assets: 80.0,
+                rate_at_target: 0.0,
+                fee: 0.0,
+            },
+        }
+    }
+
+    fn vault(allocation: Vec<MarketAllocation>) -> VaultView {
+        VaultView {
+            info: VaultInfo {
+                address: Address::repeat_byte(0x5a),
+                name: "Synthetic USDC".to_string(),
+                symbol: "sUSDC".to_string(),
+                decimals: 18,
+                asset: Address::ZERO,
+                total_assets: U256::from(100),
+                total_supply: U256::from(100),
+                owner: Address::ZERO,
+                curator: Address::ZERO,
+                guardian: Address::ZERO,
+                timelock: U256::ZERO,
+            },
+            apy: 0.05,
+            allocation,
+            pending_actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn removes_the_cap_usage_of_removed_markets_and_failed_vaults() {
+        let metrics = Metrics::new().unwrap();
+        let kept = B256::repeat_byte(1);
+        let removed = B256::repeat_byte(2);
+
+        metrics.update_vault(&vault(vec![
+            allocation(kept, 100.0),
+            allocation(removed, 100.0),
+        ]));
+        let encoded = metrics.encode().unwrap();
+        assert!(encoded.contains(&removed.to_string()));
+
+        // The cap of the market went to zero before its removal
+        metrics.update_vault(&vault(vec![
+            allocation(kept, 100.0),
+            allocation(removed, 0.0),
+        ]));
+        let encoded = metrics.encode().unwrap();
+        assert!(encoded.contains(&kept.to_string()));
+        assert!(!encoded.contains(&removed.to_string()));
+
+        metrics.remove_vault(Address::repeat_byte(0x5a));
+        assert!(!metrics.encode().unwrap().contains("morpho_vault_"));
+    }
+}
@@ -0,0 +1,351 @@
+use crate::error::Result;
+use crate::market::{IMorpho, IOracle, MarketRow, IIRM, MORPHO_ADDRESS};
+use alloy::{
+    primitives::{address, Address, Bytes, B256, U256},
+    providers::ProviderBuilder,
+    sol,
+    sol_types::SolCall,
+    transports::http::reqwest::Url,
+};
+use serde::{Serialize, Serializer};
+use Multicall3::Call3;
+
+sol!(
+    #[sol(rpc)]
+    IMulticall3,
+    "data/abis/multicall3.json"
+);
+
+// Multicall3 is deployed at the same address on every chain
+pub const MULTICALL3_ADDRESS: Address = address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchLimits {
+    // Maximum calldata size of one aggregate3 call
+    pub max_calldata_bytes: usize,
+    // Maximum estimated gas of one aggregate3 call, RPCs cap eth_call gas
+    pub max_gas: u64,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits {
+            max_calldata_bytes: 100_000,
+            max_gas: 20_000_000,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum MarketCall {
+    IdToMarketParams(B256),
+    Market(B256),
+    Position(B256, Address),
+    OraclePrice(Address),
+    BorrowRateView(Address, IIRM::MarketParams, IIRM::Market),
+}
+
+#[derive(Clone)]
+pub enum MarketCallResult {
+    IdToMarketParams(IMorpho::idToMarketParamsReturn),
+    Market(IMorpho::marketReturn),
+    Position(IMorpho::positionReturn),
+    OraclePrice(U256),
+    BorrowRateView(U256),
+}
+
+impl MarketCall {
+    pub fn target(&self) -> Address {
+        match self {
+            MarketCall::IdToMarketParams(_)
+            | MarketCall::Market(_)
+            | MarketCall::Position(_, _) => MORPHO_ADDRESS,
+            MarketCall::OraclePrice(oracle) => *oracle,
+            MarketCall::BorrowRateView(irm, _, _) => *irm,
+        }
+    }
+
+    pub fn calldata(&self) -> Vec<u8> {
+        match self {
+            MarketCall::IdToMarketParams(id) => {
+                IMorpho::idToMarketParamsCall { _0: *id }.abi_encode()
+            }
+            MarketCall::Market(id) => IMorpho::marketCall { _0: *id }.abi_encode(),
+            MarketCall::Position(id, user) => {
+                IMorpho::positionCall { _0: *id, _1: *user }.abi_encode()
+            }
+            MarketCall::OraclePrice(_) => IOracle::priceCall {}.abi_encode(),
+            MarketCall::BorrowRateView(_, market_params, market) => IIRM::borrowRateViewCall {
+                marketParams: market_params.clone(),
+                market: market.clone(),
+            }
+            .abi_encode(),
+        }
+    }
+
+    // Rough upper bounds, oracles read external feeds and the IRM computes exponentials
+    pub fn estimated_gas(&self) -> u64 {
+        match self {
+            MarketCall::IdToMarketParams(_) => 15_000,
+            MarketCall::Market(_) => 10_000,
+            MarketCall::Position(_, _) => 10_000,
+            MarketCall::OraclePrice(_) => 100_000,
+            MarketCall::BorrowRateView(_, _, _) => 30_000,
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<MarketCallResult> {
+        Ok(match self {
+            MarketCall::IdToMarketParams(_) => MarketCallResult::IdToMarketParams(
+                IMorpho::idToMarketParamsCall::abi_decode_returns(data, true)?,
+            ),
+            MarketCall::Market(_) => {
+                MarketCallResult::Market(IMorpho::marketCall::abi_decode_returns(data, true)?)
+            }
+            MarketCall::Position(_, _) => {
+                MarketCallResult::Position(IMorpho::positionCall::abi_decode_returns(data, true)?)
+            }
+            MarketCall::OraclePrice(_) => MarketCallResult::OraclePrice(
+                IOracle::priceCall::abi_decode_returns(data, true)?._0,
+            ),
+            MarketCall::BorrowRateView(_, _, _) => MarketCallResult::BorrowRateView(
+                IIRM::borrowRateViewCall::abi_decode_returns(data, true)?._0,
+            ),
+        })
+    }
+}
+
+// Splits calls into batches respecting both limits
+pub fn chunk_calls(calls: &[MarketCall], limits: BatchLimits) -> Vec<&[MarketCall]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut calldata_bytes = 0;
+    let mut gas = 0;
+    for (i, call) in calls.iter().enumerate() {
+        // Each Call3 adds its target, flag and calldata offsets on top of the calldata itself
+        let call_bytes = call.calldata().len() + 4 * 32;
+        let call_gas = call.estimated_gas();
+        if i > start
+            && (calldata_bytes + call_bytes > limits.max_calldata_bytes
+                || gas + call_gas > limits.max_gas)
+        {
+            chunks.push(&calls[start..i]);
+            start = i;
+            calldata_bytes = 0;
+            gas = 0;
+        }
+        calldata_bytes += call_bytes;
+        gas += call_gas;
+    }
+    if start < calls.len() {
+        chunks.push(&calls[start..]);
+    }
+    chunks
+}
+
+// Runs the calls through Multicall3 aggregate3, failed calls are returned as None
+pub async fn execute_calls(
+    rpc_url: Url,
+    calls: &[MarketCall],
+    limits: BatchLimits,
+) -> Result<Vec<Option<MarketCallResult>>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let multicall = IMulticall3::new(MULTICALL3_ADDRESS, provider);
+
+    let mut results = Vec::with_capacity(calls.len());
+    for chunk in chunk_calls(calls, limits) {
+        let call3s: Vec<Call3> = chunk
+            .iter()
+            .map(|call| Call3 {
+                target: call.target(),
+                allowFailure: true,
+                callData: Bytes::from(call.calldata()),
+            })
+            .collect();
+        let return_data = multicall.aggregate3(call3s).call().await?.returnData;
+        for (call, result) in chunk.iter().zip(return_data) {
+            if result.success {
+                results.push(call.decode(&result.returnData).ok());
+            } else {
+                results.push(None);
+            }
+        }
+    }
+    Ok(results)
+}
+
+pub fn irm_market_params(market_params: &IMorpho::idToMarketParamsReturn) -> IIRM::MarketParams {
+    IIRM::MarketParams {
+        loanToken: market_params.loanToken,
+        collateralToken: market_params.collateralToken,
+        oracle: market_params.oracle,
+        irm: market_params.irm,
+        lltv: market_params.lltv,
+    }
+}
+
+pub fn irm_market(market: &IMorpho::marketReturn) -> IIRM::Market {
+    IIRM::Market {
+        totalSupplyAssets: market.totalSupplyAssets,
+        totalSupplyShares: market.totalSupplyShares,
+        totalBorrowAssets: market.totalBorrowAssets,
+        totalBorrowShares: market.totalBorrowShares,
+        lastUpdate: market.lastUpdate,
+        fee: market.fee,
+    }
+}
+
+#[derive(Clone)]
+pub struct MarketState {
+    pub id: B256,
+    pub params: IMorpho::idToMarketParamsReturn,
+    pub market: IMorpho::marketReturn,
+    pub price: Option<U256>,
+    pub borrow_rate: Option<U256>,
+}
+
+impl Serialize for MarketState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let params = IMorpho::MarketParams {
+            loanToken: self.params.loanToken,
+            collateralToken: self.params.collateralToken,
+            oracle: self.params.oracle,
+            irm: self.params.irm,
+            lltv: self.params.lltv,
+        };
+        MarketRow::new(self.id, &params)
+            .with_state(&self.market, self.price, self.borrow_rate)
+            .serialize(serializer)
+    }
+}
+
+// Reads the params, state, oracle price and borrow rate of many markets in a few round trips. Ids
+// that are not created markets are left out.
+pub async fn retrieve_market_states(
+    rpc_url: Url,
+    ids: &[B256],
+    limits: BatchLimits,
+) -> Result<Vec<MarketState>> {
+    // First pass: params and state, needed to build the oracle and IRM calls
+    let calls: Vec<MarketCall> = ids
+        .iter()
+        .flat_map(|id| [MarketCall::IdToMarketParams(*id), MarketCall::Market(*id)])
+        .collect();
+    let results = execute_calls(rpc_url.clone(), &calls, limits).await?;
+
+    let mut states = Vec::new();
+    for (id, pair) in ids.iter().zip(results.chunks(2)) {
+        // createMarket sets the last update, unknown ids are left out as fetch_market_info rejects them
+        if let [Some(MarketCallResult::IdToMarketParams(params)), Some(MarketCallResult::Market(market))] =
+            pair
+        {
+            if market.lastUpdate == 0 {
+                continue;
+            }
+            states.push(MarketState {
+                id: *id,
+                params: params.clone(),
+                market: market.clone(),
+                price: None,
+                borrow_rate: None,
+            });
+        }
+    }
+
+    // Second pass: oracle prices and borrow rates (markets without oracle or IRM simply fail)
+    let calls: Vec<MarketCall> = states
+        .iter()
+        .flat_map(|state| {
+            [
+                MarketCall::OraclePrice(state.params.oracle),
+                MarketCall::BorrowRateView(
+                    state.params.irm,
+                    irm_market_params(&state.params),
+                    irm_market(&state.market),
+                ),
+            ]
+        })
+        .collect();
+    let results = execute_calls(rpc_url, &calls, limits).await?;
+
+    for (state, pair) in states.iter_mut().zip(results.chunks(2)) {
+        if let Some(MarketCallResult::OraclePrice(price)) = pair[0] {
+            state.price = Some(price);
+        }
+        if let Some(MarketCallResult::BorrowRateView(rate)) = pair[1] {
+            state.borrow_rate = Some(rate);
+        }
+    }
+    Ok(states)
+}
+
+// Reads the positions of users on markets, failed reads are returned as None
+pub async fn retrieve_positions(
+    rpc_url: Url,
+    positions: &[(B256, Address)],
+    limits: BatchLimits,
+) -> Result<Vec<Option<IMorpho::positionReturn>>> {
+    let calls: Vec<MarketCall> = positions
+        .iter()
+        .map(|(id, user)| MarketCall::Position(*id, *user))
+        .collect();
+    let results = execute_calls(rpc_url, &calls, limits).await?;
+    Ok(results
+        .into_iter()
+        .map(|result| match result {
+            Some(MarketCallResult::Position(position)) => Some(position),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 bytes of selector, 32 of id and 128 of Call3 overhead
+    const CALL_BYTES: usize = 164;
+    const CALL_GAS: u64 = 15_000;
+
+    fn calls(count: usize) -> Vec<MarketCall> {
+        (0..count)
+            .map(|i| MarketCall::IdToMarketParams(B256::with_last_byte(i as u8)))
+            .collect()
+    }
+
+    fn chunk_lengths(calls: &[MarketCall], limits: BatchLimits) -> Vec<usize> {
+        chunk_calls(calls, limits)
+            .iter()
+            .map(|chunk| chunk.len())
+            .collect()
+    }
+
+    #[test]
+    fn chunks_calls_at_the_calldata_limit() {
+        let limits = BatchLimits {
+            max_calldata_bytes: 3 * CALL_BYTES,
+            max_gas: u64::MAX,
+        };
+        assert!(chunk_calls(&calls(0), limits).is_empty());
+        assert_eq!(chunk_lengths(&calls(3), limits), [3]);
+        assert_eq!(chunk_lengths(&calls(4), limits), [3, 1]);
+        assert_eq!(chunk_lengths(&calls(7), limits), [3, 3, 1]);
+    }
+
+    #[test]
+    fn chunks_calls_at_the_gas_limit() {
+        let limits = BatchLimits {
+            max_calldata_bytes: usize::MAX,
+            max_gas: 2 * CALL_GAS,
+        };
+        assert_eq!(chunk_lengths(&calls(2), limits), [2]);
+        assert_eq!(chunk_lengths(&calls(3), limits), [2, 1]);
+
+        // A call above the limits still gets a batch of its own
+        let limits = BatchLimits {
+            max_calldata_bytes: 1,
+            max_gas: 1,
+        };
+        assert_eq!(chunk_lengths(&calls(2), limits), [1, 1]);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::error::{Error, Result};
+use crate::market::IOracle;
+use crate::output::{checksummed, decimal, decimal_option};
+use crate::vault::IVault;
+use alloy::{
+    network::Ethereum,
+    primitives::{ruint::UintTryFrom, Address, I256, U256, U512},
+    providers::{Provider, ProviderBuilder},
+    sol,
+    transports::{http::reqwest::Url, Transport},
+};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+sol!(
+    #[sol(rpc)]
+    IChainlinkAggregator,
+    "data/abis/chainlink_aggregator.json"
+);
+
+// Chainlink feeds are updated at least once a day (heartbeat), with some margin
+pub const DEFAULT_MAX_FEED_AGE: u64 = 26 * 3600;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedInfo {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    pub description: String,
+    pub decimals: u8,
+    #[serde(serialize_with = "decimal")]
+    pub answer: I256,
+    pub updated_at: u64,
+    pub stale: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultConversion {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    #[serde(serialize_with = "decimal")]
+    pub sample: U256,
+    #[serde(serialize_with = "decimal")]
+    pub assets: U256,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OracleReport {
+    #[serde(serialize_with = "checksummed")]
+    pub oracle: Address,
+    pub base_feed_1: Option<FeedInfo>,
+    pub base_feed_2: Option<FeedInfo>,
+    pub quote_feed_1: Option<FeedInfo>,
+    pub quote_feed_2: Option<FeedInfo>,
+    pub base_vault: Option<VaultConversion>,
+    pub quote_vault: Option<VaultConversion>,
+    #[serde(serialize_with = "decimal")]
+    pub scale_factor: U256,
+    // Scale factor implied by the token and feed decimals, None if it cannot be an integer
+    #[serde(serialize_with = "decimal_option")]
+    pub expected_scale_factor: Option<U256>,
+    #[serde(serialize_with = "decimal")]
+    pub price: U256,
+    #[serde(serialize_with = "decimal")]
+    pub recomputed_price: U256,
+}
+
+impl OracleReport {
+    pub fn feeds(&self) -> impl Iterator<Item = &FeedInfo> {
+        [
+            &self.base_feed_1,
+            &self.base_feed_2,
+            &self.quote_feed_1,
+            &self.quote_feed_2,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    pub fn stale_feeds(&self) -> Vec<&FeedInfo> {
+        self.feeds().filter(|feed| feed.stale).collect()
+    }
+
+    pub fn price_matches(&self) -> bool {
+        self.price == self.recomputed_price
+    }
+
+    pub fn scale_factor_matches(&self) -> bool {
+        self.expected_scale_factor == Some(self.scale_factor)
+    }
+}
+
+async fn inspect_feed<T, P>(
+    provider: &P,
+    feed: Address,
+    now: u64,
+    max_age: u64,
+) -> Result<Option<FeedInfo>>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum> + Clone,
+{
+    // Unused feeds are set to the zero address and count as 1
+    if feed == Address::ZERO {
+        return Ok(None);
+    }
+    let aggregator = IChainlinkAggregator::new(feed, provider.clone());
+    let round = aggregator.latestRoundData().call().await?;
+    let updated_at = round.updatedAt.saturating_to::<u64>();
+    Ok(Some(FeedInfo {
+        address: feed,
+        description: aggregator.description().call().await?._0,
+        decimals: aggregator.decimals().call().await?._0,
+        answer: round.answer,
+        updated_at,
+        stale: now.saturating_sub(updated_at) > max_age,
+    }))
+}
+
+async fn inspect_vault<T, P>(
+    provider: &P,
+    vault: Address,
+    sample: U256,
+) -> Result<Option<VaultConversion>>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum> + Clone,
+{
+    // Unused vaults are set to the zero address with a conversion sample of 1
+    if vault == Address::ZERO {
+        return Ok(None);
+    }
+    let erc4626 = IVault::new(vault, provider.clone());
+    Ok(Some(VaultConversion {
+        address: vault,
+        sample,
+        assets: erc4626.convertToAssets(sample).call().await?._0,
+    }))
+}
+
+fn feed_answer(feed: &Option<FeedInfo>) -> Result<U512> {
+    match feed {
+        Some(feed) if feed.answer.is_negative() => Err(Error::Math(format!(
+            "feed {:#20x} has a negative answer",
+            feed.address
+        ))),
+        Some(feed) => Ok(U512::from(feed.answer.into_raw())),
+        None => Ok(U512::from(1)),
+    }
+}
+
+fn feed_decimals(feed: &Option<FeedInfo>) -> i32 {
+    feed.as_ref().map_or(0, |feed| feed.decimals as i32)
+}
+
+fn vault_assets(vault: &Option<VaultConversion>) -> U512 {
+    vault
+        .as_ref()
+        .map_or(U512::from(1), |vault| U512::from(vault.assets))
+}
+
+fn vault_sample(vault: &Option<VaultConversion>) -> U256 {
+    vault.as_ref().map_or(U256::from(1), |vault| vault.sample)
+}
+
+// Same computation as MorphoChainlinkOracleV2.price()
+pub fn recompute_price(
+    scale_factor: U256,
+    base_vault: &Option<VaultConversion>,
+    base_feed_1: &Option<FeedInfo>,
+    base_feed_2: &Option<FeedInfo>,
+    quote_vault: &Option<VaultConversion>,
+    quote_feed_1: &Option<FeedInfo>,
+    quote_feed_2: &Option<FeedInfo>,
+) -> Result<U256> {
+    let overflow = || Error::Math("oracle price overflows".to_string());
+    let numerator = [
+        vault_assets(base_vault),
+        feed_answer(base_feed_1)?,
+        feed_answer(base_feed_2)?,
+    ]
+    .into_iter()
+    .try_fold(U512::from(scale_factor), |product, factor| {
+        product.checked_mul(factor)
+    })
+    .ok_or_else(overflow)?;
+    let denominator = [feed_answer(quote_feed_1)?, feed_answer(quote_feed_2)?]
+        .into_iter()
+        .try_fold(vault_assets(quote_vault), |product, factor| {
+            product.checked_mul(factor)
+        })
+        .ok_or_else(overflow)?;
+    if denominator.is_zero() {
+        return Err(Error::Math("quote price is zero".to_string()));
+    }
+    U256::uint_try_from(numerator / denominator).map_err(|_| overflow())
+}
+
+// SCALE_FACTOR = 10^(36 + dQ1 + fpQ1 + fpQ2 - dB1 - fpB1 - fpB2) * quoteVaultConversionSample / baseVaultConversionSample
+pub fn expected_scale_factor(
+    base_token_decimals: u8,
+    quote_token_decimals: u8,
+    report: &OracleReport,
+) -> Option<U256> {
+    let exponent = 36
+        + quote_token_decimals as i32
+        + feed_decimals(&report.quote_feed_1)
+        + feed_decimals(&report.quote_feed_2)
+        - base_token_decimals as i32
+        - feed_decimals(&report.base_feed_1)
+        - feed_decimals(&report.base_feed_2);
+    if exponent < 0 {
+        return None;
+    }
+    let scale = U256::from(10).checked_pow(U256::from(exponent))?;
+    scale
+        .checked_mul(vault_sample(&report.quote_vault))?
+        .checked_div(vault_sample(&report.base_vault))
+}
+
+// `base_token_decimals` are the collateral token decimals (or the base vault asset decimals when a base vault is set),
+// `quote_token_decimals` the loan token decimals (or the quote vault asset decimals)
+pub async fn inspect_oracle(
+    rpc_url: Url,
+    oracle_address: Address,
+    base_token_decimals: u8,
+    quote_token_decimals: u8,
+    max_feed_age: u64,
+) -> Result<OracleReport> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let oracle = IOracle::new(oracle_address, provider.clone());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let base_feed_1 = inspect_feed(
+        &provider,
+        oracle.BASE_FEED_1().call().await?._0,
+        now,
+        max_feed_age,
+    )
+    .await?;
+    let base_feed_2 = inspect_feed(
+        &provider,
+        oracle.BASE_FEED_2().call().await?._0,
+        now,
+        max_feed_age,
+    )
+    .await?;
+    let quote_feed_1 = inspect_feed(
+        &provider,
+        oracle.QUOTE_FEED_1().call().await?._0,
+        now,
+        max_feed_age,
+    )
+    .await?;
+    let quote_feed_2 = inspect_feed(
+        &provider,
+        oracle.QUOTE_FEED_2().call().await?._0,
+        now,
+        max_feed_age,
+    )
+    .await?;
+    let base_vault = inspect_vault(
+        &provider,
+        oracle.BASE_VAULT().call().await?._0,
+        oracle.BASE_VAULT_CONVERSION_SAMPLE().call().await?._0,
+    )
+    .await?;
+    let quote_vault = inspect_vault(
+        &provider,
+        oracle.QUOTE_VAULT().call().await?._0,
+        oracle.QUOTE_VAULT_CONVERSION_SAMPLE().call().await?._0,
+    )
+    .await?;
+    let scale_factor = oracle.SCALE_FACTOR().call().await?._0;
+    let price = oracle.price().call().await?._0;
+
+    let recomputed_price = recompute_price(
+        scale_factor,
+        &base_vault,
+        &base_feed_1,
+        &base_feed_2,
+        &quote_vault,
+        &quote_feed_1,
+        &quote_feed_2,
+    )?;
+
+    let mut report = OracleReport {
+        oracle: oracle_address,
+        base_feed_1,
+        base_feed_2,
+        quote_feed_1,
+        quote_feed_2,
+        base_vault,
+        quote_vault,
+        scale_factor,
+        expected_scale_factor: None,
+        price,
+        recomputed_price,
+    };
+    report.expected_scale_factor =
+        expected_scale_factor(base_token_decimals, quote_token_decimals, &report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(decimals: u8, answer: i64) -> Option<FeedInfo> {
+        Some(FeedInfo {
+            address: Address::repeat_byte(0xfe),
+            description: String::new(),
+            decimals,
+            answer: I256::try_from(answer).unwrap(),
+            updated_at: 0,
+            stale: false,
+        })
+    }
+
+    fn oracle_report(
+        base_feeds: [Option<FeedInfo>; 2],
+        quote_feed: Option<FeedInfo>,
+        base_vault: Option<VaultConversion>,
+    ) -> OracleReport {
+        let [base_feed_1, base_feed_2] = base_feeds;
+        OracleReport {
+            oracle: Address::ZERO,
+            base_feed_1,
+            base_feed_2,
+            quote_feed_1: quote_feed,
+            quote_feed_2: None,
+            base_vault,
+            quote_vault: None,
+            scale_factor: U256::ZERO,
+            expected_scale_factor: None,
+            price: U256::ZERO,
+            recomputed_price: U256::ZERO,
+        }
+    }
+
+    fn price(scale_factor: U256, report: &OracleReport) -> Result<U256> {
+        recompute_price(
+            scale_factor,
+            &report.base_vault,
+            &report.base_feed_1,
+            &report.base_feed_2,
+            &report.quote_vault,
+            &report.quote_feed_1,
+            &report.quote_feed_2,
+        )
+    }
+
+    #[test]
+    fn prices_wbtc_in_usdc_through_btc_and_usd_feeds() {
+        // WBTC/BTC and BTC/USD over USDC/USD, all with 8 decimals, for WBTC (8) and USDC (6)
+        let report = oracle_report(
+            [feed(8, 99_950_000), feed(8, 6_000_000_000_000)],
+            feed(8, 100_000_000),
+            None,
+        );
+        let scale_factor = expected_scale_factor(8, 6, &report).unwrap();
+        assert_eq!(scale_factor, U256::from(10).pow(U256::from(26)));
+
+        // 1 WBTC is 59,970 USDC
+        let price = price(scale_factor, &report).unwrap();
+        assert_eq!(
+            price,
+            U256::from(5_997u64) * U256::from(10).pow(U256::from(35))
+        );
+        let one_wbtc = U256::from(100_000_000u64);
+        assert_eq!(
+            one_wbtc * price / U256::from(10).pow(U256::from(36)),
+            U256::from(59_970_000_000u64)
+        );
+    }
+
+    #[test]
+    fn prices_a_vault_share_through_its_conversion_sample() {
+        // sDAI over DAI/USD and USDC/USD: the sample of 1e18 shares divides the scale factor
+        let sdai = VaultConversion {
+            address: Address::repeat_byte(0x5d),
+            sample: U256::from(10).pow(U256::from(18)),
+            assets: U256::from(11) * U256::from(10).pow(U256::from(17)),
+        };
+        let report = oracle_report(
+            [feed(8, 100_000_000), None],
+            feed(8, 100_000_000),
+            Some(sdai),
+        );
+        let scale_factor = expected_scale_factor(18, 6, &report).unwrap();
+        assert_eq!(scale_factor, U256::from(1_000_000));
+
+        // 1 sDAI is 1.1 USDC
+        let price = price(scale_factor, &report).unwrap();
+        assert_eq!(
+            U256::from(10).pow(U256::from(18)) * price / U256::from(10).pow(U256::from(36)),
+            U256::from(1_100_000)
+        );
+    }
+
+    #[test]
+    fn rejects_prices_out_of_range() {
+        // Larger than a U256 after the division, then than a U512 before it
+        let report = oracle_report([feed(8, i64::MAX), feed(8, i64::MAX)], None, None);
+        assert!(matches!(price(U256::MAX, &report), Err(Error::Math(_))));
+        let vault = VaultConversion {
+            address: Address::repeat_byte(0x5d),
+            sample: U256::from(1),
+            assets: U256::MAX,
+        };
+        let report = oracle_report([feed(8, i64::MAX), None], None, Some(vault));
+        assert!(matches!(price(U256::MAX, &report), Err(Error::Math(_))));
+
+        let report = oracle_report([feed(8, 1), None], feed(8, 0), None);
+        assert!(matches!(price(U256::from(1), &report), Err(Error::Math(_))));
+
+        let report = oracle_report([feed(8, -1), None], None, None);
+        assert!(matches!(price(U256::from(1), &report), Err(Error::Math(_))));
+
+        // Base decimals above 36 plus the quote decimals would need a fractional scale factor
+        let report = oracle_report([feed(30, 1), feed(30, 1)], None, None);
+        assert_eq!(expected_scale_factor(18, 6, &report), None);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::error::Result;
+use alloy::primitives::{utils::format_units, Address, U256};
+use comfy_table::Table;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Write},
+};
+
+// Serde helpers for the result types: addresses are serialized with their EIP-55 checksum and
+// amounts as decimal strings (JSON numbers lose precision above 2^53)
+pub fn checksummed<S: Serializer>(
+    address: &Address,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&address.to_checksum(None))
+}
+
+pub fn checksummed_option<S: Serializer>(
+    address: &Option<Address>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match address {
+        Some(address) => checksummed(address, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn checksummed_keys<V: Serialize, S: Serializer>(
+    map: &BTreeMap<Address, V>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        map.iter()
+            .map(|(key, value)| (key.to_checksum(None), value)),
+    )
+}
+
+pub fn decimal<T: Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub fn decimal_option<T: Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // Aligned table, printed once every record is written
+    Table,
+    // One JSON object per line, for jq
+    JsonLines,
+    // Header row with the union of the record fields, for spreadsheets
+    Csv,
+}
+
+// Writes serializable records as flat rows, nested fields are joined with a dot (e.g. "position.collateral")
+pub struct Output<W: Write> {
+    writer: W,
+    format: Format,
+    columns: Vec<String>,
+    rows: Vec<Map<String, Value>>,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(writer: W, format: Format) -> Self {
+        Output {
+            writer,
+            format,
+            columns: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    // `scale` gives the decimals of the amount fields to render in token units, e.g. [("totalSupplyAssets", 6)]
+    pub fn write<T: Serialize>(&mut self, record: &T, scale: &[(&str, u8)]) -> Result<()> {
+        let mut row = Map::new();
+        flatten(
+            "",
+            serde_json::to_value(record).map_err(io::Error::from)?,
+            &mut row,
+        );
+        for (field, decimals) in scale {
+            if let Some(Value::String(raw)) = row.get_mut(*field) {
+                if let Ok(amount) = raw.parse::<U256>() {
+                    // format_units appends ".0" to amounts without decimals
+                    *raw = match decimals {
+                        0 => amount.to_string(),
+                        decimals => format_units(amount, *decimals)?,
+                    };
+                }
+            }
+        }
+
+        match self.format {
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &row).map_err(io::Error::from)?;
+                writeln!(self.writer)?;
+            }
+            Format::Table | Format::Csv => {
+                for key in row.keys() {
+                    if !self.columns.contains(key) {
+                        self.columns.push(key.clone());
+                    }
+                }
+                self.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        match self.format {
+            Format::JsonLines => {}
+            Format::Table => {
+                let mut table = Table::new();
+                table.set_header(&self.columns);
+                for row in &self.rows {
+                    table.add_row(self.columns.iter().map(|column| cell(row.get(column))));
+                }
+                writeln!(self.writer, "{}", table)?;
+            }
+            Format::Csv => {
+                let mut csv = csv::Writer::from_writer(&mut self.writer);
+                csv.write_record(&self.columns)?;
+                for row in &self.rows {
+                    csv.write_record(self.columns.iter().map(|column| cell(row.get(column))))?;
+                }
+                csv.flush()?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn flatten(prefix: &str, value: Value, row: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, row);
+            }
+        }
+        value if prefix.is_empty() => {
+            row.insert("value".to_string(), value);
+        }
+        value => {
+            row.insert(prefix.to_string(), value);
+        }
+    }
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn csv(records: &[Value], scale: &[(&str, u8)]) -> String {
+        let mut buffer = Vec::new();
+        let mut output = Output::new(&mut buffer, Format::Csv);
+        for record in records {
+            output.write(record, scale).unwrap();
+        }
+        output.finish().unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn flattens_nested_fields_into_the_csv_header() {
+        let records = [
+            json!({"id": "a", "position": {"collateral": "1", "borrowShares": "2"}}),
+            json!({"id": "b", "position": {"collateral": "3"}, "healthy": true}),
+        ];
+        assert_eq!(
+            csv(&records, &[]),
+            "id,position.collateral,position.borrowShares,healthy\n\
+             a,1,2,\n\
+             b,3,,true\n"
+        );
+        assert_eq!(csv(&[json!("1")], &[]), "value\n1\n");
+    }
+
+    #[test]
+    fn scales_amounts_to_token_units() {
+        // 2^128 does not fit in a u128
+        let records = [json!({
+            "zero": "1000",
+            "usdc": "1500000",
+            "wei": "340282366920938463463374607431768211456",
+            "label": "n/a",
+        })];
+        let scale = [("zero", 0), ("usdc", 6), ("wei", 18), ("label", 6)];
+        assert_eq!(
+            csv(&records, &scale),
+            "zero,usdc,wei,label\n\
+             1000,1.500000,340282366920938463463.374607431768211456,n/a\n"
+        );
+    }
+}
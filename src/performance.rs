@@ -0,0 +1,290 @@
+use crate::error::{Error, Result};
+use crate::events::get_logs_in_ranges;
+use crate::irm::SECONDS_PER_YEAR;
+use crate::output::decimal;
+use crate::registry::DEFAULT_MAX_BLOCK_RANGE;
+use crate::vault::IVault;
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    network::Ethereum,
+    primitives::{Address, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{BlockTransactionsKind, Filter},
+    sol_types::SolEvent,
+    transports::{http::reqwest::Url, Transport},
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharePricePoint {
+    pub block_number: u64,
+    pub timestamp: u64,
+    #[serde(serialize_with = "decimal")]
+    pub total_assets: U256,
+    #[serde(serialize_with = "decimal")]
+    pub total_supply: U256,
+    // Underlying assets (raw units) per vault share (10^vault decimals raw units)
+    pub share_price: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultPerformance {
+    pub apy_7d: Option<f64>,
+    pub apy_30d: Option<f64>,
+    pub apy_since_inception: Option<f64>,
+    // Fee shares minted to the fee recipient over the whole period
+    #[serde(serialize_with = "decimal")]
+    pub fee_shares: U256,
+}
+
+// Vault shares have DECIMALS_OFFSET more decimals than the underlying asset
+pub fn share_price(total_assets: U256, total_supply: U256, decimals_offset: u8) -> f64 {
+    if total_supply.is_zero() {
+        return 1.0;
+    }
+    f64::from(total_assets) / f64::from(total_supply) * 10f64.powi(decimals_offset as i32)
+}
+
+async fn block_timestamp<T, P>(provider: &P, block_number: u64) -> Result<u64>
+where
+    T: Transport + Clone,
+    P: Provider<T, Ethereum>,
+{
+    let block = provider
+        .get_block_by_number(
+            BlockNumberOrTag::Number(block_number),
+            BlockTransactionsKind::Hashes,
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("block {}", block_number)))?;
+    Ok(block.header.timestamp)
+}
+
+// Samples the share price every `interval` blocks through historical eth_call
+pub async fn sample_share_prices(
+    rpc_url: Url,
+    vault_address: Address,
+    from_block: u64,
+    to_block: u64,
+    interval: u64,
+) -> Result<Vec<SharePricePoint>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let vault = IVault::new(vault_address, provider.clone());
+    let decimals_offset = vault.DECIMALS_OFFSET().call().await?._0;
+
+    let mut points = Vec::new();
+    let mut block_number = from_block;
+    while block_number <= to_block {
+        let block = BlockId::number(block_number);
+        let total_assets = vault.totalAssets().block(block).call().await?.assets;
+        let total_supply = vault.totalSupply().block(block).call().await?._0;
+        points.push(SharePricePoint {
+            block_number,
+            timestamp: block_timestamp(&provider, block_number).await?,
+            total_assets,
+            total_supply,
+            share_price: share_price(total_assets, total_supply, decimals_offset),
+        });
+        block_number += interval.max(1);
+    }
+    Ok(points)
+}
+
+// Rebuilds the share price after every vault interaction from its logs.
+// The total supply moves with mints and burns, the total assets with UpdateLastTotalAssets.
+pub async fn reconstruct_share_prices(
+    rpc_url: Url,
+    vault_address: Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<(Vec<SharePricePoint>, U256)> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let vault = IVault::new(vault_address, provider.clone());
+    let decimals_offset = vault.DECIMALS_OFFSET().call().await?._0;
+
+    let start = BlockId::number(from_block.saturating_sub(1));
+    let mut total_assets = vault.totalAssets().block(start).call().await?.assets;
+    let mut total_supply = vault.totalSupply().block(start).call().await?._0;
+
+    let filter = Filter::new().address(vault_address).events([
+        IVault::UpdateLastTotalAssets::SIGNATURE_HASH,
+        IVault::AccrueInterest::SIGNATURE_HASH,
+        IVault::Deposit::SIGNATURE_HASH,
+        IVault::Withdraw::SIGNATURE_HASH,
+        IVault::Transfer::SIGNATURE_HASH,
+    ]);
+
+    let logs = get_logs_in_ranges(
+        &provider,
+        &filter,
+        from_block,
+        to_block,
+        DEFAULT_MAX_BLOCK_RANGE,
+    )
+    .await?;
+
+    let mut points: Vec<SharePricePoint> = Vec::new();
+    let mut fee_shares = U256::ZERO;
+    for log in logs {
+        match log.topic0() {
+            Some(&IVault::Transfer::SIGNATURE_HASH) => {
+                let IVault::Transfer { from, to, value } = log.log_decode()?.inner.data;
+                if from == Address::ZERO {
+                    total_supply += value;
+                }
+                if to == Address::ZERO {
+                    total_supply -= value;
+                }
+            }
+            Some(&IVault::AccrueInterest::SIGNATURE_HASH) => {
+                let IVault::AccrueInterest {
+                    newTotalAssets,
+                    feeShares,
+                } = log.log_decode()?.inner.data;
+                total_assets = newTotalAssets;
+                fee_shares += feeShares;
+            }
+            Some(&IVault::UpdateLastTotalAssets::SIGNATURE_HASH) => {
+                let IVault::UpdateLastTotalAssets { updatedTotalAssets } =
+                    log.log_decode()?.inner.data;
+                total_assets = updatedTotalAssets;
+            }
+            // Deposits and withdrawals are followed by a Transfer and an UpdateLastTotalAssets
+            _ => continue,
+        }
+
+        let block_number = log
+            .block_number
+            .ok_or_else(|| Error::NotFound("block number of a log".to_string()))?;
+        let timestamp = match log.block_timestamp {
+            Some(timestamp) => timestamp,
+            None => match points.last() {
+                Some(last) if last.block_number == block_number => last.timestamp,
+                _ => block_timestamp(&provider, block_number).await?,
+            },
+        };
+        let point = SharePricePoint {
+            block_number,
+            timestamp,
+            total_assets,
+            total_supply,
+            share_price: share_price(total_assets, total_supply, decimals_offset),
+        };
+        // Keep only the last state of each block
+        match points.last_mut() {
+            Some(last) if last.block_number == block_number => *last = point,
+            _ => points.push(point),
+        }
+    }
+    Ok((points, fee_shares))
+}
+
+// Annualized share price growth between two points
+pub fn apy_between(start: &SharePricePoint, end: &SharePricePoint) -> Option<f64> {
+    let elapsed = end.timestamp.checked_sub(start.timestamp)? as f64;
+    if elapsed == 0.0 || start.share_price == 0.0 {
+        return None;
+    }
+    Some((end.share_price / start.share_price).powf(SECONDS_PER_YEAR / elapsed) - 1.0)
+}
+
+// Realized APY over the last `window` seconds, or since the first point when `window` is None
+pub fn realized_apy(points: &[SharePricePoint], window: Option<u64>) -> Option<f64> {
+    let end = points.last()?;
+    let start = match window {
+        Some(window) => {
+            let since = end.timestamp.checked_sub(window)?;
+            // The window must be fully covered by the series
+            if points.first()?.timestamp > since {
+                return None;
+            }
+            points.iter().rev().find(|p| p.timestamp <= since)?
+        }
+        None => points.first()?,
+    };
+    apy_between(start, end)
+}
+
+pub fn compute_performance(points: &[SharePricePoint], fee_shares: U256) -> VaultPerformance {
+    VaultPerformance {
+        apy_7d: realized_apy(points, Some(7 * 24 * 3600)),
+        apy_30d: realized_apy(points, Some(30 * 24 * 3600)),
+        apy_since_inception: realized_apy(points, None),
+        fee_shares,
+    }
+}
+
+pub fn write_share_prices_csv(path: &str, points: &[SharePricePoint]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "block_number",
+        "timestamp",
+        "total_assets",
+        "total_supply",
+        "share_price",
+    ])?;
+    for point in points {
+        writer.write_record([
+            point.block_number.to_string(),
+            point.timestamp.to_string(),
+            point.total_assets.to_string(),
+            point.total_supply.to_string(),
+            point.share_price.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 3600;
+
+    fn point(timestamp: u64, share_price: f64) -> SharePricePoint {
+        SharePricePoint {
+            block_number: timestamp / 12,
+            timestamp,
+            total_assets: U256::ZERO,
+            total_supply: U256::ZERO,
+            share_price,
+        }
+    }
+
+    #[test]
+    fn annualizes_the_share_price_growth() {
+        let start = point(0, 1.0);
+        // 1% over half a year compounds to 2.01% a year
+        let end = point((SECONDS_PER_YEAR / 2.0) as u64, 1.01);
+        let apy = apy_between(&start, &end).unwrap();
+        assert!((apy - 0.0201).abs() < 1e-9);
+
+        assert_eq!(apy_between(&start, &point(0, 1.01)), None);
+        assert_eq!(apy_between(&end, &start), None);
+        assert_eq!(apy_between(&point(0, 0.0), &end), None);
+    }
+
+    #[test]
+    fn realized_apy_needs_a_covered_window() {
+        let points = [
+            point(100 * DAY, 1.0),
+            point(110 * DAY, 1.001),
+            point(130 * DAY, 1.002),
+            point(140 * DAY, 1.003),
+        ];
+        // The window starts at the last point before it, 130 days for 7 days back from 140
+        let apy_7d = realized_apy(&points, Some(7 * DAY)).unwrap();
+        let expected = apy_between(&points[2], &points[3]).unwrap();
+        assert_eq!(apy_7d, expected);
+        assert_eq!(
+            realized_apy(&points, None),
+            apy_between(&points[0], &points[3])
+        );
+        // 60 days back is before the first point
+        assert_eq!(realized_apy(&points, Some(60 * DAY)), None);
+        assert_eq!(realized_apy(&[], None), None);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::error::Result;
+use crate::events::get_logs_in_ranges;
+use crate::output::{checksummed, decimal};
+use crate::vault::{IVault, IVaultFactory};
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{address, Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VaultFactory {
+    pub chain_id: u64,
+    pub address: Address,
+    pub version: &'static str,
+    pub deployment_block: u64,
+}
+
+// Known MetaMorpho factories
+pub const VAULT_FACTORIES: [VaultFactory; 4] = [
+    VaultFactory {
+        chain_id: 1,
+        address: address!("A9c3D3a366466Fa809d1Ae982Fb2c46E5fC41101"),
+        version: "v1.0",
+        deployment_block: 18_925_584,
+    },
+    VaultFactory {
+        chain_id: 1,
+        address: address!("1897A8997241C1cD4bD0698647e4EB7213535c24"),
+        version: "v1.1",
+        deployment_block: 21_439_510,
+    },
+    VaultFactory {
+        chain_id: 8453,
+        address: address!("A9c3D3a366466Fa809d1Ae982Fb2c46E5fC41101"),
+        version: "v1.0",
+        deployment_block: 13_978_134,
+    },
+    VaultFactory {
+        chain_id: 8453,
+        address: address!("FF62A7c278C62eD665133147129245053Bbf5918"),
+        version: "v1.1",
+        deployment_block: 23_928_808,
+    },
+];
+
+// Largest block range of one eth_getLogs request
+pub const DEFAULT_MAX_BLOCK_RANGE: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultRecord {
+    pub chain_id: u64,
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub factory: Address,
+    pub version: String,
+    pub created_at_block: Option<u64>,
+    #[serde(serialize_with = "checksummed")]
+    pub caller: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub initial_owner: Address,
+    #[serde(serialize_with = "decimal")]
+    pub initial_timelock: U256,
+    #[serde(serialize_with = "checksummed")]
+    pub asset: Address,
+    pub name: String,
+    pub symbol: String,
+    pub salt: B256,
+    // Current roles, read when the vault is indexed then followed through SetCurator and
+    // OwnershipTransferred logs
+    #[serde(serialize_with = "checksummed")]
+    pub owner: Address,
+    #[serde(serialize_with = "checksummed")]
+    pub curator: Address,
+}
+
+#[derive(Debug, Clone)]
+pub struct VaultRegistry {
+    pub chain_id: u64,
+    pub vaults: BTreeMap<Address, VaultRecord>,
+    // Last indexed block per factory
+    pub checkpoints: BTreeMap<Address, u64>,
+    pub max_block_range: u64,
+}
+
+impl VaultRegistry {
+    pub fn new(chain_id: u64) -> Self {
+        Self {
+            chain_id,
+            vaults: BTreeMap::new(),
+            checkpoints: BTreeMap::new(),
+            max_block_range: DEFAULT_MAX_BLOCK_RANGE,
+        }
+    }
+
+    pub fn factories(&self) -> impl Iterator<Item = &'static VaultFactory> + '_ {
+        VAULT_FACTORIES
+            .iter()
+            .filter(move |factory| factory.chain_id == self.chain_id)
+    }
+
+    // Indexes the vaults created since the last indexed block of every factory, and returns how many were added.
+    // The roles of the known vaults are updated from their logs since the oldest checkpoint.
+    // Logs are fetched `max_block_range` blocks at a time and the checkpoints move after each range.
+    pub async fn refresh(&mut self, rpc_url: Url) -> Result<usize> {
+        let provider = ProviderBuilder::new().on_http(rpc_url);
+        let latest_block = provider.get_block_number().await?;
+
+        // Roles of the known vaults changed since the oldest checkpoint, before any checkpoint moves
+        let known: Vec<Address> = self.vaults.keys().copied().collect();
+        if let Some(checkpoint) = self.checkpoints.values().min() {
+            if !known.is_empty() && *checkpoint < latest_block {
+                let filter = Filter::new().address(known).events([
+                    IVault::SetCurator::SIGNATURE_HASH,
+                    IVault::OwnershipTransferred::SIGNATURE_HASH,
+                ]);
+                let logs = get_logs_in_ranges(
+                    &provider,
+                    &filter,
+                    checkpoint + 1,
+                    latest_block,
+                    self.max_block_range,
+                )
+                .await?;
+                for log in &logs {
+                    self.apply_role_log(log)?;
+                }
+            }
+        }
+
+        let factories: Vec<VaultFactory> = self.factories().copied().collect();
+        let mut added = 0;
+        for factory in factories {
+            let from_block = match self.checkpoints.get(&factory.address) {
+                Some(checkpoint) => checkpoint + 1,
+                None => factory.deployment_block,
+            };
+            let factory_contract = IVaultFactory::new(factory.address, provider.clone());
+
+            let range = self.max_block_range.max(1);
+            for from_block in (from_block..=latest_block).step_by(range as usize) {
+                let to_block = latest_block.min(from_block + range - 1);
+                let filter = Filter::new()
+                    .address(factory.address)
+                    .from_block(BlockNumberOrTag::Number(from_block))
+                    .to_block(BlockNumberOrTag::Number(to_block))
+                    .event_signature(IVaultFactory::CreateMetaMorpho::SIGNATURE_HASH);
+
+                for log in provider.get_logs(&filter).await? {
+                    let IVaultFactory::CreateMetaMorpho {
+                        metaMorpho,
+                        caller,
+                        initialOwner,
+                        initialTimelock,
+                        asset,
+                        name,
+                        symbol,
+                        salt,
+                    } = log.log_decode()?.inner.data;
+
+                    // Only keep vaults the factory recognizes
+                    if !factory_contract.isMetaMorpho(metaMorpho).call().await?._0 {
+                        continue;
+                    }
+
+                    let vault = IVault::new(metaMorpho, provider.clone());
+                    let record = VaultRecord {
+                        chain_id: self.chain_id,
+                        address: metaMorpho,
+                        factory: factory.address,
+                        version: factory.version.to_string(),
+                        created_at_block: log.block_number,
+                        caller,
+                        initial_owner: initialOwner,
+                        initial_timelock: initialTimelock,
+                        asset,
+                        name,
+                        symbol,
+                        salt,
+                        owner: vault.owner().call().await?._0,
+                        curator: vault.curator().call().await?._0,
+                    };
+                    if self.vaults.insert(metaMorpho, record).is_none() {
+                        added += 1;
+                    }
+                }
+                self.checkpoints.insert(factory.address, to_block);
+            }
+        }
+        Ok(added)
+    }
+
+    fn apply_role_log(&mut self, log: &Log) -> Result<()> {
+        let Some(record) = self.vaults.get_mut(&log.address()) else {
+            return Ok(());
+        };
+        match log.topic0() {
+            Some(&IVault::SetCurator::SIGNATURE_HASH) => {
+                record.curator = log
+                    .log_decode::<IVault::SetCurator>()?
+                    .inner
+                    .data
+                    .newCurator;
+            }
+            Some(&IVault::OwnershipTransferred::SIGNATURE_HASH) => {
+                record.owner = log
+                    .log_decode::<IVault::OwnershipTransferred>()?
+                    .inner
+                    .data
+                    .newOwner;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub fn by_asset(&self, asset: Address) -> Vec<&VaultRecord> {
+        self.vaults.values().filter(|v| v.asset == asset).collect()
+    }
+
+    pub fn by_curator(&self, curator: Address) -> Vec<&VaultRecord> {
+        self.vaults
+            .values()
+            .filter(|v| v.curator == curator)
+            .collect()
+    }
+
+    pub fn by_owner(&self, owner: Address) -> Vec<&VaultRecord> {
+        self.vaults.values().filter(|v| v.owner == owner).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT: Address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    const DEPLOYER: Address = address!("000000000000000000000000000000000000dEaD");
+    const CURATOR: Address = address!("000000000000000000000000000000000000C0DE");
+    const OWNER: Address = address!("0000000000000000000000000000000000000B0B");
+
+    fn log<E: SolEvent>(event: E) -> Log {
+        Log {
+            inner: alloy::primitives::Log {
+                address: VAULT,
+                data: event.encode_log_data(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn follows_the_roles_set_after_the_creation() {
+        let mut registry = VaultRegistry::new(1);
+        registry.vaults.insert(
+            VAULT,
+            VaultRecord {
+                chain_id: 1,
+                address: VAULT,
+                factory: VAULT_FACTORIES[0].address,
+                version: "v1.0".to_string(),
+                created_at_block: Some(18_925_584),
+                caller: DEPLOYER,
+                initial_owner: DEPLOYER,
+                initial_timelock: U256::ZERO,
+                asset: Address::ZERO,
+                name: "Vault".to_string(),
+                symbol: "V".to_string(),
+                salt: B256::ZERO,
+                owner: DEPLOYER,
+                curator: Address::ZERO,
+            },
+        );
+
+        let logs = [
+            log(IVault::SetCurator {
+                newCurator: CURATOR,
+            }),
+            log(IVault::OwnershipTransferred {
+                previousOwner: DEPLOYER,
+                newOwner: OWNER,
+            }),
+        ];
+        for log in &logs {
+            registry.apply_role_log(log).unwrap();
+        }
+        assert_eq!(registry.by_curator(CURATOR).len(), 1);
+        assert_eq!(registry.by_owner(OWNER).len(), 1);
+        assert!(registry.by_owner(DEPLOYER).is_empty());
+    }
+}
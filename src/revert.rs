@@ -0,0 +1,407 @@
+use crate::vault::IVault::IVaultErrors;
+use alloy::{
+    primitives::{Bytes, U256},
+    sol,
+    sol_types::{Panic, Revert, SolError, SolInterface},
+    transports::TransportError,
+};
+use std::fmt;
+
+sol! {
+    // Raised by OpenZeppelin's Math.mulDiv, used by MorphoChainlinkOracleV2 to compute its price
+    error MathOverflowedMulDiv();
+}
+
+// Morpho Blue reverts with the strings of ErrorsLib.sol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorphoRevert {
+    NotOwner,
+    MaxLltvExceeded,
+    MaxFeeExceeded,
+    AlreadySet,
+    IrmNotEnabled,
+    LltvNotEnabled,
+    MarketAlreadyCreated,
+    NoCode,
+    MarketNotCreated,
+    InconsistentInput,
+    ZeroAssets,
+    ZeroAddress,
+    Unauthorized,
+    InsufficientCollateral,
+    InsufficientLiquidity,
+    HealthyPosition,
+    InvalidSignature,
+    SignatureExpired,
+    InvalidNonce,
+    TransferReverted,
+    TransferReturnedFalse,
+    TransferFromReverted,
+    TransferFromReturnedFalse,
+    MaxUint128Exceeded,
+}
+
+impl MorphoRevert {
+    pub fn from_reason(reason: &str) -> Option<Self> {
+        Some(match reason {
+            "not owner" => MorphoRevert::NotOwner,
+            "max LLTV exceeded" => MorphoRevert::MaxLltvExceeded,
+            "max fee exceeded" => MorphoRevert::MaxFeeExceeded,
+            "already set" => MorphoRevert::AlreadySet,
+            "IRM not enabled" => MorphoRevert::IrmNotEnabled,
+            "LLTV not enabled" => MorphoRevert::LltvNotEnabled,
+            "market already created" => MorphoRevert::MarketAlreadyCreated,
+            "no code" => MorphoRevert::NoCode,
+            "market not created" => MorphoRevert::MarketNotCreated,
+            "inconsistent input" => MorphoRevert::InconsistentInput,
+            "zero assets" => MorphoRevert::ZeroAssets,
+            "zero address" => MorphoRevert::ZeroAddress,
+            "unauthorized" => MorphoRevert::Unauthorized,
+            "insufficient collateral" => MorphoRevert::InsufficientCollateral,
+            "insufficient liquidity" => MorphoRevert::InsufficientLiquidity,
+            "position is healthy" => MorphoRevert::HealthyPosition,
+            "invalid signature" => MorphoRevert::InvalidSignature,
+            "signature expired" => MorphoRevert::SignatureExpired,
+            "invalid nonce" => MorphoRevert::InvalidNonce,
+            "transfer reverted" => MorphoRevert::TransferReverted,
+            "transfer returned false" => MorphoRevert::TransferReturnedFalse,
+            "transferFrom reverted" => MorphoRevert::TransferFromReverted,
+            "transferFrom returned false" => MorphoRevert::TransferFromReturnedFalse,
+            "max uint128 exceeded" => MorphoRevert::MaxUint128Exceeded,
+            _ => return None,
+        })
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            MorphoRevert::NotOwner => "the caller is not the owner of Morpho",
+            MorphoRevert::MaxLltvExceeded => "the LLTV is above 100%",
+            MorphoRevert::MaxFeeExceeded => "the fee is above the maximum fee (25%)",
+            MorphoRevert::AlreadySet => "the value is already set",
+            MorphoRevert::IrmNotEnabled => "the IRM is not enabled by governance",
+            MorphoRevert::LltvNotEnabled => "the LLTV is not enabled by governance",
+            MorphoRevert::MarketAlreadyCreated => "a market with these params already exists",
+            MorphoRevert::NoCode => "the token of the flash loan or the callback has no code",
+            MorphoRevert::MarketNotCreated => "no market exists with these params",
+            MorphoRevert::InconsistentInput => {
+                "exactly one of assets and shares must be zero (or seized assets and repaid shares)"
+            }
+            MorphoRevert::ZeroAssets => "the amount of assets is zero",
+            MorphoRevert::ZeroAddress => "the onBehalf or receiver address is zero",
+            MorphoRevert::Unauthorized => {
+                "the caller is neither onBehalf nor authorized by it (see setAuthorization)"
+            }
+            MorphoRevert::InsufficientCollateral => {
+                "the position would not be healthy: not enough collateral for the borrow"
+            }
+            MorphoRevert::InsufficientLiquidity => {
+                "the market does not have enough supplied assets left to borrow or withdraw"
+            }
+            MorphoRevert::HealthyPosition => "the position is healthy and cannot be liquidated",
+            MorphoRevert::InvalidSignature => "the authorization signer is not the authorizer",
+            MorphoRevert::SignatureExpired => "the authorization deadline has passed",
+            MorphoRevert::InvalidNonce => "the authorization nonce is not the current nonce",
+            MorphoRevert::TransferReverted => "the token transfer reverted",
+            MorphoRevert::TransferReturnedFalse => "the token transfer returned false",
+            MorphoRevert::TransferFromReverted => {
+                "the token transferFrom reverted, check the balance and the allowance to Morpho"
+            }
+            MorphoRevert::TransferFromReturnedFalse => "the token transferFrom returned false",
+            MorphoRevert::MaxUint128Exceeded => "an amount does not fit in 128 bits",
+        }
+    }
+}
+
+// Revert data returned by the node when an eth_call fails
+pub fn revert_data(error: &TransportError) -> Option<Bytes> {
+    error
+        .as_error_resp()
+        .and_then(|payload| payload.as_revert_data())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    Morpho(MorphoRevert),
+    Vault(IVaultErrors),
+    MathOverflowedMulDiv,
+    // Error(string) reverts not coming from Morpho
+    Reason(String),
+    Panic(U256),
+    Unknown(Bytes),
+}
+
+impl ProtocolError {
+    // Decodes revert data. Morpho string reverts and the oracle mulDiv error are tried before the vault errors
+    // (the vault declares MathOverflowedMulDiv too).
+    pub fn decode(data: &[u8]) -> Self {
+        if let Ok(revert) = Revert::abi_decode(data, true) {
+            return match MorphoRevert::from_reason(&revert.reason) {
+                Some(morpho_revert) => ProtocolError::Morpho(morpho_revert),
+                None => ProtocolError::Reason(revert.reason),
+            };
+        }
+        if let Ok(panic) = Panic::abi_decode(data, true) {
+            return ProtocolError::Panic(panic.code);
+        }
+        if data.starts_with(&MathOverflowedMulDiv::SELECTOR) {
+            return ProtocolError::MathOverflowedMulDiv;
+        }
+        match IVaultErrors::abi_decode(data, true) {
+            Ok(vault_error) => ProtocolError::Vault(vault_error),
+            Err(_) => ProtocolError::Unknown(Bytes::copy_from_slice(data)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ProtocolError::Morpho(morpho_revert) => format!("{:?}", morpho_revert),
+            ProtocolError::Vault(vault_error) => vault_error_name(vault_error).to_string(),
+            ProtocolError::MathOverflowedMulDiv => "MathOverflowedMulDiv".to_string(),
+            ProtocolError::Reason(_) => "Error".to_string(),
+            ProtocolError::Panic(_) => "Panic".to_string(),
+            ProtocolError::Unknown(_) => "Unknown".to_string(),
+        }
+    }
+
+    pub fn explanation(&self) -> String {
+        match self {
+            ProtocolError::Morpho(morpho_revert) => morpho_revert.explanation().to_string(),
+            ProtocolError::Vault(vault_error) => match vault_error_details(vault_error) {
+                Some(details) => format!("{} ({})", vault_error_explanation(vault_error), details),
+                None => vault_error_explanation(vault_error).to_string(),
+            },
+            ProtocolError::MathOverflowedMulDiv => {
+                "a mulDiv overflowed, usually the oracle price computation (feed answers or scale factor)"
+                    .to_string()
+            }
+            ProtocolError::Reason(reason) => reason.clone(),
+            ProtocolError::Panic(code) => match code.saturating_to::<u64>() {
+                0x01 => "assertion failed".to_string(),
+                0x11 => "arithmetic overflow or underflow".to_string(),
+                0x12 => "division by zero".to_string(),
+                0x32 => "array index out of bounds".to_string(),
+                _ => format!("panic with code {:#x}", code),
+            },
+            ProtocolError::Unknown(data) => format!("unknown revert data {}", data),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name(), self.explanation())
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+pub fn vault_error_name(error: &IVaultErrors) -> &'static str {
+    match error {
+        IVaultErrors::AboveMaxTimelock(_) => "AboveMaxTimelock",
+        IVaultErrors::AddressEmptyCode(_) => "AddressEmptyCode",
+        IVaultErrors::AddressInsufficientBalance(_) => "AddressInsufficientBalance",
+        IVaultErrors::AllCapsReached(_) => "AllCapsReached",
+        IVaultErrors::AlreadyPending(_) => "AlreadyPending",
+        IVaultErrors::AlreadySet(_) => "AlreadySet",
+        IVaultErrors::BelowMinTimelock(_) => "BelowMinTimelock",
+        IVaultErrors::DuplicateMarket(_) => "DuplicateMarket",
+        IVaultErrors::ECDSAInvalidSignature(_) => "ECDSAInvalidSignature",
+        IVaultErrors::ECDSAInvalidSignatureLength(_) => "ECDSAInvalidSignatureLength",
+        IVaultErrors::ECDSAInvalidSignatureS(_) => "ECDSAInvalidSignatureS",
+        IVaultErrors::ERC20InsufficientAllowance(_) => "ERC20InsufficientAllowance",
+        IVaultErrors::ERC20InsufficientBalance(_) => "ERC20InsufficientBalance",
+        IVaultErrors::ERC20InvalidApprover(_) => "ERC20InvalidApprover",
+        IVaultErrors::ERC20InvalidReceiver(_) => "ERC20InvalidReceiver",
+        IVaultErrors::ERC20InvalidSender(_) => "ERC20InvalidSender",
+        IVaultErrors::ERC20InvalidSpender(_) => "ERC20InvalidSpender",
+        IVaultErrors::ERC2612ExpiredSignature(_) => "ERC2612ExpiredSignature",
+        IVaultErrors::ERC2612InvalidSigner(_) => "ERC2612InvalidSigner",
+        IVaultErrors::ERC4626ExceededMaxDeposit(_) => "ERC4626ExceededMaxDeposit",
+        IVaultErrors::ERC4626ExceededMaxMint(_) => "ERC4626ExceededMaxMint",
+        IVaultErrors::ERC4626ExceededMaxRedeem(_) => "ERC4626ExceededMaxRedeem",
+        IVaultErrors::ERC4626ExceededMaxWithdraw(_) => "ERC4626ExceededMaxWithdraw",
+        IVaultErrors::FailedInnerCall(_) => "FailedInnerCall",
+        IVaultErrors::InconsistentAsset(_) => "InconsistentAsset",
+        IVaultErrors::InconsistentReallocation(_) => "InconsistentReallocation",
+        IVaultErrors::InvalidAccountNonce(_) => "InvalidAccountNonce",
+        IVaultErrors::InvalidMarketRemovalNonZeroCap(_) => "InvalidMarketRemovalNonZeroCap",
+        IVaultErrors::InvalidMarketRemovalNonZeroSupply(_) => "InvalidMarketRemovalNonZeroSupply",
+        IVaultErrors::InvalidMarketRemovalTimelockNotElapsed(_) => {
+            "InvalidMarketRemovalTimelockNotElapsed"
+        }
+        IVaultErrors::InvalidShortString(_) => "InvalidShortString",
+        IVaultErrors::MarketNotCreated(_) => "MarketNotCreated",
+        IVaultErrors::MarketNotEnabled(_) => "MarketNotEnabled",
+        IVaultErrors::MathOverflowedMulDiv(_) => "MathOverflowedMulDiv",
+        IVaultErrors::MaxFeeExceeded(_) => "MaxFeeExceeded",
+        IVaultErrors::MaxQueueLengthExceeded(_) => "MaxQueueLengthExceeded",
+        IVaultErrors::NoPendingValue(_) => "NoPendingValue",
+        IVaultErrors::NonZeroCap(_) => "NonZeroCap",
+        IVaultErrors::NotAllocatorRole(_) => "NotAllocatorRole",
+        IVaultErrors::NotCuratorNorGuardianRole(_) => "NotCuratorNorGuardianRole",
+        IVaultErrors::NotCuratorRole(_) => "NotCuratorRole",
+        IVaultErrors::NotEnoughLiquidity(_) => "NotEnoughLiquidity",
+        IVaultErrors::NotGuardianRole(_) => "NotGuardianRole",
+        IVaultErrors::OwnableInvalidOwner(_) => "OwnableInvalidOwner",
+        IVaultErrors::OwnableUnauthorizedAccount(_) => "OwnableUnauthorizedAccount",
+        IVaultErrors::PendingCap(_) => "PendingCap",
+        IVaultErrors::PendingRemoval(_) => "PendingRemoval",
+        IVaultErrors::SafeCastOverflowedUintDowncast(_) => "SafeCastOverflowedUintDowncast",
+        IVaultErrors::SafeERC20FailedOperation(_) => "SafeERC20FailedOperation",
+        IVaultErrors::StringTooLong(_) => "StringTooLong",
+        IVaultErrors::SupplyCapExceeded(_) => "SupplyCapExceeded",
+        IVaultErrors::TimelockNotElapsed(_) => "TimelockNotElapsed",
+        IVaultErrors::UnauthorizedMarket(_) => "UnauthorizedMarket",
+        IVaultErrors::ZeroAddress(_) => "ZeroAddress",
+        IVaultErrors::ZeroFeeRecipient(_) => "ZeroFeeRecipient",
+    }
+}
+
+// Amounts and addresses of the errors a deposit, a withdrawal or a permit can run into
+fn vault_error_details(error: &IVaultErrors) -> Option<String> {
+    Some(match error {
+        IVaultErrors::ERC4626ExceededMaxDeposit(e) => {
+            format!("{} assets for {:#20x}, max {}", e.assets, e.receiver, e.max)
+        }
+        IVaultErrors::ERC4626ExceededMaxMint(e) => {
+            format!("{} shares for {:#20x}, max {}", e.shares, e.receiver, e.max)
+        }
+        IVaultErrors::ERC4626ExceededMaxWithdraw(e) => {
+            format!("{} assets of {:#20x}, max {}", e.assets, e.owner, e.max)
+        }
+        IVaultErrors::ERC4626ExceededMaxRedeem(e) => {
+            format!("{} shares of {:#20x}, max {}", e.shares, e.owner, e.max)
+        }
+        IVaultErrors::ERC20InsufficientAllowance(e) => format!(
+            "allowance of {:#20x} is {}, {} needed",
+            e.spender, e.allowance, e.needed
+        ),
+        IVaultErrors::ERC20InsufficientBalance(e) => format!(
+            "balance of {:#20x} is {}, {} needed",
+            e.sender, e.balance, e.needed
+        ),
+        IVaultErrors::ERC2612ExpiredSignature(e) => format!("deadline {}", e.deadline),
+        IVaultErrors::ERC2612InvalidSigner(e) => {
+            format!("signed by {:#20x} instead of {:#20x}", e.signer, e.owner)
+        }
+        _ => return None,
+    })
+}
+
+pub fn vault_error_explanation(error: &IVaultErrors) -> &'static str {
+    match error {
+        IVaultErrors::AboveMaxTimelock(_) => "the timelock is above the maximum (2 weeks)",
+        IVaultErrors::AddressEmptyCode(_) => "the called address has no code",
+        IVaultErrors::AddressInsufficientBalance(_) => "the vault does not have enough ETH",
+        IVaultErrors::AllCapsReached(_) => {
+            "the deposit cannot be supplied: every market of the supply queue is at its cap"
+        }
+        IVaultErrors::AlreadyPending(_) => "the same value is already pending",
+        IVaultErrors::AlreadySet(_) => "the value is already set",
+        IVaultErrors::BelowMinTimelock(_) => "the timelock is below the minimum (1 day)",
+        IVaultErrors::DuplicateMarket(_) => "a market appears twice in the queue",
+        IVaultErrors::ECDSAInvalidSignature(_) => "the permit signature is invalid",
+        IVaultErrors::ECDSAInvalidSignatureLength(_) => {
+            "the permit signature has an invalid length"
+        }
+        IVaultErrors::ECDSAInvalidSignatureS(_) => "the permit signature has a malleable s",
+        IVaultErrors::ERC20InsufficientAllowance(_) => "the allowance of the spender is too low",
+        IVaultErrors::ERC20InsufficientBalance(_) => "the share balance of the sender is too low",
+        IVaultErrors::ERC20InvalidApprover(_) => "the approver is the zero address",
+        IVaultErrors::ERC20InvalidReceiver(_) => "the receiver is the zero address",
+        IVaultErrors::ERC20InvalidSender(_) => "the sender is the zero address",
+        IVaultErrors::ERC20InvalidSpender(_) => "the spender is the zero address",
+        IVaultErrors::ERC2612ExpiredSignature(_) => "the permit deadline has passed",
+        IVaultErrors::ERC2612InvalidSigner(_) => "the permit was not signed by the owner",
+        IVaultErrors::ERC4626ExceededMaxDeposit(_) => "the deposit exceeds maxDeposit",
+        IVaultErrors::ERC4626ExceededMaxMint(_) => "the mint exceeds maxMint",
+        IVaultErrors::ERC4626ExceededMaxRedeem(_) => {
+            "the redemption exceeds maxRedeem (balance or vault liquidity)"
+        }
+        IVaultErrors::ERC4626ExceededMaxWithdraw(_) => {
+            "the withdrawal exceeds maxWithdraw (balance or vault liquidity)"
+        }
+        IVaultErrors::FailedInnerCall(_) => "a low level call failed",
+        IVaultErrors::InconsistentAsset(_) => "the market loan token is not the vault asset",
+        IVaultErrors::InconsistentReallocation(_) => {
+            "the reallocation does not withdraw as much as it supplies"
+        }
+        IVaultErrors::InvalidAccountNonce(_) => "the permit nonce is not the current nonce",
+        IVaultErrors::InvalidMarketRemovalNonZeroCap(_) => {
+            "a market can only be removed from the withdraw queue with a zero cap"
+        }
+        IVaultErrors::InvalidMarketRemovalNonZeroSupply(_) => {
+            "a market with a supply can only be removed after its removal timelock"
+        }
+        IVaultErrors::InvalidMarketRemovalTimelockNotElapsed(_) => {
+            "the market removal timelock has not elapsed"
+        }
+        IVaultErrors::InvalidShortString(_) => "the string is not a valid short string",
+        IVaultErrors::MarketNotCreated(_) => "the market is not created on Morpho",
+        IVaultErrors::MarketNotEnabled(_) => "the market is not enabled in the vault",
+        IVaultErrors::MathOverflowedMulDiv(_) => "a mulDiv overflowed in the vault",
+        IVaultErrors::MaxFeeExceeded(_) => "the fee is above the maximum (50%)",
+        IVaultErrors::MaxQueueLengthExceeded(_) => "the queue is longer than 30 markets",
+        IVaultErrors::NoPendingValue(_) => "there is no pending value to accept",
+        IVaultErrors::NonZeroCap(_) => "the market still has a non zero cap",
+        IVaultErrors::NotAllocatorRole(_) => "the caller is not an allocator",
+        IVaultErrors::NotCuratorNorGuardianRole(_) => "the caller is neither curator nor guardian",
+        IVaultErrors::NotCuratorRole(_) => "the caller is not the curator (nor the owner)",
+        IVaultErrors::NotEnoughLiquidity(_) => "the vault cannot withdraw enough from its markets",
+        IVaultErrors::NotGuardianRole(_) => "the caller is not the guardian (nor the owner)",
+        IVaultErrors::OwnableInvalidOwner(_) => "the new owner is the zero address",
+        IVaultErrors::OwnableUnauthorizedAccount(_) => "the caller is not the owner",
+        IVaultErrors::PendingCap(_) => "a cap is already pending for the market",
+        IVaultErrors::PendingRemoval(_) => "the market removal is pending, its cap cannot be set",
+        IVaultErrors::SafeCastOverflowedUintDowncast(_) => "a value does not fit its type",
+        IVaultErrors::SafeERC20FailedOperation(_) => "a token transfer failed",
+        IVaultErrors::StringTooLong(_) => "the string is too long",
+        IVaultErrors::SupplyCapExceeded(_) => "the reallocation supplies above the market cap",
+        IVaultErrors::TimelockNotElapsed(_) => "the timelock of the pending value has not elapsed",
+        IVaultErrors::UnauthorizedMarket(_) => "the market is not enabled in the vault",
+        IVaultErrors::ZeroAddress(_) => "the address is zero",
+        IVaultErrors::ZeroFeeRecipient(_) => "a fee cannot be set without a fee recipient",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::IVault;
+    use alloy::primitives::Address;
+
+    #[test]
+    fn decodes_morpho_vault_and_panic_reverts() {
+        let revert = Revert::from("insufficient collateral").abi_encode();
+        assert_eq!(
+            ProtocolError::decode(&revert),
+            ProtocolError::Morpho(MorphoRevert::InsufficientCollateral)
+        );
+
+        let exceeded = IVault::ERC4626ExceededMaxWithdraw {
+            owner: Address::ZERO,
+            assets: U256::from(2),
+            max: U256::from(1),
+        };
+        let error = ProtocolError::decode(&exceeded.abi_encode());
+        assert_eq!(
+            error,
+            ProtocolError::Vault(IVaultErrors::ERC4626ExceededMaxWithdraw(exceeded))
+        );
+        assert_eq!(
+            error.to_string(),
+            "ERC4626ExceededMaxWithdraw: the withdrawal exceeds maxWithdraw (balance or vault liquidity) \
+             (2 assets of 0x0000000000000000000000000000000000000000, max 1)"
+        );
+
+        let panic = Panic::from(0x11).abi_encode();
+        assert_eq!(
+            ProtocolError::decode(&panic),
+            ProtocolError::Panic(U256::from(0x11))
+        );
+        assert_eq!(
+            ProtocolError::decode(&MathOverflowedMulDiv::SELECTOR),
+            ProtocolError::MathOverflowedMulDiv
+        );
+    }
+}
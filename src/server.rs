@@ -0,0 +1,415 @@
+use crate::api::{get_usd_prices, Asset};
+use crate::error::{Error, Result};
+use crate::events::{morpho_event_market_id, morpho_event_supplier, watch_morpho_events};
+use crate::governance::GovernanceHistory;
+use crate::market::morpho_deployment_block;
+use crate::metrics::{Metrics, MetricsTargets};
+use crate::oracle::{inspect_oracle, OracleReport, DEFAULT_MAX_FEED_AGE};
+use crate::units::retrieve_market_tokens;
+use crate::views::{
+    fetch_market_view, fetch_position_view, fetch_vault_view, MarketView, PositionView, VaultView,
+};
+use alloy::{
+    primitives::{Address, B256},
+    transports::http::reqwest::Url,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, Mutex},
+};
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    // Entries older than this are fetched again on their next request. Markets and positions are
+    // also refreshed by the Morpho event stream, vault governance and API prices are not.
+    pub max_age: Duration,
+    pub metrics: MetricsTargets,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            max_age: Duration::from_secs(60),
+            metrics: MetricsTargets::default(),
+        }
+    }
+}
+
+struct Cached<T> {
+    value: T,
+    fetched_at: Instant,
+}
+
+impl<T: Clone> Cached<T> {
+    fn new(value: T) -> Self {
+        Cached {
+            value,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    fn fresh(&self, max_age: Duration) -> Option<T> {
+        (self.fetched_at.elapsed() <= max_age).then(|| self.value.clone())
+    }
+}
+
+#[derive(Default)]
+struct Cache {
+    markets: HashMap<B256, Cached<MarketView>>,
+    positions: HashMap<(B256, Address), Cached<PositionView>>,
+    vaults: HashMap<Address, Cached<VaultView>>,
+    // Kept across vault refreshes, only the blocks since the last fetch are scanned
+    governance: HashMap<Address, GovernanceHistory>,
+    prices: Option<Cached<Vec<Asset>>>,
+    // None for the oracles which are not a MorphoChainlinkOracleV2
+    oracles: HashMap<B256, Cached<Option<OracleReport>>>,
+}
+
+fn fresh<K: Eq + Hash, V: Clone>(
+    entries: &HashMap<K, Cached<V>>,
+    key: &K,
+    max_age: Duration,
+) -> Option<V> {
+    entries.get(key).and_then(|entry| entry.fresh(max_age))
+}
+
+struct Server {
+    rpc_url: Url,
+    chain_id: u64,
+    options: ServeOptions,
+    cache: Mutex<Cache>,
+    metrics: Metrics,
+}
+
+impl Server {
+    async fn market(&self, id: B256) -> Result<MarketView> {
+        let cached = fresh(&self.cache.lock().await.markets, &id, self.options.max_age);
+        if let Some(market) = cached {
+            return Ok(market);
+        }
+        let market = fetch_market_view(self.rpc_url.clone(), id).await?;
+        self.cache
+            .lock()
+            .await
+            .markets
+            .insert(id, Cached::new(market.clone()));
+        Ok(market)
+    }
+
+    async fn position(&self, id: B256, user: Address) -> Result<PositionView> {
+        let cached = fresh(
+            &self.cache.lock().await.positions,
+            &(id, user),
+            self.options.max_age,
+        );
+        if let Some(position) = cached {
+            return Ok(position);
+        }
+        let market = self.market(id).await?;
+        let position = fetch_position_view(self.rpc_url.clone(), &market, user).await?;
+        self.cache
+            .lock()
+            .await
+            .positions
+            .insert((id, user), Cached::new(position.clone()));
+        Ok(position)
+    }
+
+    async fn vault(&self, address: Address) -> Result<VaultView> {
+        let cached = fresh(
+            &self.cache.lock().await.vaults,
+            &address,
+            self.options.max_age,
+        );
+        if let Some(vault) = cached {
+            return Ok(vault);
+        }
+        let history = self.cache.lock().await.governance.get(&address).cloned();
+        let mut history = match history {
+            Some(history) => history,
+            None => GovernanceHistory::new(morpho_deployment_block(self.chain_id)?),
+        };
+        let vault = fetch_vault_view(self.rpc_url.clone(), address, &mut history).await?;
+        let mut cache = self.cache.lock().await;
+        cache.governance.insert(address, history);
+        cache.vaults.insert(address, Cached::new(vault.clone()));
+        Ok(vault)
+    }
+
+    async fn prices(&self) -> Result<Vec<Asset>> {
+        let cached = self
+            .cache
+            .lock()
+            .await
+            .prices
+            .as_ref()
+            .and_then(|prices| prices.fresh(self.options.max_age));
+        if let Some(prices) = cached {
+            return Ok(prices);
+        }
+        let prices: Vec<Asset> = get_usd_prices()
+            .await?
+            .into_iter()
+            .filter(|asset| asset.chain == self.chain_id)
+            .collect();
+        self.cache.lock().await.prices = Some(Cached::new(prices.clone()));
+        Ok(prices)
+    }
+
+    async fn oracle(&self, market: &MarketView) -> Result<Option<OracleReport>> {
+        let id = market.info.id;
+        let cached = fresh(&self.cache.lock().await.oracles, &id, self.options.max_age);
+        if let Some(report) = cached {
+            return Ok(report);
+        }
+        let (loan_token, collateral_token) =
+            retrieve_market_tokens(self.rpc_url.clone(), id).await?;
+        let report = match inspect_oracle(
+            self.rpc_url.clone(),
+            market.info.params.oracle,
+            collateral_token.decimals,
+            loan_token.decimals,
+            DEFAULT_MAX_FEED_AGE,
+        )
+        .await
+        {
+            Ok(report) => Some(report),
+            // Other oracles do not have the feed getters
+            Err(Error::Revert(_) | Error::AbiDecode(_)) => None,
+            Err(error) => return Err(error),
+        };
+        self.cache
+            .lock()
+            .await
+            .oracles
+            .insert(id, Cached::new(report.clone()));
+        Ok(report)
+    }
+
+    // Reads the metrics targets through the cache, a failed read is counted and its gauges removed
+    async fn collect_metrics(&self) {
+        let targets = &self.options.metrics;
+        let mut markets: BTreeSet<B256> = targets.markets.iter().copied().collect();
+        markets.extend(targets.positions.iter().map(|(id, _)| *id));
+
+        for id in markets {
+            match self.market(id).await {
+                Ok(market) => {
+                    self.metrics.update_market(&market);
+                    match self.oracle(&market).await {
+                        Ok(Some(report)) => self.metrics.update_oracle(id, &report),
+                        Ok(None) => self.metrics.remove_oracle(id),
+                        Err(error) => {
+                            self.metrics.remove_oracle(id);
+                            self.metrics
+                                .record_error(&format!("the oracle of market {}", id), &error);
+                        }
+                    }
+                }
+                Err(error) => {
+                    self.metrics.remove_market(id);
+                    self.metrics.record_error(&format!("market {}", id), &error);
+                }
+            }
+        }
+        for address in &targets.vaults {
+            match self.vault(*address).await {
+                Ok(vault) => self.metrics.update_vault(&vault),
+                Err(error) => {
+                    self.metrics.remove_vault(*address);
+                    self.metrics
+                        .record_error(&format!("vault {}", address), &error);
+                }
+            }
+        }
+        for (id, user) in &targets.positions {
+            match self.position(*id, *user).await {
+                Ok(position) => self.metrics.update_position(&position),
+                Err(error) => {
+                    self.metrics.remove_position(*id, *user);
+                    self.metrics
+                        .record_error(&format!("position of {} on {}", user, id), &error);
+                }
+            }
+        }
+    }
+
+    // Drops the vaults whose supply changed, they take many calls and are fetched on their next request
+    async fn evict_vaults(&self, suppliers: &BTreeSet<Address>) {
+        self.cache
+            .lock()
+            .await
+            .vaults
+            .retain(|address, _| !suppliers.contains(address));
+    }
+
+    // Fetches again a market and the cached positions on it after one of its events. The cached vaults
+    // supplying to it get the new market state, their own supply is only refreshed by `evict_vaults`
+    // or after `max_age`.
+    async fn refresh_market(&self, id: B256) -> Result<()> {
+        let (cached, users) = {
+            let cache = self.cache.lock().await;
+            let users: Vec<Address> = cache
+                .positions
+                .keys()
+                .filter(|(market_id, _)| *market_id == id)
+                .map(|(_, user)| *user)
+                .collect();
+            let allocated = cache
+                .vaults
+                .values()
+                .any(|vault| vault.value.allocation.iter().any(|market| market.id == id));
+            (cache.markets.contains_key(&id) || allocated, users)
+        };
+        if !cached && users.is_empty() {
+            return Ok(());
+        }
+
+        let market = fetch_market_view(self.rpc_url.clone(), id).await?;
+        {
+            let mut cache = self.cache.lock().await;
+            cache.markets.insert(id, Cached::new(market.clone()));
+            for vault in cache.vaults.values_mut() {
+                vault.value.update_market(&market);
+            }
+        }
+        for user in users {
+            let position = fetch_position_view(self.rpc_url.clone(), &market, user).await?;
+            self.cache
+                .lock()
+                .await
+                .positions
+                .insert((id, user), Cached::new(position));
+        }
+        Ok(())
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Config(_) => StatusCode::BAD_REQUEST,
+            // Every other failure comes from the RPC, the chain or the API behind the server
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+async fn get_market(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<B256>,
+) -> Result<Json<MarketView>> {
+    Ok(Json(server.market(id).await?))
+}
+
+async fn get_position(
+    State(server): State<Arc<Server>>,
+    Path((id, user)): Path<(B256, Address)>,
+) -> Result<Json<PositionView>> {
+    Ok(Json(server.position(id, user).await?))
+}
+
+async fn get_vault(
+    State(server): State<Arc<Server>>,
+    Path(address): Path<Address>,
+) -> Result<Json<VaultView>> {
+    Ok(Json(server.vault(address).await?))
+}
+
+async fn get_prices(State(server): State<Arc<Server>>) -> Result<Json<Vec<Asset>>> {
+    Ok(Json(server.prices().await?))
+}
+
+async fn get_metrics(State(server): State<Arc<Server>>) -> Result<impl IntoResponse> {
+    server.collect_metrics().await;
+    Ok((
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        server.metrics.encode()?,
+    ))
+}
+
+// Serves markets, positions, vaults and prices as JSON, and the gauges of `options.metrics` on
+// /metrics, until the listener fails. Responses come from a cache kept fresh by the Morpho event
+// stream of `ws_url`.
+pub async fn serve(
+    rpc_url: Url,
+    ws_url: String,
+    chain_id: u64,
+    address: SocketAddr,
+    options: ServeOptions,
+) -> Result<()> {
+    let max_age = options.max_age;
+    let server = Arc::new(Server {
+        rpc_url,
+        chain_id,
+        options,
+        cache: Mutex::new(Cache::default()),
+        metrics: Metrics::new()?,
+    });
+
+    // The stream only sends the ids of the updated markets and their suppliers, they are fetched by
+    // the refresher
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let watched = watch_morpho_events(&ws_url, |log| {
+            if let Some(id) = morpho_event_market_id(&log.event) {
+                let _ = sender.send((id, morpho_event_supplier(&log.event)));
+            }
+        })
+        .await;
+        if let Err(error) = watched {
+            eprintln!(
+                "Event stream stopped, entries are now only refreshed after {:?}: {}",
+                max_age, error
+            );
+        }
+    });
+    let refresher = server.clone();
+    tokio::spawn(async move {
+        while let Some(update) = receiver.recv().await {
+            // Events of the same block are refreshed once
+            let mut ids = BTreeSet::new();
+            let mut suppliers = BTreeSet::new();
+            let mut next = Some(update);
+            while let Some((id, supplier)) = next {
+                ids.insert(id);
+                suppliers.extend(supplier);
+                next = receiver.try_recv().ok();
+            }
+            refresher.evict_vaults(&suppliers).await;
+            for id in ids {
+                if let Err(error) = refresher.refresh_market(id).await {
+                    eprintln!("Failed to refresh market {}: {}", id, error);
+                }
+            }
+        }
+    });
+
+    let app = Router::new()
+        .route("/markets/:id", get(get_market))
+        .route("/markets/:id/positions/:user", get(get_position))
+        .route("/vaults/:address", get(get_vault))
+        .route("/prices", get(get_prices))
+        .route("/metrics", get(get_metrics))
+        .with_state(server);
+    let listener = TcpListener::bind(address).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// Snippets with example arguments, run by `cargo run -- snippets`. They print to stdout and only live in
+// the binary, the library returns typed values.
+mod markets;
+mod transactions;
+mod vaults;
+
+use alloy::transports::http::reqwest::Url;
+use eyre::Result;
+use morpho_rust_snippets::api::get_usd_prices;
+
+pub async fn run(rpc_url: Url, wss_url: &str) -> Result<()> {
+    markets::retrieve_market_info(rpc_url.clone()).await?;
+    markets::inspect_market_oracle(rpc_url.clone()).await?;
+    markets::retrieve_markets(rpc_url.clone()).await?;
+    markets::find_markets_by_spec(rpc_url.clone()).await?;
+    markets::scan_markets(rpc_url.clone()).await?;
+    markets::snapshot_with_ext_sloads(rpc_url.clone()).await?;
+    transactions::build_and_preflight_transactions(rpc_url.clone()).await?;
+    transactions::authorize_with_signature(rpc_url.clone()).await?;
+    markets::retrieve_events_with_logs(rpc_url.clone()).await?;
+    markets::reconstruct_liquidations(rpc_url.clone()).await?;
+    vaults::retrieve_vault_details(rpc_url.clone()).await?;
+    vaults::retrieve_vaults(rpc_url.clone()).await?;
+    vaults::index_vaults(rpc_url.clone()).await?;
+    vaults::retrieve_vault_activity_details(rpc_url.clone()).await?;
+    transactions::prepare_vault_transactions(rpc_url.clone()).await?;
+    vaults::optimize_vault_allocation(rpc_url.clone()).await?;
+    vaults::track_vaults_governance(rpc_url.clone()).await?;
+    vaults::retrieve_vault_performance(rpc_url.clone()).await?;
+    vaults::retrieve_vault_statements(rpc_url.clone()).await?;
+
+    // The API rate limits its clients, transient errors are worth a second try
+    let prices = match get_usd_prices().await {
+        Err(error) if error.is_transient() => get_usd_prices().await?,
+        prices => prices?,
+    };
+    println!("{:?}", prices);
+    markets::monitor_oracle_deviations(rpc_url.clone()).await?;
+
+    markets::subscribe_to_event_logs(wss_url).await?;
+
+    Ok(())
+}
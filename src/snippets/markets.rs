@@ -0,0 +1,510 @@
+// Examples of the market reads, printing to stdout
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    primitives::{address, Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::Filter,
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use eyre::{eyre, Result};
+use morpho_rust_snippets::{
+    api::get_usd_prices,
+    deviation::{check_oracle_deviations, MarketWatch, DEFAULT_DEVIATION_THRESHOLD},
+    events::{describe_morpho_event, fetch_morpho_events, watch_morpho_events, MorphoLog},
+    history::{read_position_at, sample_oracle_prices, sample_position_history},
+    market::{
+        fetch_market_info, fetch_markets, IMorpho, IOracle, MarketRecord, IERC20, MORPHO_ADDRESS,
+    },
+    market_id::{parse_market_spec, resolve_market_spec, validate_market_id},
+    multicall::{retrieve_market_states, retrieve_positions, BatchLimits},
+    oracle::{inspect_oracle, DEFAULT_MAX_FEED_AGE},
+    storage::*,
+    units::{
+        collateral_price_in_loan, format_amount, loan_price_in_collateral, retrieve_token_info,
+    },
+    vault::IVault,
+};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub async fn retrieve_market_info(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+
+    // Getting market information
+    // You can change this market id
+    let market_id: B256 =
+        "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?; // USD0++/USDC (86%) with AdaptiveCurve
+    let info = fetch_market_info(rpc_url.clone(), market_id, BlockId::latest()).await?;
+    let market_params = &info.params;
+    let market_data = &info.market;
+
+    println!(
+        "Market with id {:#32x} was updated for the last time at timestamp {}",
+        market_id, market_data.lastUpdate
+    );
+    println!(
+        "Market Params:\n- Collateral asset: {:#20x} \n- Loan asset: {:#20x} \n- LLTV: {} \n- Oracle: {:#20x} \n- IRM: {:#20x}",
+        market_params.collateralToken,
+        market_params.loanToken,
+        market_params.lltv,
+        market_params.oracle,
+        market_params.irm
+    );
+
+    // Note that some interest might be lacking
+    println!(
+        "Market Data:\n- Fee: {} \n- Total borrow assets: {} \n- Total borrow shares: {} \n- Total supply assets: {} \n- Total supply shares: {}",
+        market_data.fee,
+        market_data.totalBorrowAssets,
+        market_data.totalBorrowShares,
+        market_data.totalSupplyAssets,
+        market_data.totalSupplyShares
+    );
+
+    // Getting the price of the oracle
+    let price = info.price;
+    println!("Current price of market oracle is {}", price);
+
+    // The raw price depends on the tokens decimals, so convert it to a human readable one
+    let collateral = retrieve_token_info(rpc_url.clone(), market_params.collateralToken).await?;
+    let loan = retrieve_token_info(rpc_url, market_params.loanToken).await?;
+    println!(
+        "1 {} = {} {} and 1 {} = {} {}",
+        collateral.symbol,
+        collateral_price_in_loan(price, collateral.decimals, loan.decimals)?,
+        loan.symbol,
+        loan.symbol,
+        loan_price_in_collateral(price, collateral.decimals, loan.decimals)?,
+        collateral.symbol
+    );
+    println!(
+        "Loan token {:#20x}: total supply assets are {} and total borrow assets {}",
+        loan.address,
+        format_amount(U256::from(market_data.totalSupplyAssets), &loan)?,
+        format_amount(U256::from(market_data.totalBorrowAssets), &loan)?
+    );
+
+    // Getting current rate from the IRM
+    println!(
+        "Current average rate since last update for this market is {}",
+        info.borrow_rate
+    );
+
+    // Compute updated market total with interest
+    let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let interest = info.accrued_interest(current_time);
+
+    println!(
+        "Market has {} pending interest since last update at {}, now it's {} so total supply asset will be {} and total borrow asset will be {}",
+         interest,
+         market_data.lastUpdate,
+        current_time,
+         U256::from(market_data.totalSupplyAssets)+interest,
+         U256::from(market_data.totalBorrowAssets)+interest
+    );
+
+    // Getting a user position on this market
+    // You can change this user
+    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+    let position = morpho.position(market_id, user).call().await?;
+
+    println!(
+        "User {:#20x} position on this market:\n- Collateral: {}\n- Borrow shares: {}\n- Supply shares: {}",
+        user, position.collateral, position.borrowShares, position.supplyShares
+    );
+    Ok(())
+}
+
+fn print_morpho_logs(logs: &[MorphoLog]) {
+    for log in logs {
+        // SetOwner, SetFee, SetFeeRecipient, EnableIrm, EnableLltv, FlashLoan, SetAuthorization, IncrementNonce
+        // and AccrueInterest events are not printed
+        if let Some(description) = describe_morpho_event(&log.event) {
+            println!("{}", description);
+        }
+    }
+}
+
+pub async fn retrieve_events_with_logs(rpc_url: Url) -> Result<()> {
+    // You can change the block range (the script fails if there is too much logs on a range)
+    let logs = fetch_morpho_events(rpc_url, 21_250_000, 21_260_000).await?;
+
+    println!("Got {} logs", logs.len());
+    print_morpho_logs(&logs);
+    Ok(())
+}
+
+pub async fn retrieve_markets(rpc_url: Url) -> Result<()> {
+    for MarketRecord { id, params } in fetch_markets(rpc_url, BlockNumberOrTag::Latest).await? {
+        println!(
+            "Market with id {:#32x} was created with params: {:#20x}, {:#20x}, {}, {:#20x}, {:#20x}",
+            id, params.collateralToken, params.loanToken, params.lltv, params.oracle, params.irm
+        );
+    }
+    Ok(())
+}
+
+pub async fn subscribe_to_event_logs(rpc_url: &str) -> Result<()> {
+    watch_morpho_events(rpc_url, |log| print_morpho_logs(&[log])).await?;
+    Ok(())
+}
+
+pub async fn find_markets_by_spec(rpc_url: Url) -> Result<()> {
+    // You can change this spec
+    let spec = parse_market_spec("wstETH/USDC 86% lltv")?;
+
+    let mut markets = Vec::new();
+    for MarketRecord { id, params } in
+        fetch_markets(rpc_url.clone(), BlockNumberOrTag::Latest).await?
+    {
+        // Ids are computed locally, no need to trust the event
+        if params.id() != id {
+            return Err(eyre!("market {:#32x} does not match its params", id));
+        }
+        markets.push(params);
+    }
+
+    // Symbols come from the API to avoid one call per token
+    let symbols: HashMap<Address, String> = get_usd_prices()
+        .await?
+        .into_iter()
+        .filter(|asset| asset.chain == 1)
+        .map(|asset| (asset.token, asset.symbol))
+        .collect();
+
+    for market_params in resolve_market_spec(&spec, &markets, &symbols) {
+        let id = market_params.id();
+        println!(
+            "Market {:#32x} matches {}/{} (oracle {:#20x}, irm {:#20x}), valid: {}",
+            id,
+            spec.collateral_symbol,
+            spec.loan_symbol,
+            market_params.oracle,
+            market_params.irm,
+            validate_market_id(rpc_url.clone(), id, &market_params).await?
+        );
+    }
+    Ok(())
+}
+
+pub async fn scan_markets(rpc_url: Url) -> Result<()> {
+    let ids: Vec<B256> = fetch_markets(rpc_url.clone(), BlockNumberOrTag::Latest)
+        .await?
+        .into_iter()
+        .map(|market| market.id)
+        .collect();
+
+    let states = retrieve_market_states(rpc_url.clone(), &ids, BatchLimits::default()).await?;
+    println!("Got the state of {} markets", states.len());
+    for state in states {
+        println!(
+            "Market {:#32x}: {} supplied, {} borrowed, oracle price {:?}, borrow rate {:?}",
+            state.id,
+            state.market.totalSupplyAssets,
+            state.market.totalBorrowAssets,
+            state.price,
+            state.borrow_rate
+        );
+    }
+
+    // You can change the user
+    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+    let positions: Vec<(B256, Address)> = ids.iter().map(|id| (*id, user)).collect();
+    let results = retrieve_positions(rpc_url, &positions, BatchLimits::default()).await?;
+    for ((id, _), position) in positions.iter().zip(results) {
+        if let Some(position) = position {
+            if position.supplyShares > U256::ZERO
+                || position.borrowShares > 0
+                || position.collateral > 0
+            {
+                println!(
+                    "User {:#20x} on market {:#32x}: {} supply shares, {} borrow shares, {} collateral",
+                    user, id, position.supplyShares, position.borrowShares, position.collateral
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn inspect_market_oracle(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    // You can change this market id
+    let market_id: B256 =
+        "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?; // USD0++/USDC (86%) with AdaptiveCurve
+    let market_params = morpho.idToMarketParams(market_id).call().await?;
+
+    // With an ERC4626 vault, the feeds price the vault asset, so its decimals are the ones to use
+    let base_vault = IOracle::new(market_params.oracle, provider.clone())
+        .BASE_VAULT()
+        .call()
+        .await?
+        ._0;
+    let quote_vault = IOracle::new(market_params.oracle, provider.clone())
+        .QUOTE_VAULT()
+        .call()
+        .await?
+        ._0;
+    let base_token = if base_vault == Address::ZERO {
+        market_params.collateralToken
+    } else {
+        IVault::new(base_vault, provider.clone())
+            .asset()
+            .call()
+            .await?
+            ._0
+    };
+    let quote_token = if quote_vault == Address::ZERO {
+        market_params.loanToken
+    } else {
+        IVault::new(quote_vault, provider.clone())
+            .asset()
+            .call()
+            .await?
+            ._0
+    };
+    let base_token_decimals = IERC20::new(base_token, provider.clone())
+        .decimals()
+        .call()
+        .await?
+        ._0;
+    let quote_token_decimals = IERC20::new(quote_token, provider.clone())
+        .decimals()
+        .call()
+        .await?
+        ._0;
+
+    let report = inspect_oracle(
+        rpc_url,
+        market_params.oracle,
+        base_token_decimals,
+        quote_token_decimals,
+        DEFAULT_MAX_FEED_AGE,
+    )
+    .await?;
+
+    println!("Oracle {:#20x} components:", report.oracle);
+    for feed in report.feeds() {
+        println!(
+            "- Feed {:#20x} ({}): answer {} with {} decimals, updated at {}{}",
+            feed.address,
+            feed.description,
+            feed.answer,
+            feed.decimals,
+            feed.updated_at,
+            if feed.stale { " (STALE)" } else { "" }
+        );
+    }
+    let stale_feeds = report.stale_feeds();
+    if !stale_feeds.is_empty() {
+        println!(
+            "{} feeds were not updated for more than {} seconds",
+            stale_feeds.len(),
+            DEFAULT_MAX_FEED_AGE
+        );
+    }
+    for vault in [&report.base_vault, &report.quote_vault]
+        .into_iter()
+        .flatten()
+    {
+        println!(
+            "- Vault {:#20x}: {} shares convert to {} assets",
+            vault.address, vault.sample, vault.assets
+        );
+    }
+    println!(
+        "Price is {} on chain and {} recomputed from components ({})",
+        report.price,
+        report.recomputed_price,
+        if report.price_matches() {
+            "match"
+        } else {
+            "MISMATCH"
+        }
+    );
+    println!(
+        "Scale factor is {}, expected {:?} ({})",
+        report.scale_factor,
+        report.expected_scale_factor,
+        if report.scale_factor_matches() {
+            "ok"
+        } else {
+            "MISCONFIGURED"
+        }
+    );
+    Ok(())
+}
+
+pub async fn snapshot_with_ext_sloads(rpc_url: Url) -> Result<()> {
+    // You can change this market id and user
+    let market_id: B256 =
+        "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?; // USD0++/USDC (86%) with AdaptiveCurve
+    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+
+    let market = snapshot_markets(rpc_url.clone(), &[market_id]).await?[0];
+    let position = snapshot_positions(rpc_url.clone(), &[(market_id, user)]).await?[0];
+    let params = ext_sloads(
+        rpc_url.clone(),
+        &[
+            id_to_market_params_slot(market_id, LOAN_TOKEN_OFFSET),
+            id_to_market_params_slot(market_id, COLLATERAL_TOKEN_OFFSET),
+            id_to_market_params_slot(market_id, ORACLE_OFFSET),
+            id_to_market_params_slot(market_id, IRM_OFFSET),
+            id_to_market_params_slot(market_id, LLTV_OFFSET),
+            nonce_slot(user),
+            owner_slot(),
+            fee_recipient_slot(),
+        ],
+    )
+    .await?;
+    // Morpho bundler on Ethereum
+    let bundler = address!("4095F064B8d3c3548A3bebfd0Bbfd04750E30077");
+    let flags = ext_sloads(
+        rpc_url,
+        &[
+            is_irm_enabled_slot(slot_to_address(params[3])),
+            is_lltv_enabled_slot(U256::from_be_bytes(params[4].0)),
+            is_authorized_slot(user, bundler),
+        ],
+    )
+    .await?;
+    let is_set = |value: B256| value != B256::ZERO;
+
+    println!(
+        "Morpho owner is {:#20x} and fee recipient {:#20x}",
+        slot_to_address(params[6]),
+        slot_to_address(params[7])
+    );
+
+    println!(
+        "Market {:#32x} read from storage:\n- Loan asset: {:#20x}\n- Collateral asset: {:#20x}\n- Oracle: {:#20x}\n- IRM: {:#20x} (enabled: {})\n- LLTV: {} (enabled: {})\n- Total supply assets: {}\n- Total supply shares: {}\n- Total borrow assets: {}\n- Total borrow shares: {}\n- Last update: {}\n- Fee: {}",
+        market_id,
+        slot_to_address(params[0]),
+        slot_to_address(params[1]),
+        slot_to_address(params[2]),
+        slot_to_address(params[3]),
+        is_set(flags[0]),
+        U256::from_be_bytes(params[4].0),
+        is_set(flags[1]),
+        market.total_supply_assets,
+        market.total_supply_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        market.last_update,
+        market.fee
+    );
+    println!(
+        "User {:#20x} position read from storage:\n- Collateral: {}\n- Borrow shares: {}\n- Supply shares: {}\n- Nonce: {}\n- Bundler authorized: {}",
+        user,
+        position.collateral,
+        position.borrow_shares,
+        position.supply_shares,
+        U256::from_be_bytes(params[5].0),
+        is_set(flags[2])
+    );
+    Ok(())
+}
+
+pub async fn reconstruct_liquidations(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+
+    // You can change the block range
+    let filter = Filter::new()
+        .address(MORPHO_ADDRESS)
+        .from_block(BlockNumberOrTag::Number(21_250_000))
+        .to_block(BlockNumberOrTag::Number(21_260_000))
+        .event_signature(IMorpho::Liquidate::SIGNATURE_HASH);
+
+    let logs = provider.get_logs(&filter).await?;
+
+    println!("Got {} liquidations", logs.len());
+    let mut last_liquidation = None;
+    for log in logs {
+        let IMorpho::Liquidate {
+            id,
+            caller: _caller,
+            borrower,
+            repaidAssets: _repaid_assets,
+            repaidShares: _repaid_shares,
+            seizedAssets,
+            badDebtAssets: _bad_debt_assets,
+            badDebtShares: _bad_debt_shares,
+        } = log.log_decode()?.inner.data;
+        let Some(block_number) = log.block_number else {
+            continue;
+        };
+
+        // State at the end of the previous block, i.e. what the liquidator saw
+        let (market, position) =
+            read_position_at(rpc_url.clone(), id, borrower, block_number - 1).await?;
+        println!(
+            "User {:#20x} was liquidated on market {:#32x} at block {} ({} collateral seized): oracle price was {}, collateral {}, borrow shares {} and health factor {:?}",
+            borrower,
+            id,
+            block_number,
+            seizedAssets,
+            market.oracle_price,
+            position.collateral,
+            position.borrow_shares,
+            position.health_factor
+        );
+        last_liquidation = Some((id, borrower, block_number));
+    }
+
+    // Oracle price and position over the blocks before the last liquidation
+    if let Some((id, borrower, block_number)) = last_liquidation {
+        let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+        let oracle = morpho.idToMarketParams(id).call().await?.oracle;
+        let from_block = block_number.saturating_sub(50);
+        for (block, price) in
+            sample_oracle_prices(rpc_url.clone(), oracle, from_block, block_number, 10).await?
+        {
+            println!("Block {}: oracle price was {}", block, price);
+        }
+        for (market, position) in
+            sample_position_history(rpc_url, id, borrower, from_block, block_number - 1, 10).await?
+        {
+            println!(
+                "Block {}: user {:#20x} had {} supply shares, {} borrow shares and {} collateral, the market had {} supply assets for {} shares (last update at {}, fee {}) at block {}",
+                position.block_number,
+                position.user,
+                position.supply_shares,
+                position.borrow_shares,
+                position.collateral,
+                market.total_supply_assets,
+                market.total_supply_shares,
+                market.last_update,
+                market.fee,
+                market.block_number
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn monitor_oracle_deviations(rpc_url: Url) -> Result<()> {
+    // You can change the watched markets and their thresholds
+    let markets = [MarketWatch {
+        id: "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?, // USD0++/USDC (86%) with AdaptiveCurve
+        threshold: DEFAULT_DEVIATION_THRESHOLD,
+    }];
+
+    let assets = get_usd_prices().await?;
+    let reports = check_oracle_deviations(rpc_url, 1, &markets, &assets).await?;
+
+    for report in reports {
+        println!(
+            "Market {:#32x}: oracle price {} vs USD implied price {} ({:.2}% deviation){}",
+            report.id,
+            report.oracle_price,
+            report.implied_price,
+            report.deviation * 100.0,
+            if report.is_alert() { " ALERT" } else { "" }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+// Examples of the transaction builders, simulated with eth_call
+use alloy::{
+    primitives::{address, Bytes, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    signers::local::PrivateKeySigner,
+    transports::http::reqwest::Url,
+};
+use eyre::Result;
+use morpho_rust_snippets::{
+    authorization::{
+        authorization_digest, build_authorization, build_authorization_transaction,
+        sign_authorization, verify_authorization,
+    },
+    market::{IMorpho, MORPHO_ADDRESS},
+    transactions::{build_transaction, preflight, Amount, MorphoAction},
+    vault_transactions::{build_deposit, build_permit_transaction, build_withdraw, sign_permit},
+    Error,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub async fn build_and_preflight_transactions(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+
+    // You can change this market id and user (run against a fork, e.g. `anvil --fork-url <rpc>`, to send them)
+    let market_id: B256 =
+        "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2".parse()?; // USD0++/USDC (86%) with AdaptiveCurve
+    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+
+    let params = morpho.idToMarketParams(market_id).call().await?;
+    let market_params = IMorpho::MarketParams {
+        loanToken: params.loanToken,
+        collateralToken: params.collateralToken,
+        oracle: params.oracle,
+        irm: params.irm,
+        lltv: params.lltv,
+    };
+
+    let actions = [
+        MorphoAction::Supply {
+            amount: Amount::Assets(U256::from(1_000_000)),
+            on_behalf: user,
+        },
+        MorphoAction::Withdraw {
+            amount: Amount::Shares(U256::from(1_000_000)),
+            on_behalf: user,
+            receiver: user,
+        },
+        MorphoAction::SupplyCollateral {
+            assets: U256::from(1_000_000),
+            on_behalf: user,
+        },
+        MorphoAction::WithdrawCollateral {
+            assets: U256::from(1_000_000),
+            on_behalf: user,
+            receiver: user,
+        },
+        MorphoAction::Repay {
+            amount: Amount::Shares(U256::from(1_000_000)),
+            on_behalf: user,
+        },
+        MorphoAction::Borrow {
+            amount: Amount::Assets(U256::from(1_000_000)),
+            on_behalf: user,
+            receiver: user,
+        },
+        MorphoAction::Liquidate {
+            borrower: user,
+            amount: Amount::Shares(U256::from(1_000_000)),
+        },
+        MorphoAction::FlashLoan {
+            token: market_params.loanToken,
+            assets: U256::from(1_000_000),
+            data: Bytes::new(),
+        },
+    ];
+
+    for action in actions.iter() {
+        let tx = build_transaction(user, &market_params, action);
+        // 0.5% maximum slippage between the expected and simulated amounts
+        match preflight(rpc_url.clone(), &tx, action, market_id, 50).await {
+            Ok(result) => println!("{:?} would succeed with {:?}", action, result.amounts),
+            Err(error) => println!("{:?} would revert with {}", action, error),
+        }
+    }
+    Ok(())
+}
+
+pub async fn authorize_with_signature(rpc_url: Url) -> Result<()> {
+    // You can use your own key, a random one is enough to sign and verify offline
+    let signer = PrivateKeySigner::random();
+    // You can change the authorized address, here the Morpho EthereumBundlerV2
+    let bundler = address!("4095F064B8d3c3548A3bebfd0Bbfd04750E30077");
+    let deadline = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + 3600;
+
+    let (authorization, domain) = build_authorization(
+        rpc_url.clone(),
+        signer.address(),
+        bundler,
+        true,
+        U256::from(deadline),
+    )
+    .await?;
+    let signature = sign_authorization(&signer, &authorization, &domain)?;
+
+    println!(
+        "Authorization of {:#20x} by {:#20x} (nonce {}, deadline {}):\n- Domain separator: {:#32x}\n- Digest: {:#32x}\n- Signature: v {}, r {:#32x}, s {:#32x}\n- Valid: {}",
+        authorization.authorized,
+        authorization.authorizer,
+        authorization.nonce,
+        authorization.deadline,
+        domain.separator(),
+        authorization_digest(&authorization, &domain),
+        signature.v,
+        signature.r,
+        signature.s,
+        verify_authorization(&authorization, &signature, &domain)
+    );
+
+    // Anyone can submit the signature, simulate it from the bundler
+    let tx = build_authorization_transaction(bundler, authorization, signature);
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    provider.call(&tx).await?;
+    println!("setAuthorizationWithSig would succeed");
+    Ok(())
+}
+
+pub async fn prepare_vault_transactions(rpc_url: Url) -> Result<()> {
+    // You can change this vault, user and amount
+    let vault = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB"); // Steakhouse USDC
+    let user = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+    let assets = U256::from(1_000_000_000); // 1,000 USDC
+
+    // 0.1% maximum slippage
+    for (action, result) in [
+        (
+            "Deposit",
+            build_deposit(rpc_url.clone(), vault, user, assets, user, 10).await,
+        ),
+        (
+            "Withdrawal",
+            build_withdraw(rpc_url.clone(), vault, user, assets, user, 10).await,
+        ),
+    ] {
+        match result {
+            Ok(vault_tx) => println!(
+                "{} of {} assets would succeed: {} shares previewed, preflight bound {}, calldata {:?}",
+                action,
+                assets,
+                vault_tx.expected_shares,
+                vault_tx.preflight_shares_bound,
+                vault_tx.tx.input.input()
+            ),
+            Err(Error::Revert(error)) => println!("{} rejected by the vault: {}", action, error),
+            Err(error) => println!("{} failed: {}", action, error),
+        }
+    }
+
+    // Permit of vault shares, signed with a random key
+    let signer = PrivateKeySigner::random();
+    let deadline = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + 3600;
+    let signature =
+        sign_permit(rpc_url, vault, &signer, user, assets, U256::from(deadline)).await?;
+    let tx = build_permit_transaction(user, vault, signer.address(), user, assets, signature);
+    println!(
+        "Permit of {} shares of {:#20x} signed by {:#20x}: {:?}",
+        assets,
+        vault,
+        signer.address(),
+        tx.input.input()
+    );
+    Ok(())
+}
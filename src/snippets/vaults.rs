@@ -0,0 +1,306 @@
+// Examples of the vault reads, printing to stdout
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    primitives::address,
+    providers::{Provider, ProviderBuilder},
+    rpc::types::Filter,
+    sol_types::SolEvent,
+    transports::http::reqwest::Url,
+};
+use eyre::Result;
+use morpho_rust_snippets::{
+    allocation::{optimize_allocation, retrieve_vault_allocations, DEFAULT_STEPS},
+    governance::{retrieve_governance_history, retrieve_pending_actions},
+    ledger::{build_vault_ledger, convert_to_assets},
+    performance::{
+        compute_performance, reconstruct_share_prices, sample_share_prices, write_share_prices_csv,
+    },
+    registry::VaultRegistry,
+    vault::{fetch_vault_info, IVault, IVaultFactory},
+};
+
+pub async fn retrieve_vault_details(rpc_url: Url) -> Result<()> {
+    // This is a valid morpho vault address on Ethereum (Steakhouse USDC)
+    let vault_address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB"); // Steakhouse USDC
+    let vault = fetch_vault_info(rpc_url, vault_address, BlockId::latest()).await?;
+
+    println!("Vault {} at address {:#20x} has underlying token {:#20x} and currently has {} assets under management", vault.name, vault_address, vault.asset, vault.total_assets);
+    Ok(())
+}
+
+pub async fn retrieve_vault_activity_details(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+
+    // This is a valid morpho vault address on Ethereum (Steakhouse USDC)
+    let vault_address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+
+    // Vault activity on a specific block range
+    let filter = Filter::new()
+        .address(vault_address)
+        .from_block(BlockNumberOrTag::Number(21_200_000))
+        .events([
+            IVault::Deposit::SIGNATURE_HASH,
+            IVault::Withdraw::SIGNATURE_HASH,
+            IVault::Transfer::SIGNATURE_HASH,
+            IVault::UpdateLastTotalAssets::SIGNATURE_HASH,
+        ]);
+
+    let logs = provider.get_logs(&filter).await?;
+
+    println!("Got {} logs", logs.len());
+    for log in logs {
+        match log.topic0() {
+            Some(&IVault::Deposit::SIGNATURE_HASH) => {
+                let IVault::Deposit {
+                    sender: _sender,
+                    owner,
+                    assets,
+                    shares,
+                } = log.log_decode()?.inner.data;
+                println!(
+                    "User {:#20x} deposited {} assets for {} shares",
+                    owner, assets, shares
+                );
+            }
+            Some(&IVault::Withdraw::SIGNATURE_HASH) => {
+                let IVault::Withdraw {
+                    sender: _sender,
+                    receiver: _receiver,
+                    owner,
+                    assets,
+                    shares,
+                } = log.log_decode()?.inner.data;
+                println!(
+                    "User {:#20x} withdrew {} assets for {} shares",
+                    owner, assets, shares
+                );
+            }
+            Some(&IVault::Transfer::SIGNATURE_HASH) => {
+                let IVault::Transfer { from, to, value } = log.log_decode()?.inner.data;
+                println!(
+                    "User {:#20x} transfered {} shares to user {:#20x}",
+                    from, value, to
+                );
+            }
+            Some(&IVault::UpdateLastTotalAssets::SIGNATURE_HASH) => {
+                let IVault::UpdateLastTotalAssets { updatedTotalAssets } =
+                    log.log_decode()?.inner.data;
+                println!("Vault updated its total assets to {}", updatedTotalAssets)
+            }
+            // Timelocked governance events (SubmitCap, SetCap, SubmitTimelock, ...) are decoded in governance.rs
+            // Missing SetSkimRecipient, SetFee, SetFeeRecipient, SetCurator, SetIsAllocator,
+            // SetSupplyQueue, SetWithdrawQueue, ReallocateSupply, ReallocateWithdraw, AccrueInterest, Skim
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+pub async fn retrieve_vaults(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+
+    // This factory emits an event when a vault is deployed
+    let vault_factory_address = address!("A9c3D3a366466Fa809d1Ae982Fb2c46E5fC41101");
+
+    // Filter over factory since deployment
+    let filter = Filter::new()
+        .address(vault_factory_address)
+        .from_block(BlockNumberOrTag::Number(18_925_584));
+
+    let logs = provider.get_logs(&filter).await?;
+
+    println!("Got {} logs", logs.len());
+    for log in logs {
+        if let Some(&IVaultFactory::CreateMetaMorpho::SIGNATURE_HASH) = log.topic0() {
+            let IVaultFactory::CreateMetaMorpho {
+                metaMorpho,
+                caller,
+                initialOwner: _initial_owner,
+                initialTimelock: _initial_timelock,
+                asset,
+                name,
+                symbol: _symbol,
+                salt: _salt,
+            } = log.log_decode()?.inner.data;
+            println!(
+                "Morpho vault {} at address {:#20x} created by {:#20x}, for asset {:#20x} ",
+                name, metaMorpho, caller, asset
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn index_vaults(rpc_url: Url) -> Result<()> {
+    let mut registry = VaultRegistry::new(1);
+    let added = registry.refresh(rpc_url.clone()).await?;
+    println!("Indexed {} vaults on chain {}", added, registry.chain_id);
+
+    // USDC on Ethereum
+    let usdc = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    for vault in registry.by_asset(usdc) {
+        println!(
+            "Morpho vault {} ({}) {} at address {:#20x} owned by {:#20x}, curated by {:#20x}",
+            vault.name, vault.symbol, vault.version, vault.address, vault.owner, vault.curator
+        );
+    }
+
+    // Steakhouse USDC
+    let steakhouse = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    if let Some(record) = registry.vaults.get(&steakhouse) {
+        for vault in registry
+            .by_curator(record.curator)
+            .into_iter()
+            .chain(registry.by_owner(record.owner))
+        {
+            println!(
+                "Morpho vault {} on chain {} created at block {:?} by {:#20x} through factory {:#20x}, with initial owner {:#20x}, initial timelock {} and salt {}",
+                vault.name,
+                vault.chain_id,
+                vault.created_at_block,
+                vault.caller,
+                vault.factory,
+                vault.initial_owner,
+                vault.initial_timelock,
+                vault.salt
+            );
+        }
+    }
+
+    // Only new vaults are fetched on refresh
+    let added = registry.refresh(rpc_url).await?;
+    println!("{} vaults created since last refresh", added);
+    Ok(())
+}
+
+pub async fn track_vaults_governance(rpc_url: Url) -> Result<()> {
+    // These are valid morpho vault addresses on Ethereum
+    let vaults = [
+        address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB"), // Steakhouse USDC
+    ];
+
+    for vault_address in vaults {
+        // Scan since the vault factory deployment
+        let history =
+            retrieve_governance_history(rpc_url.clone(), vault_address, 18_925_584).await?;
+        let pending = retrieve_pending_actions(rpc_url.clone(), vault_address, &history).await?;
+
+        println!(
+            "Vault {:#20x} has {} governance events and {} pending actions",
+            vault_address,
+            history.len(),
+            pending.len()
+        );
+        for event in history {
+            println!(
+                "Block {:?}, transaction {:?}: {:?} {:?} with value {:?}",
+                event.block_number, event.transaction_hash, event.action, event.status, event.value
+            );
+        }
+        for action in pending {
+            println!(
+                "Pending {:?} on vault {:#20x} with value {:?} can be accepted from timestamp {}",
+                action.action, action.vault, action.value, action.valid_at
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn retrieve_vault_performance(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+
+    // This is a valid morpho vault address on Ethereum (Steakhouse USDC)
+    let vault_address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    let latest_block = provider.get_block_number().await?;
+    // One sample a day (7200 blocks) over the last 60 days, or since genesis on a younger chain
+    let from_block = latest_block.saturating_sub(60 * 7200);
+
+    let points = sample_share_prices(
+        rpc_url.clone(),
+        vault_address,
+        from_block,
+        latest_block,
+        7200,
+    )
+    .await?;
+    let (_, fee_shares) =
+        reconstruct_share_prices(rpc_url.clone(), vault_address, from_block, latest_block).await?;
+    let performance = compute_performance(&points, fee_shares);
+
+    println!(
+        "Vault {:#20x} realized APY: 7d {:?}, 30d {:?}, over the period {:?}, with {} fee shares minted",
+        vault_address,
+        performance.apy_7d,
+        performance.apy_30d,
+        performance.apy_since_inception,
+        performance.fee_shares
+    );
+
+    write_share_prices_csv("share_prices.csv", &points)?;
+    println!("Share price history written to share_prices.csv");
+    Ok(())
+}
+
+pub async fn retrieve_vault_statements(rpc_url: Url) -> Result<()> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+
+    // This is a valid morpho vault address on Ethereum (Steakhouse USDC)
+    let vault_address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    let vault = IVault::new(vault_address, provider.clone());
+
+    // The whole history is needed to get the right cost basis, so scan since the vault factory deployment
+    let ledger = build_vault_ledger(rpc_url, vault_address, 18_925_584).await?;
+
+    let total_assets = vault.totalAssets().call().await?.assets;
+    let total_supply = vault.totalSupply().call().await?._0;
+    let decimals_offset = vault.DECIMALS_OFFSET().call().await?._0;
+
+    for (owner, position) in ledger.positions.iter() {
+        let value = convert_to_assets(position.shares, total_assets, total_supply, decimals_offset);
+        println!(
+            "User {:#20x} holds {} shares worth {} assets:\n- Net deposited: {}\n- Average entry share price: {:?}\n- Realized PnL: {}\n- Unrealized PnL: {}",
+            owner,
+            position.shares,
+            value,
+            position.net_deposited,
+            position.average_entry_price(decimals_offset),
+            position.realized_pnl,
+            position.unrealized_pnl(value)
+        );
+    }
+    Ok(())
+}
+
+pub async fn optimize_vault_allocation(rpc_url: Url) -> Result<()> {
+    // This is a valid morpho vault address on Ethereum (Steakhouse USDC)
+    let vault_address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+
+    let (total_assets, markets) = retrieve_vault_allocations(rpc_url, vault_address).await?;
+    let plan = optimize_allocation(total_assets, &markets, DEFAULT_STEPS);
+
+    println!(
+        "Vault {:#20x} manages {} assets over {} markets",
+        vault_address,
+        total_assets,
+        markets.len()
+    );
+    for (id, current, target) in plan.allocations.iter() {
+        println!(
+            "Market {:#32x}: {:.0} assets currently supplied, {:.0} after reallocation",
+            id, current, target
+        );
+    }
+    if plan.idle_assets > 0.0 {
+        println!(
+            "{:.0} assets cannot be allocated (all caps reached)",
+            plan.idle_assets
+        );
+    }
+    println!(
+        "Expected vault APY is {:.4}% before and {:.4}% after reallocation",
+        plan.apy_before * 100.0,
+        plan.apy_after * 100.0
+    );
+    Ok(())
+}
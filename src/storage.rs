@@ -0,0 +1,261 @@
+use crate::error::Result;
+use crate::market::{IMorpho, MORPHO_ADDRESS};
+use alloy::{
+    primitives::{keccak256, Address, B256, U256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+
+// Storage layout of Morpho.sol (equivalent of MorphoStorageLib.sol)
+pub const OWNER_SLOT: u64 = 0;
+pub const FEE_RECIPIENT_SLOT: u64 = 1;
+pub const POSITION_SLOT: u64 = 2;
+pub const MARKET_SLOT: u64 = 3;
+pub const IS_IRM_ENABLED_SLOT: u64 = 4;
+pub const IS_LLTV_ENABLED_SLOT: u64 = 5;
+pub const IS_AUTHORIZED_SLOT: u64 = 6;
+pub const NONCE_SLOT: u64 = 7;
+pub const ID_TO_MARKET_PARAMS_SLOT: u64 = 8;
+
+// Offsets of the fields inside their struct
+pub const SUPPLY_SHARES_OFFSET: u64 = 0;
+pub const BORROW_SHARES_AND_COLLATERAL_OFFSET: u64 = 1;
+pub const TOTAL_SUPPLY_ASSETS_AND_SHARES_OFFSET: u64 = 0;
+pub const TOTAL_BORROW_ASSETS_AND_SHARES_OFFSET: u64 = 1;
+pub const LAST_UPDATE_AND_FEE_OFFSET: u64 = 2;
+pub const LOAN_TOKEN_OFFSET: u64 = 0;
+pub const COLLATERAL_TOKEN_OFFSET: u64 = 1;
+pub const ORACLE_OFFSET: u64 = 2;
+pub const IRM_OFFSET: u64 = 3;
+pub const LLTV_OFFSET: u64 = 4;
+
+// Number of slots read per extSloads call
+pub const SLOTS_PER_CALL: usize = 1_000;
+
+fn slot(slot: u64) -> B256 {
+    B256::from(U256::from(slot))
+}
+
+// Slot of mapping[key] for a mapping stored at `slot`: keccak256(abi.encode(key, slot))
+fn mapping_slot(key: B256, slot: B256) -> B256 {
+    keccak256([key.as_slice(), slot.as_slice()].concat())
+}
+
+fn offset_slot(slot: B256, offset: u64) -> B256 {
+    B256::from(U256::from_be_bytes(slot.0) + U256::from(offset))
+}
+
+pub fn owner_slot() -> B256 {
+    slot(OWNER_SLOT)
+}
+
+pub fn fee_recipient_slot() -> B256 {
+    slot(FEE_RECIPIENT_SLOT)
+}
+
+pub fn position_supply_shares_slot(id: B256, user: Address) -> B256 {
+    offset_slot(
+        mapping_slot(user.into_word(), mapping_slot(id, slot(POSITION_SLOT))),
+        SUPPLY_SHARES_OFFSET,
+    )
+}
+
+pub fn position_borrow_shares_and_collateral_slot(id: B256, user: Address) -> B256 {
+    offset_slot(
+        mapping_slot(user.into_word(), mapping_slot(id, slot(POSITION_SLOT))),
+        BORROW_SHARES_AND_COLLATERAL_OFFSET,
+    )
+}
+
+pub fn market_total_supply_assets_and_shares_slot(id: B256) -> B256 {
+    offset_slot(
+        mapping_slot(id, slot(MARKET_SLOT)),
+        TOTAL_SUPPLY_ASSETS_AND_SHARES_OFFSET,
+    )
+}
+
+pub fn market_total_borrow_assets_and_shares_slot(id: B256) -> B256 {
+    offset_slot(
+        mapping_slot(id, slot(MARKET_SLOT)),
+        TOTAL_BORROW_ASSETS_AND_SHARES_OFFSET,
+    )
+}
+
+pub fn market_last_update_and_fee_slot(id: B256) -> B256 {
+    offset_slot(
+        mapping_slot(id, slot(MARKET_SLOT)),
+        LAST_UPDATE_AND_FEE_OFFSET,
+    )
+}
+
+pub fn is_irm_enabled_slot(irm: Address) -> B256 {
+    mapping_slot(irm.into_word(), slot(IS_IRM_ENABLED_SLOT))
+}
+
+pub fn is_lltv_enabled_slot(lltv: U256) -> B256 {
+    mapping_slot(B256::from(lltv), slot(IS_LLTV_ENABLED_SLOT))
+}
+
+pub fn is_authorized_slot(authorizer: Address, authorized: Address) -> B256 {
+    mapping_slot(
+        authorized.into_word(),
+        mapping_slot(authorizer.into_word(), slot(IS_AUTHORIZED_SLOT)),
+    )
+}
+
+pub fn nonce_slot(authorizer: Address) -> B256 {
+    mapping_slot(authorizer.into_word(), slot(NONCE_SLOT))
+}
+
+pub fn id_to_market_params_slot(id: B256, offset: u64) -> B256 {
+    offset_slot(mapping_slot(id, slot(ID_TO_MARKET_PARAMS_SLOT)), offset)
+}
+
+// Two uint128 packed in a slot: the first declared field is in the lower half
+pub fn unpack_u128s(value: B256) -> (u128, u128) {
+    let value = U256::from_be_bytes(value.0);
+    let low = value & U256::from(u128::MAX);
+    let high: U256 = value >> 128;
+    (low.to::<u128>(), high.to::<u128>())
+}
+
+pub fn slot_to_address(value: B256) -> Address {
+    Address::from_word(value)
+}
+
+pub async fn ext_sloads(rpc_url: Url, slots: &[B256]) -> Result<Vec<B256>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+
+    let mut values = Vec::with_capacity(slots.len());
+    for chunk in slots.chunks(SLOTS_PER_CALL) {
+        values.extend(morpho.extSloads(chunk.to_vec()).call().await?.res);
+    }
+    Ok(values)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PositionSlots {
+    pub supply_shares: U256,
+    pub borrow_shares: u128,
+    pub collateral: u128,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MarketSlots {
+    pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
+    pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
+    pub last_update: u128,
+    pub fee: u128,
+}
+
+pub async fn snapshot_positions(
+    rpc_url: Url,
+    positions: &[(B256, Address)],
+) -> Result<Vec<PositionSlots>> {
+    let slots: Vec<B256> = positions
+        .iter()
+        .flat_map(|(id, user)| {
+            [
+                position_supply_shares_slot(*id, *user),
+                position_borrow_shares_and_collateral_slot(*id, *user),
+            ]
+        })
+        .collect();
+    let values = ext_sloads(rpc_url, &slots).await?;
+
+    Ok(values
+        .chunks(2)
+        .map(|values| {
+            let (borrow_shares, collateral) = unpack_u128s(values[1]);
+            PositionSlots {
+                supply_shares: U256::from_be_bytes(values[0].0),
+                borrow_shares,
+                collateral,
+            }
+        })
+        .collect())
+}
+
+pub async fn snapshot_markets(rpc_url: Url, ids: &[B256]) -> Result<Vec<MarketSlots>> {
+    let slots: Vec<B256> = ids
+        .iter()
+        .flat_map(|id| {
+            [
+                market_total_supply_assets_and_shares_slot(*id),
+                market_total_borrow_assets_and_shares_slot(*id),
+                market_last_update_and_fee_slot(*id),
+            ]
+        })
+        .collect();
+    let values = ext_sloads(rpc_url, &slots).await?;
+
+    Ok(values
+        .chunks(3)
+        .map(|values| {
+            let (total_supply_assets, total_supply_shares) = unpack_u128s(values[0]);
+            let (total_borrow_assets, total_borrow_shares) = unpack_u128s(values[1]);
+            let (last_update, fee) = unpack_u128s(values[2]);
+            MarketSlots {
+                total_supply_assets,
+                total_supply_shares,
+                total_borrow_assets,
+                total_borrow_shares,
+                last_update,
+                fee,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, b256};
+
+    // USD0++/USDC (86%)
+    const MARKET_ID: B256 =
+        b256!("b48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2");
+    const USER: Address = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+
+    // Slots of MorphoStorageLib, computed as keccak256(abi.encode(key, slot)) + offset
+    #[test]
+    fn computes_the_morpho_storage_lib_slots() {
+        assert_eq!(owner_slot(), B256::ZERO);
+        assert_eq!(fee_recipient_slot(), B256::with_last_byte(1));
+        assert_eq!(
+            market_total_supply_assets_and_shares_slot(MARKET_ID),
+            b256!("9955c5e997978c69f21558e21c6c786788e1b73191d56557bd458da0725248e5")
+        );
+        assert_eq!(
+            market_total_borrow_assets_and_shares_slot(MARKET_ID),
+            b256!("9955c5e997978c69f21558e21c6c786788e1b73191d56557bd458da0725248e6")
+        );
+        assert_eq!(
+            market_last_update_and_fee_slot(MARKET_ID),
+            b256!("9955c5e997978c69f21558e21c6c786788e1b73191d56557bd458da0725248e7")
+        );
+        assert_eq!(
+            position_supply_shares_slot(MARKET_ID, USER),
+            b256!("b985e1b82f80ee5cf9776eccc31da6839f895cf5ce9113a2aac488b54906e7a7")
+        );
+        assert_eq!(
+            position_borrow_shares_and_collateral_slot(MARKET_ID, USER),
+            b256!("b985e1b82f80ee5cf9776eccc31da6839f895cf5ce9113a2aac488b54906e7a8")
+        );
+        assert_eq!(
+            is_authorized_slot(USER, address!("0000000000000000000000000000000000000B0B")),
+            b256!("72ba8aaeaf82c1da13f0345031436b8ce195f7af44eee5c65662f35d07154f9c")
+        );
+    }
+
+    #[test]
+    fn unpacks_the_first_field_from_the_lower_half() {
+        // totalSupplyShares in the upper 128 bits, totalSupplyAssets in the lower ones
+        let packed = b256!("00000000000000000000000000000002fffffffffffffffffffffffffffffffe");
+        assert_eq!(unpack_u128s(packed), (u128::MAX - 1, 2));
+        assert_eq!(unpack_u128s(B256::ZERO), (0, 0));
+    }
+}
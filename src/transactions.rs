@@ -0,0 +1,444 @@
+use crate::arithmetic::{to_assets_down, to_assets_up, to_shares_down, to_shares_up};
+use crate::error::{Error, Result};
+use crate::market::{accrue_interest, IMorpho, IIRM, MORPHO_ADDRESS};
+use crate::multicall::{irm_market, irm_market_params};
+use alloy::{
+    eips::BlockNumberOrTag,
+    network::TransactionBuilder,
+    primitives::{Address, Bytes, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{BlockTransactionsKind, TransactionRequest},
+    sol_types::SolCall,
+    transports::http::reqwest::Url,
+};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use IMorpho::MarketParams;
+
+// Morpho takes either assets or shares, the other one must be zero
+#[derive(Debug, Clone, Copy)]
+pub enum Amount {
+    Assets(U256),
+    Shares(U256),
+}
+
+impl Amount {
+    fn split(self) -> (U256, U256) {
+        match self {
+            Amount::Assets(assets) => (assets, U256::ZERO),
+            Amount::Shares(shares) => (U256::ZERO, shares),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MorphoAction {
+    Supply {
+        amount: Amount,
+        on_behalf: Address,
+    },
+    Withdraw {
+        amount: Amount,
+        on_behalf: Address,
+        receiver: Address,
+    },
+    Borrow {
+        amount: Amount,
+        on_behalf: Address,
+        receiver: Address,
+    },
+    Repay {
+        amount: Amount,
+        on_behalf: Address,
+    },
+    SupplyCollateral {
+        assets: U256,
+        on_behalf: Address,
+    },
+    WithdrawCollateral {
+        assets: U256,
+        on_behalf: Address,
+        receiver: Address,
+    },
+    // Assets are the seized collateral, shares the repaid borrow shares
+    Liquidate {
+        borrower: Address,
+        amount: Amount,
+    },
+    FlashLoan {
+        token: Address,
+        assets: U256,
+        data: Bytes,
+    },
+}
+
+impl MorphoAction {
+    pub fn calldata(&self, market_params: &MarketParams) -> Vec<u8> {
+        let params = market_params.clone();
+        match self.clone() {
+            MorphoAction::Supply { amount, on_behalf } => {
+                let (assets, shares) = amount.split();
+                IMorpho::supplyCall {
+                    marketParams: params,
+                    assets,
+                    shares,
+                    onBehalf: on_behalf,
+                    data: Bytes::new(),
+                }
+                .abi_encode()
+            }
+            MorphoAction::Withdraw {
+                amount,
+                on_behalf,
+                receiver,
+            } => {
+                let (assets, shares) = amount.split();
+                IMorpho::withdrawCall {
+                    marketParams: params,
+                    assets,
+                    shares,
+                    onBehalf: on_behalf,
+                    receiver,
+                }
+                .abi_encode()
+            }
+            MorphoAction::Borrow {
+                amount,
+                on_behalf,
+                receiver,
+            } => {
+                let (assets, shares) = amount.split();
+                IMorpho::borrowCall {
+                    marketParams: params,
+                    assets,
+                    shares,
+                    onBehalf: on_behalf,
+                    receiver,
+                }
+                .abi_encode()
+            }
+            MorphoAction::Repay { amount, on_behalf } => {
+                let (assets, shares) = amount.split();
+                IMorpho::repayCall {
+                    marketParams: params,
+                    assets,
+                    shares,
+                    onBehalf: on_behalf,
+                    data: Bytes::new(),
+                }
+                .abi_encode()
+            }
+            MorphoAction::SupplyCollateral { assets, on_behalf } => IMorpho::supplyCollateralCall {
+                marketParams: params,
+                assets,
+                onBehalf: on_behalf,
+                data: Bytes::new(),
+            }
+            .abi_encode(),
+            MorphoAction::WithdrawCollateral {
+                assets,
+                on_behalf,
+                receiver,
+            } => IMorpho::withdrawCollateralCall {
+                marketParams: params,
+                assets,
+                onBehalf: on_behalf,
+                receiver,
+            }
+            .abi_encode(),
+            MorphoAction::Liquidate { borrower, amount } => {
+                let (seized_assets, repaid_shares) = amount.split();
+                IMorpho::liquidateCall {
+                    marketParams: params,
+                    borrower,
+                    seizedAssets: seized_assets,
+                    repaidShares: repaid_shares,
+                    data: Bytes::new(),
+                }
+                .abi_encode()
+            }
+            MorphoAction::FlashLoan {
+                token,
+                assets,
+                data,
+            } => IMorpho::flashLoanCall {
+                token,
+                assets,
+                data,
+            }
+            .abi_encode(),
+        }
+    }
+
+    // Assets and shares the action is expected to move, with the rounding of Morpho.sol. `market` must
+    // include the interest accrued since its last update (see `accrue_interest`), as Morpho accrues it first.
+    // Only for supply, withdraw, borrow and repay, which return (assets, shares).
+    pub fn expected_amounts(&self, market: &IMorpho::marketReturn) -> Option<(U256, U256)> {
+        let total_supply_assets = U256::from(market.totalSupplyAssets);
+        let total_supply_shares = U256::from(market.totalSupplyShares);
+        let total_borrow_assets = U256::from(market.totalBorrowAssets);
+        let total_borrow_shares = U256::from(market.totalBorrowShares);
+        Some(match self {
+            MorphoAction::Supply { amount, .. } => match *amount {
+                Amount::Assets(assets) => (
+                    assets,
+                    to_shares_down(assets, total_supply_assets, total_supply_shares),
+                ),
+                Amount::Shares(shares) => (
+                    to_assets_up(shares, total_supply_assets, total_supply_shares),
+                    shares,
+                ),
+            },
+            MorphoAction::Withdraw { amount, .. } => match *amount {
+                Amount::Assets(assets) => (
+                    assets,
+                    to_shares_up(assets, total_supply_assets, total_supply_shares),
+                ),
+                Amount::Shares(shares) => (
+                    to_assets_down(shares, total_supply_assets, total_supply_shares),
+                    shares,
+                ),
+            },
+            MorphoAction::Borrow { amount, .. } => match *amount {
+                Amount::Assets(assets) => (
+                    assets,
+                    to_shares_up(assets, total_borrow_assets, total_borrow_shares),
+                ),
+                Amount::Shares(shares) => (
+                    to_assets_down(shares, total_borrow_assets, total_borrow_shares),
+                    shares,
+                ),
+            },
+            MorphoAction::Repay { amount, .. } => match *amount {
+                Amount::Assets(assets) => (
+                    assets,
+                    to_shares_down(assets, total_borrow_assets, total_borrow_shares),
+                ),
+                Amount::Shares(shares) => (
+                    to_assets_up(shares, total_borrow_assets, total_borrow_shares),
+                    shares,
+                ),
+            },
+            _ => return None,
+        })
+    }
+
+    // Checks simulated amounts against the expected ones. The side of the amount which is not fixed
+    // can move with accrued interest: it must not be worse than expected by more than `max_slippage_bps`.
+    // This is a preflight check only, Morpho takes exact amounts and the sent transaction has no bound.
+    pub fn check_preflight_slippage(
+        &self,
+        expected: (U256, U256),
+        simulated: (U256, U256),
+        max_slippage_bps: u64,
+    ) -> Result<()> {
+        check_slippage_bps(max_slippage_bps)?;
+        let bps = U256::from(10_000);
+        let lower = |x: U256| x * (bps - U256::from(max_slippage_bps)) / bps;
+        let upper = |x: U256| x * (bps + U256::from(max_slippage_bps)) / bps;
+        let ok = match self {
+            // Receiving shares: at least the expected shares, or paying at most the expected assets
+            MorphoAction::Supply { amount, .. } | MorphoAction::Repay { amount, .. } => {
+                match amount {
+                    Amount::Assets(_) => simulated.1 >= lower(expected.1),
+                    Amount::Shares(_) => simulated.0 <= upper(expected.0),
+                }
+            }
+            // Burning or minting debt shares: at most the expected shares, or at least the expected assets
+            MorphoAction::Withdraw { amount, .. } | MorphoAction::Borrow { amount, .. } => {
+                match amount {
+                    Amount::Assets(_) => simulated.1 <= upper(expected.1),
+                    Amount::Shares(_) => simulated.0 >= lower(expected.0),
+                }
+            }
+            _ => true,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::Slippage(format!(
+                "expected (assets, shares) {:?}, simulated {:?}",
+                expected, simulated
+            )))
+        }
+    }
+}
+
+// Slippages are given in basis points of the expected amount, at most 100%
+pub(crate) fn check_slippage_bps(max_slippage_bps: u64) -> Result<()> {
+    if max_slippage_bps > 10_000 {
+        return Err(Error::Config(format!(
+            "slippage of {} bps is above 100%",
+            max_slippage_bps
+        )));
+    }
+    Ok(())
+}
+
+// Unsigned transaction calling Morpho, to be signed and sent by the caller. It carries no slippage
+// bound: the amounts checked by `preflight` can still move before it is mined, enforcing a bound
+// on-chain needs a bundler wrapping the call.
+pub fn build_transaction(
+    from: Address,
+    market_params: &MarketParams,
+    action: &MorphoAction,
+) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_from(from)
+        .with_to(MORPHO_ADDRESS)
+        .with_input(action.calldata(market_params))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PreflightResult {
+    // (assets, shares) returned by supply, withdraw, borrow and repay
+    pub amounts: Option<(U256, U256)>,
+}
+
+impl Serialize for PreflightResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut result = serializer.serialize_struct("PreflightResult", 2)?;
+        result.serialize_field(
+            "assets",
+            &self.amounts.map(|(assets, _)| assets.to_string()),
+        )?;
+        result.serialize_field(
+            "shares",
+            &self.amounts.map(|(_, shares)| shares.to_string()),
+        )?;
+        result.end()
+    }
+}
+
+// Simulates the transaction with eth_call and checks the simulated amounts against the expected ones
+// (see `check_preflight_slippage`), reverts are returned as Error::Revert
+pub async fn preflight(
+    rpc_url: Url,
+    tx: &TransactionRequest,
+    action: &MorphoAction,
+    market_id: B256,
+    max_slippage_bps: u64,
+) -> Result<PreflightResult> {
+    check_slippage_bps(max_slippage_bps)?;
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider.clone());
+    let market = morpho.market(market_id).call().await?;
+    let market_params = morpho.idToMarketParams(market_id).call().await?;
+
+    // The call runs on the latest block, so the interest is accrued up to its timestamp
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+        .await?
+        .ok_or_else(|| Error::NotFound("latest block".to_string()))?;
+    let borrow_rate = if market_params.irm == Address::ZERO {
+        U256::ZERO
+    } else {
+        IIRM::new(market_params.irm, provider.clone())
+            .borrowRateView(irm_market_params(&market_params), irm_market(&market))
+            .call()
+            .await?
+            ._0
+    };
+    let market = accrue_interest(&market, borrow_rate, block.header.timestamp);
+
+    let output = provider.call(tx).await?;
+
+    let amounts = match action {
+        MorphoAction::Supply { .. } => {
+            let ret = IMorpho::supplyCall::abi_decode_returns(&output, true)?;
+            Some((ret._0, ret._1))
+        }
+        MorphoAction::Withdraw { .. } => {
+            let ret = IMorpho::withdrawCall::abi_decode_returns(&output, true)?;
+            Some((ret._0, ret._1))
+        }
+        MorphoAction::Borrow { .. } => {
+            let ret = IMorpho::borrowCall::abi_decode_returns(&output, true)?;
+            Some((ret._0, ret._1))
+        }
+        MorphoAction::Repay { .. } => {
+            let ret = IMorpho::repayCall::abi_decode_returns(&output, true)?;
+            Some((ret._0, ret._1))
+        }
+        _ => None,
+    };
+
+    if let (Some(simulated), Some(expected)) = (amounts, action.expected_amounts(&market)) {
+        action.check_preflight_slippage(expected, simulated, max_slippage_bps)?;
+    }
+    Ok(PreflightResult { amounts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    const USER: Address = address!("171c53d55B1BCb725F660677d9e8BAd7fD084282");
+
+    fn market() -> IMorpho::marketReturn {
+        IMorpho::marketReturn {
+            totalSupplyAssets: 1_000_000,
+            totalSupplyShares: 1_000_000_000_000,
+            totalBorrowAssets: 800_000,
+            totalBorrowShares: 800_000_000_000,
+            lastUpdate: 1_700_000_000,
+            fee: 0,
+        }
+    }
+
+    fn supply(assets: u64) -> MorphoAction {
+        MorphoAction::Supply {
+            amount: Amount::Assets(U256::from(assets)),
+            on_behalf: USER,
+        }
+    }
+
+    #[test]
+    fn expects_amounts_after_accrued_interest() {
+        // 1e-9 per second over 1e6 seconds is about 0.1% of interest
+        let borrow_rate = U256::from(1_000_000_000u64);
+        let accrued = accrue_interest(&market(), borrow_rate, 1_701_000_000);
+        assert!(accrued.totalSupplyAssets > market().totalSupplyAssets);
+        assert_eq!(
+            accrued.totalBorrowAssets - market().totalBorrowAssets,
+            accrued.totalSupplyAssets - market().totalSupplyAssets
+        );
+        assert_eq!(accrued.lastUpdate, 1_701_000_000);
+
+        // Shares are worth more once the interest is accrued, so fewer are minted
+        let (_, stale_shares) = supply(1_000).expected_amounts(&market()).unwrap();
+        let (assets, shares) = supply(1_000).expected_amounts(&accrued).unwrap();
+        assert_eq!(assets, U256::from(1_000));
+        assert!(shares < stale_shares);
+        // The stale expectation would reject a 0.01% bound that the accrued one accepts
+        assert!(supply(1_000)
+            .check_preflight_slippage((assets, stale_shares), (assets, shares), 1)
+            .is_err());
+        assert!(supply(1_000)
+            .check_preflight_slippage((assets, shares), (assets, shares), 1)
+            .is_ok());
+    }
+
+    #[test]
+    fn mints_fee_shares_when_accruing_interest() {
+        let market = IMorpho::marketReturn {
+            // 10% fee
+            fee: 100_000_000_000_000_000,
+            ..market()
+        };
+        let accrued = accrue_interest(&market, U256::from(1_000_000_000u64), 1_701_000_000);
+        assert!(accrued.totalSupplyShares > market.totalSupplyShares);
+    }
+
+    #[test]
+    fn rejects_slippage_above_100_percent() {
+        let amounts = (U256::from(1_000), U256::from(1_000));
+        assert!(matches!(
+            supply(1_000).check_preflight_slippage(amounts, amounts, 10_001),
+            Err(Error::Config(_))
+        ));
+        assert!(supply(1_000)
+            .check_preflight_slippage(amounts, amounts, 10_000)
+            .is_ok());
+    }
+}
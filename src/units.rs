@@ -0,0 +1,159 @@
+use crate::api::Asset;
+use crate::error::{Error, Result};
+use crate::market::{IMorpho, IERC20, MORPHO_ADDRESS};
+use crate::output::checksummed;
+use alloy::{
+    primitives::{utils::format_units, Address, B256, U256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+
+// Oracle prices are scaled by 1e36 (ORACLE_PRICE_SCALE in ConstantsLib.sol)
+pub const ORACLE_PRICE_SCALE_DECIMALS: u8 = 36;
+
+// Decimals kept when inverting an oracle price
+pub const INVERSE_PRICE_DECIMALS: u8 = 18;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenInfo {
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    pub decimals: u8,
+    pub symbol: String,
+}
+
+impl TryFrom<&Asset> for TokenInfo {
+    type Error = Error;
+
+    fn try_from(asset: &Asset) -> Result<Self> {
+        Ok(TokenInfo {
+            address: asset.token,
+            decimals: u8::try_from(asset.decimals).map_err(|_| {
+                Error::Config(format!(
+                    "token {:#20x} has {} decimals",
+                    asset.token, asset.decimals
+                ))
+            })?,
+            symbol: asset.symbol.clone(),
+        })
+    }
+}
+
+pub async fn retrieve_token_info(rpc_url: Url, token: Address) -> Result<TokenInfo> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let erc20 = IERC20::new(token, provider);
+    Ok(TokenInfo {
+        address: token,
+        decimals: erc20.decimals().call().await?._0,
+        symbol: erc20.symbol().call().await?._0,
+    })
+}
+
+// Loan and collateral tokens of a market
+pub async fn retrieve_market_tokens(
+    rpc_url: Url,
+    market_id: B256,
+) -> Result<(TokenInfo, TokenInfo)> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+    let params = morpho.idToMarketParams(market_id).call().await?;
+    Ok((
+        retrieve_token_info(rpc_url.clone(), params.loanToken).await?,
+        retrieve_token_info(rpc_url, params.collateralToken).await?,
+    ))
+}
+
+// Renders a raw amount with its token decimals and symbol, e.g. "1500.25 USDC"
+pub fn format_amount(amount: U256, token: &TokenInfo) -> Result<String> {
+    Ok(format!(
+        "{} {}",
+        format_units(amount, token.decimals)?,
+        token.symbol
+    ))
+}
+
+// The oracle price of 1 collateral token (10^collateral_decimals raw units) is
+// price * 10^collateral_decimals / 1e36 raw loan units
+fn price_decimals(collateral_decimals: u8, loan_decimals: u8) -> Result<u8> {
+    let decimals =
+        ORACLE_PRICE_SCALE_DECIMALS as i32 + loan_decimals as i32 - collateral_decimals as i32;
+    u8::try_from(decimals).map_err(|_| {
+        Error::Config(format!(
+            "collateral decimals {} and loan decimals {} give a price with {} decimals",
+            collateral_decimals, loan_decimals, decimals
+        ))
+    })
+}
+
+// Number of loan tokens worth 1 collateral token
+pub fn collateral_price_in_loan(
+    price: U256,
+    collateral_decimals: u8,
+    loan_decimals: u8,
+) -> Result<String> {
+    Ok(format_units(
+        price,
+        price_decimals(collateral_decimals, loan_decimals)?,
+    )?)
+}
+
+// Same as collateral_price_in_loan, as a float for comparisons
+pub fn collateral_price_in_loan_f64(
+    price: U256,
+    collateral_decimals: u8,
+    loan_decimals: u8,
+) -> Result<f64> {
+    Ok(f64::from(price) / 10f64.powi(price_decimals(collateral_decimals, loan_decimals)? as i32))
+}
+
+// Number of collateral tokens worth 1 loan token
+pub fn loan_price_in_collateral(
+    price: U256,
+    collateral_decimals: u8,
+    loan_decimals: u8,
+) -> Result<String> {
+    if price.is_zero() {
+        return Err(Error::Math("oracle price is zero".to_string()));
+    }
+    let exponent =
+        price_decimals(collateral_decimals, loan_decimals)? as u32 + INVERSE_PRICE_DECIMALS as u32;
+    let scale = U256::from(10)
+        .checked_pow(U256::from(exponent))
+        .ok_or_else(|| Error::Math(format!("10^{} overflows", exponent)))?;
+    let inverse = scale / price;
+    Ok(format_units(inverse, INVERSE_PRICE_DECIMALS)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_oracle_prices() {
+        // 1 WETH (18 decimals) = 3000 USDC (6 decimals)
+        let price = U256::from(3_000u64) * U256::from(10).pow(U256::from(24));
+        assert_eq!(
+            collateral_price_in_loan(price, 18, 6).unwrap(),
+            "3000.000000000000000000000000"
+        );
+        assert_eq!(collateral_price_in_loan_f64(price, 18, 6).unwrap(), 3000.0);
+        assert_eq!(
+            loan_price_in_collateral(price, 18, 6).unwrap(),
+            "0.000333333333333333"
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_decimals() {
+        assert!(matches!(
+            collateral_price_in_loan(U256::from(1), 40, 0),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            collateral_price_in_loan_f64(U256::from(1), 0, 255),
+            Err(Error::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,323 @@
+use crate::authorization::ecrecover_vrs;
+use crate::error::{Error, Result};
+use crate::market::IERC20;
+use crate::revert::ProtocolError;
+use crate::transactions::check_slippage_bps;
+use crate::vault::IVault::{self, IVaultErrors};
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol,
+    sol_types::{Eip712Domain, SolCall, SolStruct},
+    transports::http::reqwest::Url,
+};
+
+sol! {
+    // EIP-2612 permit, as hashed by ERC20Permit
+    struct Permit {
+        address owner;
+        address spender;
+        uint256 value;
+        uint256 nonce;
+        uint256 deadline;
+    }
+}
+
+// Domain of the vault shares permit, read through ERC-5267 and checked against DOMAIN_SEPARATOR
+pub async fn retrieve_vault_domain(rpc_url: Url, vault: Address) -> Result<Eip712Domain> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let vault = IVault::new(vault, provider);
+
+    let fields = vault.eip712Domain().call().await?;
+    let domain = Eip712Domain::new(
+        Some(fields.name.into()),
+        Some(fields.version.into()),
+        Some(fields.chainId),
+        Some(fields.verifyingContract),
+        None,
+    );
+    let onchain_separator = vault.DOMAIN_SEPARATOR().call().await?._0;
+    if domain.separator() != onchain_separator {
+        return Err(Error::Config(format!(
+            "domain separator mismatch: computed {:#32x}, vault has {:#32x}",
+            domain.separator(),
+            onchain_separator
+        )));
+    }
+    Ok(domain)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PermitSignature {
+    pub deadline: U256,
+    pub v: u8,
+    pub r: B256,
+    pub s: B256,
+}
+
+// Signs a permit of vault shares with the current nonce of the signer
+pub async fn sign_permit(
+    rpc_url: Url,
+    vault: Address,
+    signer: &PrivateKeySigner,
+    spender: Address,
+    value: U256,
+    deadline: U256,
+) -> Result<PermitSignature> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let owner = signer.address();
+    let nonce = IVault::new(vault, provider).nonces(owner).call().await?._0;
+    let domain = retrieve_vault_domain(rpc_url, vault).await?;
+
+    let permit = Permit {
+        owner,
+        spender,
+        value,
+        nonce,
+        deadline,
+    };
+    let signature = signer.sign_hash_sync(&permit.eip712_signing_hash(&domain))?;
+    let (v, r, s) = ecrecover_vrs(&signature);
+    Ok(PermitSignature { deadline, v, r, s })
+}
+
+pub fn build_permit_transaction(
+    from: Address,
+    vault: Address,
+    owner: Address,
+    spender: Address,
+    value: U256,
+    signature: PermitSignature,
+) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_from(from)
+        .with_to(vault)
+        .with_input(
+            IVault::permitCall {
+                owner,
+                spender,
+                value,
+                deadline: signature.deadline,
+                v: signature.v,
+                r: signature.r,
+                s: signature.s,
+            }
+            .abi_encode(),
+        )
+}
+
+#[derive(Debug, Clone)]
+pub struct VaultTransaction {
+    pub tx: TransactionRequest,
+    // Shares previewed by the vault
+    pub expected_shares: U256,
+    // Minimum shares minted by a deposit, maximum shares burnt by a withdrawal. The simulation is checked
+    // against it, but the vault takes exact assets and the sent transaction is not bounded on-chain.
+    pub preflight_shares_bound: U256,
+}
+
+pub fn deposit_transaction(
+    vault: Address,
+    owner: Address,
+    assets: U256,
+    receiver: Address,
+) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_from(owner)
+        .with_to(vault)
+        .with_input(IVault::depositCall { assets, receiver }.abi_encode())
+}
+
+pub fn withdraw_transaction(
+    vault: Address,
+    owner: Address,
+    assets: U256,
+    receiver: Address,
+) -> TransactionRequest {
+    TransactionRequest::default()
+        .with_from(owner)
+        .with_to(vault)
+        .with_input(
+            IVault::withdrawCall {
+                assets,
+                receiver,
+                owner,
+            }
+            .abi_encode(),
+        )
+}
+
+// Simulates the transaction, vault reverts are returned as Error::Revert(ProtocolError::Vault)
+async fn simulate_shares(rpc_url: Url, tx: &TransactionRequest) -> Result<U256> {
+    let provider = ProviderBuilder::new().on_http(rpc_url);
+    let output = provider.call(tx).await?;
+    // deposit and withdraw both return a single uint256
+    Ok(IVault::depositCall::abi_decode_returns(&output, true)?.shares)
+}
+
+// Deposits `assets` for `receiver`, checking maxDeposit and the allowance of the vault first.
+// ERC-4626 deposits have no minimum output, so the bound is only checked by simulating the deposit,
+// enforcing it on-chain needs a bundler or a router wrapping the call.
+pub async fn build_deposit(
+    rpc_url: Url,
+    vault: Address,
+    owner: Address,
+    assets: U256,
+    receiver: Address,
+    max_slippage_bps: u64,
+) -> Result<VaultTransaction> {
+    check_slippage_bps(max_slippage_bps)?;
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let vault_contract = IVault::new(vault, provider.clone());
+
+    let max = vault_contract.maxDeposit(receiver).call().await?._0;
+    if assets > max {
+        return Err(
+            ProtocolError::Vault(IVaultErrors::ERC4626ExceededMaxDeposit(
+                IVault::ERC4626ExceededMaxDeposit {
+                    receiver,
+                    assets,
+                    max,
+                },
+            ))
+            .into(),
+        );
+    }
+    let asset = vault_contract.asset().call().await?._0;
+    let allowance = IERC20::new(asset, provider)
+        .allowance(owner, vault)
+        .call()
+        .await?
+        ._0;
+    if allowance < assets {
+        return Err(
+            ProtocolError::Vault(IVaultErrors::ERC20InsufficientAllowance(
+                IVault::ERC20InsufficientAllowance {
+                    spender: vault,
+                    allowance,
+                    needed: assets,
+                },
+            ))
+            .into(),
+        );
+    }
+
+    let expected_shares = vault_contract.previewDeposit(assets).call().await?._0;
+    let preflight_shares_bound =
+        expected_shares * U256::from(10_000 - max_slippage_bps) / U256::from(10_000);
+    let tx = deposit_transaction(vault, owner, assets, receiver);
+
+    let simulated = simulate_shares(rpc_url, &tx).await?;
+    if simulated < preflight_shares_bound {
+        return Err(Error::Slippage(format!(
+            "previewed {} shares, bound {}, simulated {}",
+            expected_shares, preflight_shares_bound, simulated
+        )));
+    }
+    Ok(VaultTransaction {
+        tx,
+        expected_shares,
+        preflight_shares_bound,
+    })
+}
+
+// Withdraws `assets` of `owner` to `receiver`, checking maxWithdraw first (it accounts for the vault liquidity).
+// As for deposits, the maximum shares burnt is a preflight check only.
+pub async fn build_withdraw(
+    rpc_url: Url,
+    vault: Address,
+    owner: Address,
+    assets: U256,
+    receiver: Address,
+    max_slippage_bps: u64,
+) -> Result<VaultTransaction> {
+    check_slippage_bps(max_slippage_bps)?;
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let vault_contract = IVault::new(vault, provider);
+
+    let max = vault_contract.maxWithdraw(owner).call().await?.assets;
+    if assets > max {
+        return Err(
+            ProtocolError::Vault(IVaultErrors::ERC4626ExceededMaxWithdraw(
+                IVault::ERC4626ExceededMaxWithdraw { owner, assets, max },
+            ))
+            .into(),
+        );
+    }
+
+    let expected_shares = vault_contract.previewWithdraw(assets).call().await?._0;
+    let preflight_shares_bound =
+        expected_shares * U256::from(10_000 + max_slippage_bps) / U256::from(10_000);
+    let tx = withdraw_transaction(vault, owner, assets, receiver);
+
+    let simulated = simulate_shares(rpc_url, &tx).await?;
+    if simulated > preflight_shares_bound {
+        return Err(Error::Slippage(format!(
+            "previewed {} shares, bound {}, simulated {}",
+            expected_shares, preflight_shares_bound, simulated
+        )));
+    }
+    Ok(VaultTransaction {
+        tx,
+        expected_shares,
+        preflight_shares_bound,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{address, b256, TxKind};
+
+    const VAULT: Address = address!("BEEF01735c132Ada46AA9aA4c54623cAA92A64CB");
+    const OWNER: Address = address!("1111111111111111111111111111111111111111");
+    const RECEIVER: Address = address!("2222222222222222222222222222222222222222");
+
+    fn input(tx: &TransactionRequest) -> &[u8] {
+        tx.input.input().expect("calldata")
+    }
+
+    #[test]
+    fn builds_the_permit_calldata() {
+        let signature = PermitSignature {
+            deadline: U256::from(1_700_000_000u64),
+            v: 28,
+            r: b256!("00000000000000000000000000000000000000000000000000000000000000aa"),
+            s: b256!("00000000000000000000000000000000000000000000000000000000000000bb"),
+        };
+        let tx =
+            build_permit_transaction(RECEIVER, VAULT, OWNER, RECEIVER, U256::from(5), signature);
+        assert_eq!(tx.from, Some(RECEIVER));
+        assert_eq!(tx.to, Some(TxKind::Call(VAULT)));
+
+        let call = IVault::permitCall::abi_decode(input(&tx), true).unwrap();
+        assert_eq!(call.owner, OWNER);
+        assert_eq!(call.spender, RECEIVER);
+        assert_eq!(call.value, U256::from(5));
+        assert_eq!(call.deadline, signature.deadline);
+        assert_eq!((call.v, call.r, call.s), (28, signature.r, signature.s));
+    }
+
+    #[test]
+    fn builds_the_deposit_and_withdraw_calldata() {
+        let assets = U256::from(1_000_000u64);
+
+        let deposit = deposit_transaction(VAULT, OWNER, assets, RECEIVER);
+        assert_eq!(deposit.from, Some(OWNER));
+        assert_eq!(deposit.to, Some(TxKind::Call(VAULT)));
+        let call = IVault::depositCall::abi_decode(input(&deposit), true).unwrap();
+        assert_eq!((call.assets, call.receiver), (assets, RECEIVER));
+
+        let withdraw = withdraw_transaction(VAULT, OWNER, assets, RECEIVER);
+        assert_eq!(withdraw.from, Some(OWNER));
+        assert_eq!(withdraw.to, Some(TxKind::Call(VAULT)));
+        let call = IVault::withdrawCall::abi_decode(input(&withdraw), true).unwrap();
+        assert_eq!(
+            (call.assets, call.receiver, call.owner),
+            (assets, RECEIVER, OWNER)
+        );
+    }
+}
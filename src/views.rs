@@ -0,0 +1,166 @@
+use crate::allocation::{blended_apy, retrieve_vault_allocations, MarketAllocation};
+use crate::arithmetic::{health_factor, to_assets_down, to_assets_up};
+use crate::error::Result;
+use crate::governance::{
+    retrieve_pending_actions, update_governance_history, GovernanceHistory, PendingAction,
+};
+use crate::irm::{signed_wad_to_f64, wad_to_f64, MarketCurve};
+use crate::market::{fetch_market_info, fetch_position, MarketInfo, Position, IIRM};
+use crate::output::decimal;
+use crate::vault::{fetch_vault_info, VaultInfo};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, U256},
+    providers::ProviderBuilder,
+    transports::http::reqwest::Url,
+};
+use serde::Serialize;
+
+// Computed markets, positions and vaults, served by `serve` and published by `Metrics`
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketView {
+    #[serde(flatten)]
+    pub info: MarketInfo,
+    pub utilization: f64,
+    // Rates of the AdaptiveCurveIRM at the current utilization, as yearly compounded rates
+    pub supply_apy: f64,
+    pub borrow_apy: f64,
+    pub rate_at_target: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionView {
+    #[serde(flatten)]
+    pub position: Position,
+    #[serde(serialize_with = "decimal")]
+    pub supply_assets: U256,
+    #[serde(serialize_with = "decimal")]
+    pub borrow_assets: U256,
+    // None without any borrow, the position is liquidatable below 1
+    pub health_factor: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultView {
+    #[serde(flatten)]
+    pub info: VaultInfo,
+    // Blended supply APY of the current allocation, before the vault fee
+    pub apy: f64,
+    pub allocation: Vec<MarketAllocation>,
+    pub pending_actions: Vec<PendingAction>,
+}
+
+impl VaultView {
+    // Replaces the state of `market` in the allocation, keeping the supply of the vault
+    pub(crate) fn update_market(&mut self, market: &MarketView) {
+        let Some(allocation) = self
+            .allocation
+            .iter_mut()
+            .find(|allocation| allocation.id == market.info.id)
+        else {
+            return;
+        };
+        allocation.curve = market_curve(&market.info, market.rate_at_target);
+        let current: Vec<f64> = self
+            .allocation
+            .iter()
+            .map(|market| market.current_assets)
+            .collect();
+        self.apy = blended_apy(
+            f64::from(self.info.total_assets),
+            &self.allocation,
+            &current,
+        );
+    }
+}
+
+fn market_curve(info: &MarketInfo, rate_at_target: f64) -> MarketCurve {
+    MarketCurve {
+        total_supply_assets: info.market.totalSupplyAssets as f64,
+        total_borrow_assets: info.market.totalBorrowAssets as f64,
+        rate_at_target,
+        fee: wad_to_f64(U256::from(info.market.fee)),
+    }
+}
+
+pub async fn fetch_market_view(rpc_url: Url, id: B256) -> Result<MarketView> {
+    let info = fetch_market_info(rpc_url.clone(), id, BlockId::latest()).await?;
+
+    // Idle markets have no IRM and earn nothing
+    let rate_at_target = if info.params.irm == Address::ZERO {
+        0.0
+    } else {
+        let irm = IIRM::new(info.params.irm, ProviderBuilder::new().on_http(rpc_url));
+        signed_wad_to_f64(irm.rateAtTarget(id).call().await?._0)
+    };
+    let curve = market_curve(&info, rate_at_target);
+
+    Ok(MarketView {
+        utilization: curve.utilization_with(0.0),
+        supply_apy: curve.supply_apy_with(0.0),
+        borrow_apy: curve.borrow_apy_with(0.0),
+        rate_at_target,
+        info,
+    })
+}
+
+// Position with its assets and health factor, computed with the market state of `market`
+pub async fn fetch_position_view(
+    rpc_url: Url,
+    market: &MarketView,
+    user: Address,
+) -> Result<PositionView> {
+    let position = fetch_position(rpc_url, market.info.id, user, BlockId::latest()).await?;
+    let state = &market.info.market;
+    let borrow_shares = U256::from(position.borrow_shares);
+
+    Ok(PositionView {
+        supply_assets: to_assets_down(
+            position.supply_shares,
+            U256::from(state.totalSupplyAssets),
+            U256::from(state.totalSupplyShares),
+        ),
+        borrow_assets: to_assets_up(
+            borrow_shares,
+            U256::from(state.totalBorrowAssets),
+            U256::from(state.totalBorrowShares),
+        ),
+        health_factor: health_factor(
+            U256::from(position.collateral),
+            borrow_shares,
+            U256::from(state.totalBorrowAssets),
+            U256::from(state.totalBorrowShares),
+            market.info.price,
+            market.info.params.lltv,
+        ),
+        position,
+    })
+}
+
+// Vault with its allocation and pending actions. The governance history is brought up to date first,
+// the pending caps and removals are found from it.
+pub async fn fetch_vault_view(
+    rpc_url: Url,
+    address: Address,
+    history: &mut GovernanceHistory,
+) -> Result<VaultView> {
+    let info = fetch_vault_info(rpc_url.clone(), address, BlockId::latest()).await?;
+    let (total_assets, allocation) = retrieve_vault_allocations(rpc_url.clone(), address).await?;
+    let current: Vec<f64> = allocation
+        .iter()
+        .map(|market| market.current_assets)
+        .collect();
+
+    update_governance_history(rpc_url.clone(), address, history).await?;
+    let pending_actions = retrieve_pending_actions(rpc_url, address, &history.events).await?;
+
+    Ok(VaultView {
+        info,
+        apy: blended_apy(total_assets, &allocation, &current),
+        allocation,
+        pending_actions,
+    })
+}
@@ -0,0 +1,170 @@
+// Sends Morpho transactions built by the crate to an anvil fork of Ethereum. Needs anvil and an archive RPC:
+// MORPHO_FORK_URL=<rpc> cargo test --test anvil -- --ignored
+use alloy::{
+    network::TransactionBuilder,
+    primitives::{address, Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+    transports::http::reqwest::Url,
+};
+use morpho_rust_snippets::{
+    market::{IMorpho, IERC20, MORPHO_ADDRESS},
+    revert::{MorphoRevert, ProtocolError},
+    transactions::{build_transaction, preflight, Amount, MorphoAction},
+    Error,
+};
+use std::{
+    env,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+const PORT: u16 = 18545;
+// First account of the anvil mnemonic, unlocked on the node
+const USER: Address = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+// Kills the node when the test ends, even on failure
+struct Anvil(Child);
+
+impl Drop for Anvil {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+async fn start_anvil() -> (Anvil, Url) {
+    let fork_url = env::var("MORPHO_FORK_URL").expect("MORPHO_FORK_URL must be set");
+    let child = Command::new("anvil")
+        .args(["--fork-url", &fork_url, "--port", &PORT.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("anvil must be installed");
+    let anvil = Anvil(child);
+    let rpc_url: Url = format!("http://127.0.0.1:{}", PORT).parse().unwrap();
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    for _ in 0..60 {
+        if provider.get_block_number().await.is_ok() {
+            return (anvil, rpc_url);
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    panic!("anvil did not start");
+}
+
+async fn send(rpc_url: &Url, tx: TransactionRequest) {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let receipt = provider
+        .send_transaction(tx)
+        .await
+        .unwrap()
+        .get_receipt()
+        .await
+        .unwrap();
+    assert!(receipt.status());
+}
+
+// Morpho holds the supplied USDC, impersonating it is the simplest way to fund the user
+async fn fund_user(rpc_url: &Url, assets: U256) {
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    provider
+        .raw_request::<_, ()>("anvil_impersonateAccount".into(), (MORPHO_ADDRESS,))
+        .await
+        .unwrap();
+    provider
+        .raw_request::<_, ()>(
+            "anvil_setBalance".into(),
+            (MORPHO_ADDRESS, U256::from(10).pow(U256::from(18))),
+        )
+        .await
+        .unwrap();
+    let transfer = IERC20::transferCall {
+        to: USER,
+        value: assets,
+    };
+    send(
+        rpc_url,
+        TransactionRequest::default()
+            .with_from(MORPHO_ADDRESS)
+            .with_to(USDC)
+            .with_input(transfer.abi_encode()),
+    )
+    .await;
+    let approve = IERC20::approveCall {
+        spender: MORPHO_ADDRESS,
+        value: assets,
+    };
+    send(
+        rpc_url,
+        TransactionRequest::default()
+            .with_from(USER)
+            .with_to(USDC)
+            .with_input(approve.abi_encode()),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn supplies_and_decodes_reverts_on_a_fork() {
+    let (_anvil, rpc_url) = start_anvil().await;
+    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
+    let morpho = IMorpho::new(MORPHO_ADDRESS, provider);
+
+    // USD0++/USDC (86%) with AdaptiveCurve
+    let market_id: B256 = "0xb48bb53f0f2690c71e8813f2dc7ed6fca9ac4b0ace3faa37b4a8e5ece38fa1a2"
+        .parse()
+        .unwrap();
+    let params = morpho.idToMarketParams(market_id).call().await.unwrap();
+    let market_params = IMorpho::MarketParams {
+        loanToken: params.loanToken,
+        collateralToken: params.collateralToken,
+        oracle: params.oracle,
+        irm: params.irm,
+        lltv: params.lltv,
+    };
+    let assets = U256::from(1_000_000_000); // 1,000 USDC
+    fund_user(&rpc_url, assets).await;
+
+    // The supply goes through, with the shares of the simulation up to the interest of one block
+    let supply = MorphoAction::Supply {
+        amount: Amount::Assets(assets),
+        on_behalf: USER,
+    };
+    let tx = build_transaction(USER, &market_params, &supply);
+    let (_, simulated_shares) = preflight(rpc_url.clone(), &tx, &supply, market_id, 50)
+        .await
+        .unwrap()
+        .amounts
+        .unwrap();
+    send(&rpc_url, tx).await;
+    let position = morpho.position(market_id, USER).call().await.unwrap();
+    supply
+        .check_preflight_slippage(
+            (assets, simulated_shares),
+            (assets, position.supplyShares),
+            1,
+        )
+        .unwrap();
+
+    // Borrowing without collateral is rejected with the decoded Morpho reason
+    let borrow = MorphoAction::Borrow {
+        amount: Amount::Assets(U256::from(1_000_000)),
+        on_behalf: USER,
+        receiver: USER,
+    };
+    let tx = build_transaction(USER, &market_params, &borrow);
+    let result = preflight(rpc_url.clone(), &tx, &borrow, market_id, 50).await;
+    assert!(matches!(
+        result,
+        Err(Error::Revert(ProtocolError::Morpho(
+            MorphoRevert::InsufficientCollateral
+        )))
+    ));
+
+    // Slippages above 100% are rejected before any call
+    let result = preflight(rpc_url, &tx, &borrow, market_id, 10_001).await;
+    assert!(matches!(result, Err(Error::Config(_))));
+}